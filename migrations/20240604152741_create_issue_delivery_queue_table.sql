@@ -0,0 +1,10 @@
+-- Create Issue Delivery Queue Table
+
+-- One row per (issue, recipient). A row is deleted by the background worker once the
+-- email has been handed over to the email provider.
+CREATE TABLE issue_delivery_queue (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,10 @@
+-- Create Users Table
+
+-- `password_hash` stores an Argon2id hash in PHC string format, which embeds the algorithm,
+-- its parameters and the salt alongside the hash itself.
+CREATE TABLE users (
+    user_id uuid NOT NULL,
+    PRIMARY KEY (user_id),
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL
+);
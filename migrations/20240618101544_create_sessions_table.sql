@@ -0,0 +1,10 @@
+-- Create Sessions Table
+
+-- Server-side session state, the session cookie only carries `session_key`.
+-- `session_state` is the JSON-serialized key/value map managed by `actix-session`.
+CREATE TABLE sessions (
+    session_key TEXT NOT NULL,
+    PRIMARY KEY (session_key),
+    session_state TEXT NOT NULL,
+    expires_at timestamptz NOT NULL
+);
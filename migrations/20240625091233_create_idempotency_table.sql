@@ -0,0 +1,20 @@
+-- Create Idempotency Table
+
+CREATE TYPE header_pair AS (
+    name TEXT,
+    value BYTEA
+);
+
+-- One row per (user, key). The response columns are NULL while the first request is still
+-- being processed, and filled in (in the same transaction) once its response is ready.
+-- Anonymous endpoints (e.g. `POST /subscriptions`) use the nil uuid as `user_id`, which is
+-- why there is no foreign key to `users`.
+CREATE TABLE idempotency (
+    user_id uuid NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    response_status_code SMALLINT NULL,
+    response_headers header_pair[] NULL,
+    response_body BYTEA NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (user_id, idempotency_key)
+);
@@ -0,0 +1,3 @@
+-- Add Created At To Subscription Tokens Table
+-- Existing tokens are considered issued now, they will expire one TTL after the migration.
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,5 @@
+-- Add Status Check To Subscriptions Table
+-- Keep `status` as TEXT, but only accept the values of `SubscriptionStatus`.
+ALTER TABLE subscriptions
+    ADD CONSTRAINT subscriptions_status_check
+    CHECK (status IN ('pending_confirmation', 'confirmed', 'unsubscribed', 'bounced', 'complained'));
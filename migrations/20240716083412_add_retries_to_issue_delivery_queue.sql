@@ -0,0 +1,6 @@
+-- Add Retries To Issue Delivery Queue Table
+-- Deliveries failing for a transient reason are put back in the queue, to be picked up again
+-- once `execute_after` has passed.
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,4 @@
+-- Add Locale To Subscriptions Table
+-- The language emails are written in. Subscribers who registered before we had translations
+-- keep getting English.
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
@@ -0,0 +1,18 @@
+-- Create Email Events Table
+-- What the email provider reported about the emails we sent, as received by `/webhooks/email`.
+CREATE TABLE email_events(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    provider TEXT NOT NULL,
+    kind TEXT NOT NULL
+        CHECK (kind IN ('delivery', 'soft_bounce', 'hard_bounce', 'complaint')),
+    recipient TEXT NOT NULL,
+    -- Unknown recipients are kept, e.g. a bounce for an address that was since deleted.
+    subscriber_id uuid NULL REFERENCES subscriptions (id) ON DELETE SET NULL,
+    provider_message_id TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL,
+    -- The event as sent by the provider, in JSON.
+    payload TEXT NOT NULL
+);
+CREATE INDEX email_events_subscriber_id_idx ON email_events (subscriber_id);
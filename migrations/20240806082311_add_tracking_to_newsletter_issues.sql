@@ -0,0 +1,17 @@
+-- Add Tracking To Newsletter Issues
+-- Opens and clicks of the issues, recorded through the links of `TrackingLinks`.
+ALTER TABLE newsletter_issues ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE newsletter_tracking_events(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('open', 'click')),
+    -- The link that was followed, for clicks.
+    url TEXT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX newsletter_tracking_events_issue_idx
+    ON newsletter_tracking_events (newsletter_issue_id, kind);
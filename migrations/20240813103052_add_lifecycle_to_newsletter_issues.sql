@@ -0,0 +1,30 @@
+-- Add Lifecycle To Newsletter Issues
+-- Issues go through the statuses of `IssueStatus`. Until now they were all published right
+-- away, they are `sent` unless some of their deliveries are still queued.
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NULL;
+UPDATE newsletter_issues i
+    SET status = CASE
+        WHEN EXISTS (
+            SELECT 1 FROM issue_delivery_queue q
+            WHERE q.newsletter_issue_id = i.newsletter_issue_id
+        ) THEN 'sending'
+        ELSE 'sent'
+    END;
+ALTER TABLE newsletter_issues ALTER COLUMN status SET NOT NULL;
+ALTER TABLE newsletter_issues
+    ADD CONSTRAINT newsletter_issues_status_check
+    CHECK (status IN ('draft', 'scheduled', 'sending', 'sent', 'cancelled'));
+
+-- Set for scheduled issues, when the scheduler is to start sending them.
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+-- Drafts have not been published yet.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+
+-- Where sent issues are found in the archive, `/archive/{slug}`.
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL;
+UPDATE newsletter_issues SET slug = newsletter_issue_id::text;
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+
+CREATE INDEX newsletter_issues_scheduled_idx
+    ON newsletter_issues (scheduled_for) WHERE status = 'scheduled';
@@ -0,0 +1,46 @@
+-- Create Lists
+-- Several newsletters are run from the same deployment, each one is a list.
+CREATE TABLE lists(
+    list_id uuid NOT NULL,
+    PRIMARY KEY (list_id),
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    -- Where subscriptions and issues go when they do not name a list.
+    is_default BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE UNIQUE INDEX lists_is_default_idx ON lists (is_default) WHERE is_default;
+INSERT INTO lists (list_id, slug, name, is_default)
+VALUES ('5d1c1a1e-6b0c-4d8e-9a51-2f7a3c0e4b11', 'newsletter', 'Newsletter', TRUE);
+
+-- Who is on which list. `status` goes through the same statuses as `subscriptions.status`,
+-- for that list only: somebody may be confirmed on a list and pending on another one.
+-- Everybody subscribed so far was subscribed to the one list there was.
+CREATE TABLE list_memberships(
+    list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (list_id, subscriber_id),
+    status TEXT NOT NULL
+        CHECK (status IN ('pending_confirmation', 'confirmed', 'unsubscribed', 'bounced', 'complained')),
+    subscribed_at timestamptz NOT NULL DEFAULT now()
+);
+INSERT INTO list_memberships (list_id, subscriber_id, status, subscribed_at)
+SELECT '5d1c1a1e-6b0c-4d8e-9a51-2f7a3c0e4b11', id, status, subscribed_at
+FROM subscriptions;
+
+-- A confirmation link confirms the subscription to one list.
+ALTER TABLE subscription_tokens
+    ADD COLUMN list_id uuid NULL REFERENCES lists (list_id) ON DELETE CASCADE;
+UPDATE subscription_tokens SET list_id = '5d1c1a1e-6b0c-4d8e-9a51-2f7a3c0e4b11';
+ALTER TABLE subscription_tokens ALTER COLUMN list_id SET NOT NULL;
+
+-- The lists an issue is sent to.
+CREATE TABLE newsletter_issue_lists(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    PRIMARY KEY (newsletter_issue_id, list_id)
+);
+INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+SELECT newsletter_issue_id, '5d1c1a1e-6b0c-4d8e-9a51-2f7a3c0e4b11'
+FROM newsletter_issues;
@@ -0,0 +1,12 @@
+-- Create Subscriber Tags
+-- Free-form labels put on subscribers by editors, e.g. `beta`, to target issues at them.
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, tag),
+    tagged_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+-- Restricts the recipients of an issue beyond its lists, see `Segment` for the format.
+ALTER TABLE newsletter_issues ADD COLUMN segment JSONB NULL;
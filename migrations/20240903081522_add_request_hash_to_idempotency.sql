@@ -0,0 +1,4 @@
+-- Add Request Hash To Idempotency Table
+-- Anonymous clients share the nil user id, so a key alone does not tell their requests apart.
+-- Those endpoints store a hash of the request with the key, and a retry has to match it.
+ALTER TABLE idempotency ADD COLUMN request_hash BYTEA NULL;
@@ -0,0 +1,13 @@
+-- Create Transactional Email Queue
+-- Emails sent to a subscriber because of something they did, e.g. the confirmation email.
+-- They are sent by a background worker rather than while handling the request: a slow or
+-- failing email provider can neither hold up the request nor tell the caller anything.
+CREATE TABLE transactional_email_queue(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    kind TEXT NOT NULL CHECK (kind IN ('confirmation')),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,5 @@
+-- Add Welcome And Unsubscribe Confirmation Emails
+-- Sent once a subscription to a list is confirmed, and once a list is left.
+ALTER TABLE transactional_email_queue DROP CONSTRAINT transactional_email_queue_kind_check;
+ALTER TABLE transactional_email_queue ADD CONSTRAINT transactional_email_queue_kind_check
+    CHECK (kind IN ('confirmation', 'welcome', 'unsubscribe_confirmation'));
@@ -0,0 +1,8 @@
+-- Add Provider Event Id To Email Events
+-- Providers deliver webhooks at least once: the same event can come in again, it is only
+-- stored once.
+ALTER TABLE email_events ADD COLUMN provider_event_id TEXT NULL;
+UPDATE email_events SET provider_event_id = id::text;
+ALTER TABLE email_events ALTER COLUMN provider_event_id SET NOT NULL;
+ALTER TABLE email_events
+    ADD CONSTRAINT email_events_provider_event_id_key UNIQUE (provider, provider_event_id);
@@ -0,0 +1,7 @@
+mod basic;
+mod password;
+mod session;
+
+pub use basic::basic_authentication;
+pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use session::UserId;
@@ -0,0 +1,49 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, error::InternalError, FromRequest, HttpRequest};
+use uuid::Uuid;
+
+use crate::{
+    session_state::TypedSession,
+    util::{e500, see_other},
+};
+
+// The id of the logged-in user, taken from the session.
+// Extracting it from an anonymous request redirects the caller to the login form, adding it
+// to a handler's signature is enough to put the route behind the login.
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::ops::Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for UserId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let session = match TypedSession::from_request(req, payload).into_inner() {
+            Ok(session) => session,
+            Err(e) => return ready(Err(e)),
+        };
+        ready(match session.get_user_id() {
+            Ok(Some(user_id)) => Ok(UserId(user_id)),
+            Ok(None) => {
+                let e = anyhow::anyhow!("The user has not logged in");
+                Err(InternalError::from_response(e, see_other("/login")).into())
+            }
+            Err(e) => Err(e500(e)),
+        })
+    }
+}
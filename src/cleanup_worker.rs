@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::{ExpirySettings, Settings},
+    startup::get_connection_pool,
+};
+
+#[derive(Debug)]
+pub struct CleanupOutcome {
+    pub deleted_subscription_tokens: u64,
+    pub deleted_idempotency_records: u64,
+}
+
+pub async fn run_cleanup_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    cleanup_loop(connection_pool, configuration.expiry).await
+}
+
+async fn cleanup_loop(pool: PgPool, expiry: ExpirySettings) -> Result<(), anyhow::Error> {
+    loop {
+        // Failures are logged by `delete_expired_records`, we'll catch up on the next run.
+        let _ = delete_expired_records(&pool, &expiry).await;
+        tokio::time::sleep(expiry.cleanup_interval()).await;
+    }
+}
+
+#[tracing::instrument(skip_all, err)]
+pub async fn delete_expired_records(
+    pool: &PgPool,
+    expiry: &ExpirySettings,
+) -> Result<CleanupOutcome, anyhow::Error> {
+    let deleted_subscription_tokens =
+        delete_expired_subscription_tokens(pool, expiry.subscription_token_retention()).await?;
+    let deleted_idempotency_records =
+        delete_expired_idempotency_records(pool, expiry.idempotency_ttl()).await?;
+    let outcome = CleanupOutcome {
+        deleted_subscription_tokens,
+        deleted_idempotency_records,
+    };
+    tracing::info!(?outcome, "Deleted expired records");
+    Ok(outcome)
+}
+
+// Tokens outlive their TTL by a grace period, see `ExpirySettings`.
+#[tracing::instrument(skip(pool))]
+async fn delete_expired_subscription_tokens(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE created_at < $1",
+        Utc::now() - retention
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// Requests still being processed hold a lock on their row, the `DELETE` waits for them
+// instead of pulling the key out from under their feet.
+#[tracing::instrument(skip(pool))]
+async fn delete_expired_idempotency_records(
+    pool: &PgPool,
+    ttl: Duration,
+) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM idempotency WHERE created_at < $1",
+        Utc::now() - ttl
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
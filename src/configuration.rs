@@ -5,24 +5,237 @@ use sqlx::{
     ConnectOptions,
 };
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::{SubscriberEmail, TrackingLinks, UnsubscribeLinks},
+    email_client::{
+        AttachmentPolicy, EmailClient, OutboxTransport, PostmarkTransport, RateLimiter,
+        RetryPolicy, SendGridTransport, SesTransport, SmtpTls, SmtpTransport,
+    },
+    email_templates::EmailTemplates,
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: AppSettings,
     pub email_client: EmailClientSettings,
+    pub expiry: ExpirySettings,
+    pub email_templates: EmailTemplateSettings,
+}
+
+// How the email provider authenticates the events it posts to `/webhooks/email`: `Basic` auth
+// with `username` and `secret` (e.g. in the webhook URL), or `secret` alone in the
+// `X-Webhook-Secret` header.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub secret: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct EmailTemplateSettings {
+    // Relative to the working directory, see `EmailTemplate` for the files it must contain.
+    pub directory: String,
+    // The translation catalogs, one `<locale>.json` per locale, `en.json` being mandatory.
+    pub locales_directory: String,
+}
+
+impl EmailTemplateSettings {
+    pub fn load(&self) -> Result<EmailTemplates, anyhow::Error> {
+        EmailTemplates::load(&self.directory, &self.locales_directory)
+    }
+}
+
+// How long short-lived records are kept around, and how often the cleanup task looks for
+// the expired ones.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct ExpirySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_ttl_seconds: u64,
+    // Expired tokens are only deleted once this is over as well, until then following the
+    // link says it expired rather than that we have never heard of it.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_grace_period_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idempotency_ttl_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cleanup_interval_seconds: u64,
+}
+
+impl ExpirySettings {
+    pub fn subscription_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.subscription_token_ttl_seconds)
+    }
+    pub fn subscription_token_retention(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(
+            self.subscription_token_ttl_seconds + self.subscription_token_grace_period_seconds,
+        )
+    }
+    pub fn idempotency_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idempotency_ttl_seconds)
+    }
+    pub fn cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.cleanup_interval_seconds)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
-    pub base_url: String,
     pub sender_email: String,
-    pub auth_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub retry: EmailRetrySettings,
+    pub rate_limit: EmailRateLimitSettings,
+    pub attachments: EmailAttachmentSettings,
+    // Flattened so that the provider specific settings sit next to the common ones, e.g.
+    // `provider: postmark` with its `base_url` and `auth_token`.
+    #[serde(flatten)]
+    pub transport: EmailTransportSettings,
+}
+
+// See `RetryPolicy`, `max_attempts` includes the first one.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct EmailRetrySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub jitter_milliseconds: u64,
+}
+
+impl EmailRetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts.max(1),
+            base_delay: std::time::Duration::from_millis(self.base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_milliseconds),
+            jitter: std::time::Duration::from_millis(self.jitter_milliseconds),
+        }
+    }
+}
+
+// See `RateLimiter`.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct EmailRateLimitSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub messages_per_second: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_concurrent_requests: u32,
+}
+
+impl EmailRateLimitSettings {
+    pub fn limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.messages_per_second, self.max_concurrent_requests)
+    }
+}
+
+// See `AttachmentPolicy`.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct EmailAttachmentSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attachment_bytes: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_total_bytes: usize,
+    pub allowed_content_types: Vec<String>,
+}
+
+impl EmailAttachmentSettings {
+    pub fn policy(&self) -> AttachmentPolicy {
+        AttachmentPolicy {
+            max_attachment_bytes: self.max_attachment_bytes,
+            max_total_bytes: self.max_total_bytes,
+            allowed_content_types: self.allowed_content_types.clone(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum EmailTransportSettings {
+    Postmark {
+        base_url: String,
+        auth_token: Secret<String>,
+    },
+    Sendgrid {
+        base_url: String,
+        api_key: Secret<String>,
+    },
+    Ses {
+        base_url: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: Secret<String>,
+    },
+    Smtp {
+        host: String,
+        #[serde(deserialize_with = "deserialize_number_from_string")]
+        port: u16,
+        tls: SmtpTls,
+        username: Option<String>,
+        password: Option<Secret<String>>,
+    },
+    // Nothing leaves the machine, see `OutboxTransport`.
+    Outbox {
+        directory: Option<String>,
+    },
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Failed to parse sender email");
+        let timeout = self.timeout();
+        let retry_policy = self.retry.policy();
+        let rate_limiter = self.rate_limit.limiter();
+        let attachment_policy = self.attachments.policy();
+        let email_client = match self.transport {
+            EmailTransportSettings::Postmark {
+                base_url,
+                auth_token,
+            } => EmailClient::new(
+                sender_email,
+                PostmarkTransport::new(base_url, auth_token, timeout),
+            ),
+            EmailTransportSettings::Sendgrid { base_url, api_key } => EmailClient::new(
+                sender_email,
+                SendGridTransport::new(base_url, api_key, timeout),
+            ),
+            EmailTransportSettings::Ses {
+                base_url,
+                region,
+                access_key_id,
+                secret_access_key,
+            } => EmailClient::new(
+                sender_email,
+                SesTransport::new(base_url, region, access_key_id, secret_access_key, timeout),
+            ),
+            EmailTransportSettings::Smtp {
+                host,
+                port,
+                tls,
+                username,
+                password,
+            } => {
+                let credentials = username.zip(password);
+                let transport = SmtpTransport::new(&host, port, tls, credentials, timeout)
+                    .expect("Failed to build the SMTP transport");
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportSettings::Outbox { directory } => {
+                let transport = match directory {
+                    Some(directory) => OutboxTransport::in_directory(directory),
+                    None => OutboxTransport::in_memory(),
+                };
+                EmailClient::new(sender_email, transport)
+            }
+        };
+        email_client
+            .with_retry_policy(retry_policy)
+            .with_rate_limiter(rate_limiter)
+            .with_attachment_policy(attachment_policy)
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -37,6 +250,36 @@ pub struct AppSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    // Used to sign cookies (session and flash messages), must be at least 64 bytes long.
+    pub hmac_secret: Secret<String>,
+    pub session_store: SessionStoreKind,
+    pub webhooks: WebhookSettings,
+    // Open and click tracking of newsletter issues, see `TrackingLinks`. Issues can opt out
+    // on their own.
+    pub email_tracking: bool,
+}
+
+impl AppSettings {
+    pub fn unsubscribe_links(&self) -> UnsubscribeLinks {
+        UnsubscribeLinks::new(self.base_url.clone(), self.hmac_secret.clone())
+    }
+    pub fn tracking_links(&self) -> TrackingLinks {
+        TrackingLinks::new(
+            self.base_url.clone(),
+            self.hmac_secret.clone(),
+            self.email_tracking,
+        )
+    }
+}
+
+// Where session state is kept on the server side.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStoreKind {
+    Postgres,
+    // Sessions are lost on restart and are not shared between instances, only meant for
+    // tests and local development.
+    InMemory,
 }
 
 pub enum Environment {
@@ -143,5 +386,39 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .build()?;
 
     // Try to convert the configuration file into a `Settings` struct instance
-    settings.try_deserialize::<Settings>()
+    let settings = settings.try_deserialize::<Settings>()?;
+    if let Environment::Production = environment {
+        let base = config::Config::builder()
+            .add_source(config::File::from(
+                configuration_directory.join("base.yaml"),
+            ))
+            .build()?;
+        reject_base_secrets(&settings, &base)?;
+    }
+    Ok(settings)
+}
+
+// The secrets of `base.yaml` are in the repository, anyone could forge unsubscribe links or
+// post webhook events with them. Production must set its own through the environment.
+fn reject_base_secrets(
+    settings: &Settings,
+    base: &config::Config,
+) -> Result<(), config::ConfigError> {
+    let secrets = [
+        ("application.hmac_secret", &settings.application.hmac_secret),
+        (
+            "application.webhooks.secret",
+            &settings.application.webhooks.secret,
+        ),
+    ];
+    for (key, secret) in secrets {
+        if base.get_string(key).ok().as_deref() == Some(secret.expose_secret().as_str()) {
+            return Err(config::ConfigError::Message(format!(
+                "`{}` is the placeholder of base.yaml, set it with `APP_{}`",
+                key,
+                key.to_uppercase().replace('.', "__")
+            )));
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,63 @@
+use uuid::Uuid;
+
+// Where an issue is found in the archive: its title, lowercased with anything but ASCII
+// letters and digits turned into dashes, followed by the start of its id so that two issues
+// with the same title get different slugs, e.g. `march-news-3f2a9c1b`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssueSlug(String);
+
+impl IssueSlug {
+    const MAX_TITLE_LENGTH: usize = 60;
+
+    pub fn new(title: &str, issue_id: Uuid) -> Self {
+        let mut slug = String::new();
+        for c in title.chars() {
+            if slug.len() >= Self::MAX_TITLE_LENGTH {
+                break;
+            }
+            if c.is_ascii_alphanumeric() {
+                slug.push(c.to_ascii_lowercase());
+            } else if !slug.is_empty() && !slug.ends_with('-') {
+                slug.push('-');
+            }
+        }
+        if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        slug.push_str(&issue_id.simple().to_string()[..8]);
+        Self(slug)
+    }
+}
+
+impl AsRef<str> for IssueSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueSlug;
+    use uuid::Uuid;
+
+    fn id() -> Uuid {
+        Uuid::parse_str("3f2a9c1b-0000-4000-8000-000000000000").unwrap()
+    }
+
+    #[test]
+    fn the_title_is_turned_into_dashed_words() {
+        let slug = IssueSlug::new("  March news: what's new?! ", id());
+        assert_eq!(slug.as_ref(), "march-news-what-s-new-3f2a9c1b");
+    }
+
+    #[test]
+    fn titles_without_ascii_letters_leave_only_the_id() {
+        assert_eq!(IssueSlug::new("Новости", id()).as_ref(), "3f2a9c1b");
+    }
+
+    #[test]
+    fn long_titles_are_cut_short() {
+        let slug = IssueSlug::new(&"a".repeat(200), id());
+        assert_eq!(slug.as_ref().len(), 60 + "-3f2a9c1b".len());
+    }
+}
@@ -0,0 +1,123 @@
+// Lifecycle of a newsletter issue:
+// - draft -> sending -> sent, published right away;
+// - draft -> scheduled -> sending -> sent, published at a later time;
+// - scheduled -> draft, to edit it again, or scheduled -> scheduled at another time;
+// - draft / scheduled -> cancelled, never to be published.
+//
+// Only drafts can be edited. Once an issue is `sending` there is no going back: its delivery
+// tasks are queued, it becomes `sent` when the last one is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueStatus {
+    Draft,
+    Scheduled,
+    Sending,
+    Sent,
+    Cancelled,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("A newsletter issue cannot go from `{from}` to `{to}`")]
+pub struct IllegalIssueTransition {
+    pub from: IssueStatus,
+    pub to: IssueStatus,
+}
+
+impl IssueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IssueStatus::Draft => "draft",
+            IssueStatus::Scheduled => "scheduled",
+            IssueStatus::Sending => "sending",
+            IssueStatus::Sent => "sent",
+            IssueStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn can_transition_to(self, next: IssueStatus) -> bool {
+        use IssueStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Scheduled | Sending | Cancelled)
+                | (Scheduled, Draft | Scheduled | Sending | Cancelled)
+                | (Sending, Sent)
+        )
+    }
+
+    pub fn transition_to(self, next: IssueStatus) -> Result<IssueStatus, IllegalIssueTransition> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(IllegalIssueTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+impl TryFrom<String> for IssueStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "draft" => Ok(Self::Draft),
+            "scheduled" => Ok(Self::Scheduled),
+            "sending" => Ok(Self::Sending),
+            "sent" => Ok(Self::Sent),
+            "cancelled" => Ok(Self::Cancelled),
+            other => Err(format!("{} is not a valid newsletter issue status", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for IssueStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueStatus::{self, *};
+    use claims::{assert_err, assert_ok_eq};
+
+    const ALL: [IssueStatus; 5] = [Draft, Scheduled, Sending, Sent, Cancelled];
+
+    #[test]
+    fn statuses_round_trip_through_their_string_representation() {
+        for status in ALL {
+            assert_ok_eq!(IssueStatus::try_from(status.as_str().to_string()), status);
+        }
+        assert_err!(IssueStatus::try_from("published".to_string()));
+    }
+
+    #[test]
+    fn drafts_and_scheduled_issues_can_be_sent_or_cancelled() {
+        for from in [Draft, Scheduled] {
+            assert_ok_eq!(from.transition_to(Sending), Sending);
+            assert_ok_eq!(from.transition_to(Cancelled), Cancelled);
+        }
+        assert_ok_eq!(Scheduled.transition_to(Scheduled), Scheduled);
+        assert_ok_eq!(Scheduled.transition_to(Draft), Draft);
+    }
+
+    #[test]
+    fn issues_cannot_be_sent_twice() {
+        assert_err!(Sending.transition_to(Sending));
+        assert_err!(Sent.transition_to(Sending));
+        assert_ok_eq!(Sending.transition_to(Sent), Sent);
+    }
+
+    #[test]
+    fn issues_being_sent_or_gone_are_final() {
+        for from in [Sending, Sent, Cancelled] {
+            for to in [Draft, Scheduled, Cancelled] {
+                assert_err!(from.transition_to(to));
+            }
+        }
+        for to in ALL {
+            assert_err!(Sent.transition_to(to));
+            assert_err!(Cancelled.transition_to(to));
+        }
+    }
+}
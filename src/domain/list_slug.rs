@@ -0,0 +1,49 @@
+// Names a list in URLs and forms, e.g. `weekly-digest`: lowercase ASCII letters, digits and
+// dashes, neither starting nor ending with a dash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    const MAX_LENGTH: usize = 64;
+
+    pub fn parse(value: String) -> Result<ListSlug, String> {
+        let is_valid = !value.is_empty()
+            && value.len() <= Self::MAX_LENGTH
+            && value
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && !value.starts_with('-')
+            && !value.ends_with('-');
+        if !is_valid {
+            return Err(format!("{} is not a valid list slug", value));
+        }
+        Ok(Self(value))
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ListSlug;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn dashed_lowercase_words_are_valid() {
+        for slug in ["newsletter", "weekly-digest", "rust-2024"] {
+            assert_ok!(ListSlug::parse(slug.into()));
+        }
+    }
+
+    #[test]
+    fn anything_else_is_rejected() {
+        for slug in ["", "Weekly", "weekly digest", "-weekly", "weekly-", "café"] {
+            assert_err!(ListSlug::parse(slug.into()));
+        }
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+}
@@ -1,7 +1,25 @@
+mod issue_slug;
+mod issue_status;
+mod list_slug;
 mod new_subscriber;
+mod segment;
 mod subscriber_email;
+mod subscriber_locale;
 mod subscriber_name;
+mod subscriber_tag;
+mod subscription_status;
+mod tracking_links;
+mod unsubscribe_links;
 
+pub use issue_slug::IssueSlug;
+pub use issue_status::{IllegalIssueTransition, IssueStatus};
+pub use list_slug::ListSlug;
 pub use new_subscriber::NewSubscriber;
+pub use segment::Segment;
 pub use subscriber_email::SubscriberEmail;
+pub use subscriber_locale::SubscriberLocale;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
+pub use subscription_status::{IllegalStatusTransition, SubscriptionStatus};
+pub use tracking_links::{TrackedDelivery, TrackingLinks};
+pub use unsubscribe_links::{UnsubscribeLinks, UnsubscribeTarget};
@@ -1,6 +1,9 @@
-use super::{subscriber_name::SubscriberName, SubscriberEmail};
+use super::{subscriber_name::SubscriberName, ListSlug, SubscriberEmail, SubscriberLocale};
 
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub locale: SubscriberLocale,
+    // The default list when `None`.
+    pub list: Option<ListSlug>,
 }
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use super::{SubscriberLocale, SubscriberTag};
+
+// A subset of the subscribers, written as a JSON filter. Each condition is an object with a
+// single key:
+//
+//   {"tag": "beta"}
+//   {"subscribed_after": "2024-01-01T00:00:00Z"}
+//   {"locale": "fr"}                  a bare language matches its regions too, e.g. `fr-CA`
+//   {"opened_last_issue": true}       `false` for those who did not open it
+//   {"all": [<condition>, ...]}
+//   {"any": [<condition>, ...]}
+//   {"not": <condition>}
+//
+// It is compiled to SQL by `segments::push_segment_filter`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    All(Vec<Segment>),
+    Any(Vec<Segment>),
+    Not(Box<Segment>),
+    Tag(SubscriberTag),
+    SubscribedAfter(DateTime<Utc>),
+    Locale(SubscriberLocale),
+    OpenedLastIssue(bool),
+}
+
+impl Segment {
+    // Keeps the generated SQL (and our stack) at a reasonable size.
+    const MAX_DEPTH: usize = 8;
+
+    pub fn parse(value: &Value) -> Result<Segment, String> {
+        Self::parse_nested(value, 1)
+    }
+
+    fn parse_nested(value: &Value, depth: usize) -> Result<Segment, String> {
+        if depth > Self::MAX_DEPTH {
+            return Err(format!(
+                "Segments cannot be nested more than {} levels deep",
+                Self::MAX_DEPTH
+            ));
+        }
+        let (key, argument) = match value.as_object() {
+            Some(object) if object.len() == 1 => object.iter().next().unwrap(),
+            _ => {
+                return Err(format!(
+                    "{} is not a segment condition, conditions are objects with a single key",
+                    value
+                ))
+            }
+        };
+        let invalid = |expected: &str| format!("`{}` expects {}, got {}", key, expected, argument);
+        let segment = match key.as_str() {
+            "all" | "any" => {
+                let conditions = argument
+                    .as_array()
+                    .filter(|conditions| !conditions.is_empty())
+                    .ok_or_else(|| invalid("a non-empty array of conditions"))?
+                    .iter()
+                    .map(|condition| Self::parse_nested(condition, depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if key == "all" {
+                    Segment::All(conditions)
+                } else {
+                    Segment::Any(conditions)
+                }
+            }
+            "not" => Segment::Not(Box::new(Self::parse_nested(argument, depth + 1)?)),
+            "tag" => {
+                let tag = argument.as_str().ok_or_else(|| invalid("a tag"))?;
+                Segment::Tag(SubscriberTag::parse(tag.to_owned())?)
+            }
+            "subscribed_after" => {
+                let date = argument
+                    .as_str()
+                    .ok_or_else(|| invalid("an RFC 3339 date"))?;
+                let date = DateTime::parse_from_rfc3339(date)
+                    .map_err(|e| format!("{} is not a valid RFC 3339 date: {}", date, e))?;
+                Segment::SubscribedAfter(date.with_timezone(&Utc))
+            }
+            "locale" => {
+                let locale = argument.as_str().ok_or_else(|| invalid("a locale"))?;
+                Segment::Locale(SubscriberLocale::parse(locale.to_owned())?)
+            }
+            "opened_last_issue" => {
+                Segment::OpenedLastIssue(argument.as_bool().ok_or_else(|| invalid("a boolean"))?)
+            }
+            _ => return Err(format!("{} is not a segment condition", key)),
+        };
+        Ok(segment)
+    }
+
+    // The normalized form of the filter, e.g. tags lowercased and dates in UTC.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Segment::All(conditions) => {
+                json!({ "all": conditions.iter().map(Segment::to_json).collect::<Vec<_>>() })
+            }
+            Segment::Any(conditions) => {
+                json!({ "any": conditions.iter().map(Segment::to_json).collect::<Vec<_>>() })
+            }
+            Segment::Not(condition) => json!({ "not": condition.to_json() }),
+            Segment::Tag(tag) => json!({ "tag": tag.as_ref() }),
+            Segment::SubscribedAfter(date) => json!({ "subscribed_after": date.to_rfc3339() }),
+            Segment::Locale(locale) => json!({ "locale": locale.as_ref() }),
+            Segment::OpenedLastIssue(opened) => json!({ "opened_last_issue": opened }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Segment;
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+
+    #[test]
+    fn nested_conditions_are_parsed() {
+        let filter = json!({
+            "all": [
+                { "tag": "Beta" },
+                { "any": [{ "locale": "fr" }, { "locale": "pt_br" }] },
+                { "not": { "opened_last_issue": true } },
+                { "subscribed_after": "2024-01-01T02:00:00+02:00" },
+            ]
+        });
+        let segment = Segment::parse(&filter).unwrap();
+        assert_eq!(
+            segment.to_json(),
+            json!({
+                "all": [
+                    { "tag": "beta" },
+                    { "any": [{ "locale": "fr" }, { "locale": "pt-BR" }] },
+                    { "not": { "opened_last_issue": true } },
+                    { "subscribed_after": "2024-01-01T00:00:00+00:00" },
+                ]
+            })
+        );
+        assert_eq!(Segment::parse(&segment.to_json()).unwrap(), segment);
+    }
+
+    #[test]
+    fn malformed_conditions_are_rejected() {
+        for filter in [
+            json!("beta"),
+            json!({}),
+            json!({ "tag": "beta", "locale": "fr" }),
+            json!({ "label": "beta" }),
+            json!({ "tag": 42 }),
+            json!({ "tag": "beta tester" }),
+            json!({ "locale": "french" }),
+            json!({ "subscribed_after": "yesterday" }),
+            json!({ "opened_last_issue": "yes" }),
+            json!({ "all": [] }),
+            json!({ "any": { "tag": "beta" } }),
+            json!({ "not": [{ "tag": "beta" }] }),
+        ] {
+            assert_err!(Segment::parse(&filter));
+        }
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let mut filter = json!({ "tag": "beta" });
+        for _ in 0..7 {
+            filter = json!({ "not": filter });
+        }
+        assert_ok!(Segment::parse(&filter));
+        assert_err!(Segment::parse(&json!({ "not": filter })));
+    }
+}
@@ -0,0 +1,120 @@
+// The language a subscriber wants to hear from us in, as a (simplified) BCP 47 language tag:
+// a language, optionally followed by a region, e.g. `en`, `pt-BR` or `es-419`.
+// Stored as requested, even if we have no translation for it (yet): see `EmailTemplates` for
+// how we fall back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberLocale(String);
+
+impl SubscriberLocale {
+    pub fn parse(value: String) -> Result<SubscriberLocale, String> {
+        let invalid = || format!("{} is not a valid locale", value);
+        let mut parts = value.split(['-', '_']);
+        let language = parts.next().unwrap_or_default();
+        let region = parts.next();
+        if parts.next().is_some()
+            || !(2..=3).contains(&language.len())
+            || !language.chars().all(|c| c.is_ascii_alphabetic())
+        {
+            return Err(invalid());
+        }
+        let locale = match region {
+            None => language.to_ascii_lowercase(),
+            Some(region)
+                if (region.len() == 2 && region.chars().all(|c| c.is_ascii_alphabetic()))
+                    || (region.len() == 3 && region.chars().all(|c| c.is_ascii_digit())) =>
+            {
+                format!(
+                    "{}-{}",
+                    language.to_ascii_lowercase(),
+                    region.to_ascii_uppercase()
+                )
+            }
+            Some(_) => return Err(invalid()),
+        };
+        Ok(Self(locale))
+    }
+
+    // The preferred locale of an `Accept-Language` header, if we can make sense of it.
+    // Entries are tried by decreasing weight (`q`), the ones we can't parse are skipped.
+    pub fn from_accept_language(header: &str) -> Option<SubscriberLocale> {
+        let mut candidates: Vec<(f32, &str)> = header
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';');
+                let tag = parts.next()?.trim();
+                let weight = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse().ok())?;
+                (tag != "*" && weight > 0.0).then_some((weight, tag))
+            })
+            .collect();
+        // A stable sort keeps the order of the header between equal weights.
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates
+            .into_iter()
+            .find_map(|(_, tag)| SubscriberLocale::parse(tag.to_owned()).ok())
+    }
+}
+
+impl Default for SubscriberLocale {
+    fn default() -> Self {
+        Self("en".into())
+    }
+}
+
+impl AsRef<str> for SubscriberLocale {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberLocale;
+    use claims::{assert_err, assert_none, assert_ok};
+
+    fn parse(value: &str) -> String {
+        SubscriberLocale::parse(value.into())
+            .unwrap()
+            .as_ref()
+            .to_owned()
+    }
+
+    #[test]
+    fn languages_and_regions_are_normalized() {
+        assert_eq!(parse("EN"), "en");
+        assert_eq!(parse("pt_br"), "pt-BR");
+        assert_eq!(parse("es-419"), "es-419");
+        assert_eq!(parse("fil"), "fil");
+    }
+
+    #[test]
+    fn malformed_locales_are_rejected() {
+        for locale in [
+            "", "e", "english", "en-", "en-USA", "en-U1", "e1", "en-US-x",
+        ] {
+            assert_err!(SubscriberLocale::parse(locale.into()));
+        }
+    }
+
+    #[test]
+    fn a_valid_locale_is_parsed_successfully() {
+        assert_ok!(SubscriberLocale::parse("fr-CA".into()));
+    }
+
+    #[test]
+    fn the_heaviest_parsable_entry_of_accept_language_wins() {
+        let locale =
+            SubscriberLocale::from_accept_language("en;q=0.5, *, fr-CH, de;q=0.9").unwrap();
+        assert_eq!(locale.as_ref(), "fr-CH");
+        let locale = SubscriberLocale::from_accept_language("en-US,en;q=0.9").unwrap();
+        assert_eq!(locale.as_ref(), "en-US");
+    }
+
+    #[test]
+    fn an_unusable_accept_language_is_ignored() {
+        assert_none!(SubscriberLocale::from_accept_language("*"));
+        assert_none!(SubscriberLocale::from_accept_language("fr;q=0, klingon"));
+        assert_none!(SubscriberLocale::from_accept_language(""));
+    }
+}
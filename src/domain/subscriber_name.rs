@@ -74,4 +74,24 @@ mod tests {
         let name = "Pedro Almen".to_string();
         assert_ok!(SubscriberName::parse(name));
     }
+
+    #[test]
+    fn names_in_non_latin_scripts_are_valid() {
+        for name in [
+            "Дмитрий Шостакович",
+            "李小龍",
+            "محمد الخوارزمي",
+            "Zoë Ðorđević",
+            "अनुष्का",
+        ] {
+            assert_ok!(SubscriberName::parse(name.to_string()));
+        }
+    }
+
+    #[test]
+    fn combining_characters_count_as_a_single_grapheme() {
+        // Each "नि" is two code points but a single grapheme.
+        let name = "नि".repeat(256);
+        assert_ok!(SubscriberName::parse(name));
+    }
 }
@@ -0,0 +1,53 @@
+// A label editors put on subscribers to target issues at them, e.g. `beta`: lowercase ASCII
+// letters, digits and dashes, neither starting nor ending with a dash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    const MAX_LENGTH: usize = 64;
+
+    // Tags are not case sensitive, `Beta` is stored as `beta`.
+    pub fn parse(value: String) -> Result<SubscriberTag, String> {
+        let tag = value.trim().to_ascii_lowercase();
+        let is_valid = !tag.is_empty()
+            && tag.len() <= Self::MAX_LENGTH
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && !tag.starts_with('-')
+            && !tag.ends_with('-');
+        if !is_valid {
+            return Err(format!("{} is not a valid tag", value));
+        }
+        Ok(Self(tag))
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberTag;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn tags_are_lowercased() {
+        assert_eq!(
+            SubscriberTag::parse(" Beta ".into()).unwrap().as_ref(),
+            "beta"
+        );
+        assert_ok!(SubscriberTag::parse("early-adopter-2024".into()));
+    }
+
+    #[test]
+    fn anything_else_is_rejected() {
+        for tag in ["", " ", "beta tester", "-beta", "beta-", "bêta", "beta!"] {
+            assert_err!(SubscriberTag::parse(tag.into()));
+        }
+        assert_err!(SubscriberTag::parse("a".repeat(65)));
+    }
+}
@@ -0,0 +1,156 @@
+// Lifecycle of a subscription:
+// - pending_confirmation -> confirmed -> unsubscribed, the happy path;
+// - pending_confirmation -> unsubscribed, leaving from the confirmation email;
+// - unsubscribed -> pending_confirmation, subscribing again (and confirming again);
+// - pending_confirmation / confirmed -> bounced / complained, reported by the email provider.
+//
+// `bounced` and `complained` are final: we must not email those addresses again. A complaint
+// can still come in after somebody unsubscribed.
+// Moving to the current status is always allowed, e.g. clicking a confirmation link twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("A subscription cannot go from `{from}` to `{to}`")]
+pub struct IllegalStatusTransition {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+            SubscriptionStatus::Complained => "complained",
+        }
+    }
+
+    pub fn can_transition_to(self, next: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+        self == next
+            || matches!(
+                (self, next),
+                (
+                    PendingConfirmation,
+                    Confirmed | Unsubscribed | Bounced | Complained
+                ) | (Confirmed, Unsubscribed | Bounced | Complained)
+                    | (Unsubscribed, PendingConfirmation | Complained)
+            )
+    }
+
+    pub fn transition_to(
+        self,
+        next: SubscriptionStatus,
+    ) -> Result<SubscriptionStatus, IllegalStatusTransition> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(IllegalStatusTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+impl TryFrom<String> for SubscriptionStatus {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "pending_confirmation" => Ok(Self::PendingConfirmation),
+            "confirmed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "bounced" => Ok(Self::Bounced),
+            "complained" => Ok(Self::Complained),
+            other => Err(format!("{} is not a valid subscription status", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus::{self, *};
+    use claims::{assert_err, assert_ok_eq};
+
+    const ALL: [SubscriptionStatus; 5] = [
+        PendingConfirmation,
+        Confirmed,
+        Unsubscribed,
+        Bounced,
+        Complained,
+    ];
+
+    #[test]
+    fn the_happy_path_is_allowed() {
+        assert_ok_eq!(PendingConfirmation.transition_to(Confirmed), Confirmed);
+        assert_ok_eq!(Confirmed.transition_to(Unsubscribed), Unsubscribed);
+    }
+
+    #[test]
+    fn staying_in_the_same_status_is_allowed() {
+        for status in ALL {
+            assert_ok_eq!(status.transition_to(status), status);
+        }
+    }
+
+    #[test]
+    fn an_unsubscribed_user_cannot_be_confirmed() {
+        assert_err!(Unsubscribed.transition_to(Confirmed));
+    }
+
+    #[test]
+    fn only_unsubscribed_users_can_go_back_to_pending_confirmation() {
+        assert_ok_eq!(
+            Unsubscribed.transition_to(PendingConfirmation),
+            PendingConfirmation
+        );
+        for status in [Confirmed, Bounced, Complained] {
+            assert_err!(status.transition_to(PendingConfirmation));
+        }
+    }
+
+    #[test]
+    fn bounced_and_complained_are_final() {
+        for from in [Bounced, Complained] {
+            for to in ALL.into_iter().filter(|s| *s != from) {
+                assert_err!(from.transition_to(to));
+            }
+        }
+    }
+
+    #[test]
+    fn the_provider_can_report_a_bounce_or_complaint_for_an_active_subscription() {
+        for from in [PendingConfirmation, Confirmed] {
+            assert_ok_eq!(from.transition_to(Bounced), Bounced);
+            assert_ok_eq!(from.transition_to(Complained), Complained);
+        }
+        assert_ok_eq!(Unsubscribed.transition_to(Complained), Complained);
+    }
+
+    #[test]
+    fn statuses_round_trip_through_their_string_representation() {
+        for status in ALL {
+            assert_ok_eq!(
+                SubscriptionStatus::try_from(status.as_str().to_string()),
+                status
+            );
+        }
+        assert_err!(SubscriptionStatus::try_from("active".to_string()));
+    }
+}
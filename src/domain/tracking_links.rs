@@ -0,0 +1,345 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Who a tracking link was issued for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackedDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    // Links of previews and test copies lead to the same place, but record nothing.
+    pub preview: bool,
+}
+
+// Builds and verifies the open and click tracking links of newsletter issues.
+//
+// Tokens are signed like unsubscribe tokens, see `UnsubscribeLinks`:
+// - opens: `<issue id>.<subscriber id>.<hex HMAC>`;
+// - clicks: `<issue id>.<subscriber id>.<base64url target URL>.<hex HMAC>`, the target being
+//   signed too, so the redirect only ever leads where the issue pointed to.
+// Preview tokens are prefixed with `preview.` and signed apart, like preview unsubscribe
+// tokens.
+#[derive(Clone)]
+pub struct TrackingLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+    // Turned off for privacy, links are left as they are and no pixel is added. Links sent
+    // while it was on keep redirecting, without recording anything.
+    enabled: bool,
+}
+
+impl TrackingLinks {
+    const OPEN_DOMAIN: &'static [u8] = b"tracking-open:";
+    const CLICK_DOMAIN: &'static [u8] = b"tracking-click:";
+    const PREVIEW_OPEN_DOMAIN: &'static [u8] = b"preview-tracking-open:";
+    const PREVIEW_CLICK_DOMAIN: &'static [u8] = b"preview-tracking-click:";
+    const PREVIEW_PREFIX: &'static str = "preview.";
+
+    pub fn new(base_url: String, hmac_secret: Secret<String>, enabled: bool) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+            enabled,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn open_link(&self, delivery: TrackedDelivery) -> String {
+        let signature = self.mac(open_domain(delivery), delivery, b"").finalize();
+        format!(
+            "{}/t/o/{}{}.{}.{}",
+            self.base_url,
+            prefix(delivery),
+            delivery.newsletter_issue_id,
+            delivery.subscriber_id,
+            hex::encode(signature.into_bytes())
+        )
+    }
+
+    pub fn click_link(&self, delivery: TrackedDelivery, url: &str) -> String {
+        let signature = self
+            .mac(click_domain(delivery), delivery, url.as_bytes())
+            .finalize();
+        format!(
+            "{}/t/c/{}{}.{}.{}.{}",
+            self.base_url,
+            prefix(delivery),
+            delivery.newsletter_issue_id,
+            delivery.subscriber_id,
+            URL_SAFE_NO_PAD.encode(url),
+            hex::encode(signature.into_bytes())
+        )
+    }
+
+    pub fn verify_open(&self, token: &str) -> Result<TrackedDelivery, anyhow::Error> {
+        let (preview, [issue_id, subscriber_id, signature]) = split_token(token)?;
+        let delivery = TrackedDelivery {
+            newsletter_issue_id: Uuid::parse_str(issue_id)?,
+            subscriber_id: Uuid::parse_str(subscriber_id)?,
+            preview,
+        };
+        // `verify_slice` compares in constant time.
+        self.mac(open_domain(delivery), delivery, b"")
+            .verify_slice(&hex::decode(signature)?)?;
+        Ok(delivery)
+    }
+
+    // Returns the delivery and the URL to redirect to.
+    pub fn verify_click(&self, token: &str) -> Result<(TrackedDelivery, String), anyhow::Error> {
+        let (preview, [issue_id, subscriber_id, url, signature]) = split_token(token)?;
+        let delivery = TrackedDelivery {
+            newsletter_issue_id: Uuid::parse_str(issue_id)?,
+            subscriber_id: Uuid::parse_str(subscriber_id)?,
+            preview,
+        };
+        let url = String::from_utf8(URL_SAFE_NO_PAD.decode(url)?)?;
+        self.mac(click_domain(delivery), delivery, url.as_bytes())
+            .verify_slice(&hex::decode(signature)?)?;
+        Ok((delivery, url))
+    }
+
+    // Point every `http(s)` link of `html` at its click tracking link. Other links (`mailto:`,
+    // anchors, ...) are left alone.
+    pub fn track_links(&self, html: &str, delivery: TrackedDelivery) -> String {
+        let mut tracked = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some((before, url, after)) = next_href(rest) {
+            tracked.push_str(before);
+            // The attribute value is HTML-escaped, e.g. `&amp;` between query parameters.
+            let target = htmlescape::decode_html(url).unwrap_or_else(|_| url.to_owned());
+            if is_web_url(&target) {
+                tracked.push_str(&self.click_link(delivery, &target));
+            } else {
+                tracked.push_str(url);
+            }
+            rest = after;
+        }
+        tracked.push_str(rest);
+        tracked
+    }
+
+    fn mac(&self, domain: &[u8], delivery: TrackedDelivery, url: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(domain);
+        mac.update(delivery.newsletter_issue_id.as_bytes());
+        mac.update(delivery.subscriber_id.as_bytes());
+        mac.update(url);
+        mac
+    }
+}
+
+fn prefix(delivery: TrackedDelivery) -> &'static str {
+    if delivery.preview {
+        TrackingLinks::PREVIEW_PREFIX
+    } else {
+        ""
+    }
+}
+
+fn open_domain(delivery: TrackedDelivery) -> &'static [u8] {
+    if delivery.preview {
+        TrackingLinks::PREVIEW_OPEN_DOMAIN
+    } else {
+        TrackingLinks::OPEN_DOMAIN
+    }
+}
+
+fn click_domain(delivery: TrackedDelivery) -> &'static [u8] {
+    if delivery.preview {
+        TrackingLinks::PREVIEW_CLICK_DOMAIN
+    } else {
+        TrackingLinks::CLICK_DOMAIN
+    }
+}
+
+// Whether the token is a preview one, and its parts.
+fn split_token<const N: usize>(token: &str) -> Result<(bool, [&str; N]), anyhow::Error> {
+    let (preview, token) = match token.strip_prefix(TrackingLinks::PREVIEW_PREFIX) {
+        Some(token) => (true, token),
+        None => (false, token),
+    };
+    let parts: Vec<&str> = token.split('.').collect();
+    let parts = parts
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("The tracking token is malformed"))?;
+    Ok((preview, parts))
+}
+
+fn is_web_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+// Finds the next quoted `href` attribute, returns what comes before its value, the value, and
+// what comes after it.
+fn next_href(html: &str) -> Option<(&str, &str, &str)> {
+    // ASCII lowercasing keeps byte offsets as they are.
+    let lowercase = html.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(position) = lowercase[from..].find("href") {
+        let start = from + position;
+        from = start + "href".len();
+        // An attribute of its own, not the end of `data-href` or some text.
+        if !html[..start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let value = html[from..].trim_start();
+        let Some(value) = value.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        let value_start = html.len() - value.len() + 1;
+        let value_end = value_start + html[value_start..].find(quote)?;
+        return Some((
+            &html[..value_start],
+            &html[value_start..value_end],
+            &html[value_end..],
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrackedDelivery, TrackingLinks};
+    use claims::{assert_err, assert_ok, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn links(secret: &str) -> TrackingLinks {
+        TrackingLinks::new("http://127.0.0.1".into(), Secret::new(secret.into()), true)
+    }
+
+    fn delivery() -> TrackedDelivery {
+        TrackedDelivery {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            preview: false,
+        }
+    }
+
+    fn token(link: &str) -> &str {
+        link.rsplit('/').next().unwrap()
+    }
+
+    #[test]
+    fn tokens_are_valid_for_what_they_were_issued_for() {
+        let links = links("secret");
+        let delivery = delivery();
+        let open = links.open_link(delivery);
+        assert_ok_eq!(links.verify_open(token(&open)), delivery);
+        let click = links.click_link(delivery, "https://example.com/?a=1&b=2");
+        assert_ok_eq!(
+            links.verify_click(token(&click)),
+            (delivery, "https://example.com/?a=1&b=2".to_string())
+        );
+    }
+
+    #[test]
+    fn preview_tokens_are_valid_and_known_as_such() {
+        let links = links("secret");
+        let delivery = TrackedDelivery {
+            preview: true,
+            ..delivery()
+        };
+        let open = links.open_link(delivery);
+        assert_ok_eq!(links.verify_open(token(&open)), delivery);
+        let click = links.click_link(delivery, "https://example.com");
+        assert_ok_eq!(
+            links.verify_click(token(&click)),
+            (delivery, "https://example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn tokens_cannot_be_turned_into_preview_tokens_or_back() {
+        let links = links("secret");
+        let delivery = delivery();
+        let open = links.open_link(delivery);
+        assert_err!(links.verify_open(&format!("preview.{}", token(&open))));
+        let click = links.click_link(delivery, "https://example.com");
+        assert_err!(links.verify_click(&format!("preview.{}", token(&click))));
+        let preview = TrackedDelivery {
+            preview: true,
+            ..delivery
+        };
+        let open = links.open_link(preview);
+        assert_err!(links.verify_open(token(&open).strip_prefix("preview.").unwrap()));
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let delivery = delivery();
+        let open = links("another-secret").open_link(delivery);
+        assert_err!(links("secret").verify_open(token(&open)));
+        let click = links("another-secret").click_link(delivery, "https://example.com");
+        assert_err!(links("secret").verify_click(token(&click)));
+    }
+
+    #[test]
+    fn the_target_of_a_click_token_cannot_be_changed() {
+        let links = links("secret");
+        let click = links.click_link(delivery(), "https://example.com");
+        let mut parts: Vec<String> = token(&click).split('.').map(String::from).collect();
+        parts[2] = base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            "https://evil.example.com",
+        );
+        assert_err!(links.verify_click(&parts.join(".")));
+    }
+
+    #[test]
+    fn open_and_click_tokens_are_not_interchangeable() {
+        let links = links("secret");
+        let delivery = delivery();
+        let open = links.open_link(delivery);
+        let (ids, signature) = token(&open).rsplit_once('.').unwrap();
+        assert_err!(links.verify_click(&format!("{}..{}", ids, signature)));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let links = links("secret");
+        assert_err!(links.verify_open(""));
+        assert_err!(links.verify_open("not-a-token"));
+        assert_err!(links.verify_click(&format!("{}.{}.!!.00", Uuid::new_v4(), Uuid::new_v4())));
+    }
+
+    #[test]
+    fn web_links_are_rewritten_and_others_left_alone() {
+        let links = links("secret");
+        let delivery = delivery();
+        let html = r##"<p><a href="https://example.com/?a=1&amp;b=2">Read</a>
+            <a class="x" HREF = 'http://example.com'>More</a>
+            <a href="mailto:editor@example.com">Write</a> <a href="#top">Top</a>
+            <span data-href="https://example.com">href="https://example.com"</span></p>"##;
+
+        let tracked = links.track_links(html, delivery);
+
+        let click = links.click_link(delivery, "https://example.com/?a=1&b=2");
+        assert!(tracked.contains(&format!(r#"<a href="{}">Read</a>"#, click)));
+        let click = links.click_link(delivery, "http://example.com");
+        assert!(tracked.contains(&format!(r#"<a class="x" HREF = '{}'>More</a>"#, click)));
+        assert!(tracked.contains(r#"href="mailto:editor@example.com""#));
+        assert!(tracked.contains(r##"href="#top""##));
+        assert!(tracked.contains(r#"<span data-href="https://example.com">"#));
+        assert_ok!(links.verify_click(token(&click)));
+    }
+
+    #[test]
+    fn html_without_links_is_left_as_is() {
+        let html = "<p>No links, but an href mention and an unclosed <a href=\"https://</p>";
+        assert_eq!(links("secret").track_links(html, delivery()), html);
+    }
+}
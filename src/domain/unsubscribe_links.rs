@@ -0,0 +1,216 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Who an unsubscribe link was issued for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsubscribeTarget {
+    pub subscriber_id: Uuid,
+    pub list_id: Uuid,
+    // Links of previews and test copies look like the real ones, but unsubscribe nobody.
+    pub preview: bool,
+}
+
+// Builds and verifies the unsubscribe links added to every email, each one for a subscriber
+// and the list the email was sent for.
+//
+// The token is `<subscriber id>.<list id>.<hex HMAC-SHA256 of both ids>`: it never expires and
+// needs no storage, but it can't be forged for somebody else, or another list, without the
+// application's HMAC secret. Preview tokens are prefixed with `preview.` and signed apart, a
+// real token can't be turned into one or the other way around.
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+impl UnsubscribeLinks {
+    // Prefix the signed payload so these signatures can't be confused with anything else
+    // signed with the same secret.
+    const DOMAIN: &'static [u8] = b"unsubscribe:";
+    const PREVIEW_DOMAIN: &'static [u8] = b"preview-unsubscribe:";
+    const PREVIEW_PREFIX: &'static str = "preview.";
+
+    pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn link_for(&self, subscriber_id: Uuid, list_id: Uuid) -> String {
+        self.link(UnsubscribeTarget {
+            subscriber_id,
+            list_id,
+            preview: false,
+        })
+    }
+
+    // The link of a preview or test copy, for the same subscriber and list.
+    pub fn preview_link_for(&self, subscriber_id: Uuid, list_id: Uuid) -> String {
+        self.link(UnsubscribeTarget {
+            subscriber_id,
+            list_id,
+            preview: true,
+        })
+    }
+
+    pub fn token_for(&self, subscriber_id: Uuid, list_id: Uuid) -> String {
+        self.token(UnsubscribeTarget {
+            subscriber_id,
+            list_id,
+            preview: false,
+        })
+    }
+
+    pub fn verify(&self, token: &str) -> Result<UnsubscribeTarget, anyhow::Error> {
+        let (preview, token) = match token.strip_prefix(Self::PREVIEW_PREFIX) {
+            Some(token) => (true, token),
+            None => (false, token),
+        };
+        let mut parts = token.splitn(3, '.');
+        let (Some(subscriber_id), Some(list_id), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("The unsubscribe token is malformed");
+        };
+        let target = UnsubscribeTarget {
+            subscriber_id: Uuid::parse_str(subscriber_id)?,
+            list_id: Uuid::parse_str(list_id)?,
+            preview,
+        };
+        let signature = hex::decode(signature)?;
+        // `verify_slice` compares in constant time.
+        self.mac(target).verify_slice(&signature)?;
+        Ok(target)
+    }
+
+    fn link(&self, target: UnsubscribeTarget) -> String {
+        format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            self.base_url,
+            self.token(target)
+        )
+    }
+
+    fn token(&self, target: UnsubscribeTarget) -> String {
+        let signature = self.mac(target).finalize().into_bytes();
+        format!(
+            "{}{}.{}.{}",
+            if target.preview {
+                Self::PREVIEW_PREFIX
+            } else {
+                ""
+            },
+            target.subscriber_id,
+            target.list_id,
+            hex::encode(signature)
+        )
+    }
+
+    // Both ids have a fixed length, no separator is needed between them.
+    fn mac(&self, target: UnsubscribeTarget) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(if target.preview {
+            Self::PREVIEW_DOMAIN
+        } else {
+            Self::DOMAIN
+        });
+        mac.update(target.subscriber_id.as_bytes());
+        mac.update(target.list_id.as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UnsubscribeLinks, UnsubscribeTarget};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn links(secret: &str) -> UnsubscribeLinks {
+        UnsubscribeLinks::new("http://127.0.0.1".into(), Secret::new(secret.into()))
+    }
+
+    #[test]
+    fn a_token_is_valid_for_the_subscriber_and_list_it_was_issued_for() {
+        let links = links("secret");
+        let (subscriber_id, list_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = links.token_for(subscriber_id, list_id);
+        assert_ok_eq!(
+            links.verify(&token),
+            UnsubscribeTarget {
+                subscriber_id,
+                list_id,
+                preview: false,
+            }
+        );
+    }
+
+    #[test]
+    fn a_preview_token_is_valid_and_known_as_such() {
+        let links = links("secret");
+        let (subscriber_id, list_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let link = links.preview_link_for(subscriber_id, list_id);
+        let token = link.split_once("token=").unwrap().1;
+        assert_ok_eq!(
+            links.verify(token),
+            UnsubscribeTarget {
+                subscriber_id,
+                list_id,
+                preview: true,
+            }
+        );
+    }
+
+    #[test]
+    fn a_token_cannot_be_turned_into_a_preview_token_or_back() {
+        let links = links("secret");
+        let (subscriber_id, list_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = links.token_for(subscriber_id, list_id);
+        assert_err!(links.verify(&format!("preview.{}", token)));
+        let link = links.preview_link_for(subscriber_id, list_id);
+        let preview_token = link.split_once("token=").unwrap().1;
+        assert_err!(links.verify(preview_token.strip_prefix("preview.").unwrap()));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = links("another-secret").token_for(Uuid::new_v4(), Uuid::new_v4());
+        assert_err!(links("secret").verify(&token));
+    }
+
+    #[test]
+    fn a_token_cannot_be_reused_for_another_subscriber() {
+        let links = links("secret");
+        let list_id = Uuid::new_v4();
+        let token = links.token_for(Uuid::new_v4(), list_id);
+        let signature = token.rsplit('.').next().unwrap();
+        let forged = format!("{}.{}.{}", Uuid::new_v4(), list_id, signature);
+        assert_err!(links.verify(&forged));
+    }
+
+    #[test]
+    fn a_token_cannot_be_reused_for_another_list() {
+        let links = links("secret");
+        let subscriber_id = Uuid::new_v4();
+        let token = links.token_for(subscriber_id, Uuid::new_v4());
+        let signature = token.rsplit('.').next().unwrap();
+        let forged = format!("{}.{}.{}", subscriber_id, Uuid::new_v4(), signature);
+        assert_err!(links.verify(&forged));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let links = links("secret");
+        assert_err!(links.verify(""));
+        assert_err!(links.verify("not-a-token"));
+        assert_err!(links.verify(&format!("{}.not-hex", Uuid::new_v4())));
+        assert_err!(links.verify(&format!("{}.{}.not-hex", Uuid::new_v4(), Uuid::new_v4())));
+    }
+}
@@ -0,0 +1,239 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+// A file sent along with an email. With a `content_id` it is an inline attachment, displayed
+// in the HTML body where it is referenced as `cid:<content_id>` (e.g. `<img src="cid:logo">`),
+// rather than offered for download.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    #[serde(serialize_with = "serialize_base64")]
+    pub content: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_id: Option<String>,
+}
+
+impl Attachment {
+    pub fn new(
+        filename: impl Into<String>,
+        content_type: impl Into<String>,
+        content: Vec<u8>,
+    ) -> Self {
+        Self {
+            filename: filename.into(),
+            content_type: content_type.into(),
+            content,
+            content_id: None,
+        }
+    }
+
+    pub fn inline(
+        content_id: impl Into<String>,
+        filename: impl Into<String>,
+        content_type: impl Into<String>,
+        content: Vec<u8>,
+    ) -> Self {
+        Self {
+            content_id: Some(content_id.into()),
+            ..Self::new(filename, content_type, content)
+        }
+    }
+
+    // How providers with a JSON API expect the content.
+    pub(super) fn base64_content(&self) -> String {
+        STANDARD.encode(&self.content)
+    }
+}
+
+fn serialize_base64<S: serde::Serializer>(
+    content: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(content))
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum AttachmentError {
+    #[error("{filename} is {size} bytes, attachments are limited to {limit} bytes")]
+    TooLarge {
+        filename: String,
+        size: usize,
+        limit: usize,
+    },
+    #[error(
+        "The attachments add up to {size} bytes, more than the {limit} bytes allowed per email"
+    )]
+    TotalTooLarge { size: usize, limit: usize },
+    #[error("{filename} is of type {content_type}, which is not allowed")]
+    ContentTypeNotAllowed {
+        filename: String,
+        content_type: String,
+    },
+    #[error("{0:?} is not a valid attachment filename")]
+    InvalidFilename(String),
+    #[error("{0:?} is not a valid content id for an inline attachment")]
+    InvalidContentId(String),
+}
+
+// What `EmailClient` accepts to attach: providers cap the size of a message (10 MB for
+// Postmark and SES, base64 encoding included) and the file types they deliver.
+#[derive(Debug, Clone)]
+pub struct AttachmentPolicy {
+    pub max_attachment_bytes: usize,
+    pub max_total_bytes: usize,
+    // Media types, without parameters, e.g. `application/pdf`.
+    pub allowed_content_types: Vec<String>,
+}
+
+impl AttachmentPolicy {
+    pub fn check(&self, attachments: &[Attachment]) -> Result<(), AttachmentError> {
+        for attachment in attachments {
+            let filename = &attachment.filename;
+            // The filename ends up in a MIME header, a line break would let it add its own.
+            if filename.trim().is_empty()
+                || filename
+                    .chars()
+                    .any(|c| c.is_control() || c == '/' || c == '\\')
+            {
+                return Err(AttachmentError::InvalidFilename(filename.clone()));
+            }
+            if let Some(content_id) = &attachment.content_id {
+                if content_id.is_empty()
+                    || !content_id
+                        .chars()
+                        .all(|c| c.is_ascii_graphic() && !matches!(c, '<' | '>' | '"'))
+                {
+                    return Err(AttachmentError::InvalidContentId(content_id.clone()));
+                }
+            }
+            let media_type = attachment
+                .content_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim();
+            if !self
+                .allowed_content_types
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(media_type))
+            {
+                return Err(AttachmentError::ContentTypeNotAllowed {
+                    filename: filename.clone(),
+                    content_type: attachment.content_type.clone(),
+                });
+            }
+            if attachment.content.len() > self.max_attachment_bytes {
+                return Err(AttachmentError::TooLarge {
+                    filename: filename.clone(),
+                    size: attachment.content.len(),
+                    limit: self.max_attachment_bytes,
+                });
+            }
+        }
+        let total = attachments.iter().map(|a| a.content.len()).sum();
+        if total > self.max_total_bytes {
+            return Err(AttachmentError::TotalTooLarge {
+                size: total,
+                limit: self.max_total_bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for AttachmentPolicy {
+    // Issue archives and the images of the issues.
+    fn default() -> Self {
+        Self {
+            max_attachment_bytes: 5_000_000,
+            max_total_bytes: 7_000_000,
+            allowed_content_types: ["application/pdf", "image/png", "image/jpeg", "image/gif"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Attachment, AttachmentError, AttachmentPolicy};
+    use claims::{assert_matches, assert_ok};
+
+    fn policy() -> AttachmentPolicy {
+        AttachmentPolicy {
+            max_attachment_bytes: 10,
+            max_total_bytes: 15,
+            allowed_content_types: vec!["application/pdf".into(), "image/png".into()],
+        }
+    }
+
+    fn pdf(size: usize) -> Attachment {
+        Attachment::new("issue.pdf", "application/pdf", vec![0; size])
+    }
+
+    #[test]
+    fn attachments_within_the_limits_are_accepted() {
+        let logo = Attachment::inline("logo", "logo.png", "image/PNG", vec![0; 5]);
+        assert_ok!(policy().check(&[pdf(10), logo]));
+        assert_ok!(policy().check(&[]));
+    }
+
+    #[test]
+    fn content_type_parameters_are_ignored() {
+        let attachment = Attachment::new("issue.pdf", "application/pdf; name=issue.pdf", vec![]);
+        assert_ok!(policy().check(&[attachment]));
+    }
+
+    #[test]
+    fn content_types_outside_the_allowlist_are_rejected() {
+        let attachment = Attachment::new("run.exe", "application/x-msdownload", vec![0; 1]);
+        assert_matches!(
+            policy().check(&[attachment]),
+            Err(AttachmentError::ContentTypeNotAllowed { .. })
+        );
+    }
+
+    #[test]
+    fn an_attachment_above_the_size_limit_is_rejected() {
+        assert_matches!(
+            policy().check(&[pdf(11)]),
+            Err(AttachmentError::TooLarge { size: 11, .. })
+        );
+    }
+
+    #[test]
+    fn attachments_above_the_total_size_limit_are_rejected() {
+        assert_matches!(
+            policy().check(&[pdf(8), pdf(8)]),
+            Err(AttachmentError::TotalTooLarge { size: 16, .. })
+        );
+    }
+
+    #[test]
+    fn filenames_that_could_break_headers_or_paths_are_rejected() {
+        for filename in [
+            "",
+            " ",
+            "a\r\nBcc: x@example.com",
+            "../issue.pdf",
+            "a\\b.pdf",
+        ] {
+            let attachment = Attachment::new(filename, "application/pdf", vec![]);
+            assert_matches!(
+                policy().check(&[attachment]),
+                Err(AttachmentError::InvalidFilename(_))
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_content_ids_are_rejected() {
+        for content_id in ["", "my logo", "<logo>"] {
+            let attachment = Attachment::inline(content_id, "logo.png", "image/png", vec![]);
+            assert_matches!(
+                policy().check(&[attachment]),
+                Err(AttachmentError::InvalidContentId(_))
+            );
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use crate::domain::SubscriberEmail;
+
+use super::{Attachment, EmailHeader, SendEmailError};
+
+// Headers `EmailClient` sets itself, or that would change who gets the email.
+const RESERVED_HEADERS: [&str; 10] = [
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "subject",
+    "content-type",
+    "content-transfer-encoding",
+    "mime-version",
+    "list-unsubscribe",
+    "list-unsubscribe-post",
+];
+
+// What `EmailClient::send` delivers. Only the recipient, subject and bodies are required, the
+// rest is opt-in:
+//
+//     EmailMessage::new(&recipient, subject, html, text)
+//         .unsubscribe_link(link)
+//         .tag("confirmation")
+//         .metadata("subscriber_id", subscriber_id.to_string())
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub(super) recipient: SubscriberEmail,
+    pub(super) subject: String,
+    pub(super) html_content: String,
+    pub(super) text_content: String,
+    pub(super) unsubscribe_link: Option<String>,
+    pub(super) message_stream: Option<String>,
+    pub(super) tag: Option<String>,
+    pub(super) metadata: BTreeMap<String, String>,
+    pub(super) headers: Vec<EmailHeader>,
+    pub(super) attachments: Vec<Attachment>,
+}
+
+impl EmailMessage {
+    pub fn new(
+        recipient: &SubscriberEmail,
+        subject: impl Into<String>,
+        html_content: impl Into<String>,
+        text_content: impl Into<String>,
+    ) -> Self {
+        Self {
+            recipient: recipient.clone(),
+            subject: subject.into(),
+            html_content: html_content.into(),
+            text_content: text_content.into(),
+            unsubscribe_link: None,
+            message_stream: None,
+            tag: None,
+            metadata: BTreeMap::new(),
+            headers: vec![],
+            attachments: vec![],
+        }
+    }
+
+    // Added to the bodies and to the `List-Unsubscribe` headers.
+    pub fn unsubscribe_link(mut self, unsubscribe_link: impl Into<String>) -> Self {
+        self.unsubscribe_link = Some(unsubscribe_link.into());
+        self
+    }
+
+    // The Postmark message stream to send through (e.g. `broadcast`), the default stream of
+    // the server otherwise. Ignored by the other providers.
+    pub fn message_stream(mut self, message_stream: impl Into<String>) -> Self {
+        self.message_stream = Some(message_stream.into());
+        self
+    }
+
+    // Groups emails in the statistics of the provider, e.g. `confirmation` or
+    // `newsletter-<issue id>`. An email has at most one.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    // Reported back by the provider along with the events of the email (bounces, opens, ...).
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push(EmailHeader {
+            name: name.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    pub fn attachment(mut self, attachment: Attachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    // The HTML body as it is sent, followed by the unsubscribe link if there is one.
+    pub fn html_body(&self) -> String {
+        match &self.unsubscribe_link {
+            Some(unsubscribe_link) => format!(
+                "{}<br><br><a href=\"{}\">Unsubscribe</a>",
+                self.html_content,
+                htmlescape::encode_minimal(unsubscribe_link)
+            ),
+            None => self.html_content.clone(),
+        }
+    }
+
+    // Custom headers must be well-formed, and must not override the ones we set.
+    pub(super) fn check_headers(&self) -> Result<(), SendEmailError> {
+        for header in &self.headers {
+            let well_formed = !header.name.is_empty()
+                && header
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_graphic() && c != ':')
+                && !header.value.chars().any(|c| c == '\r' || c == '\n');
+            let reserved = RESERVED_HEADERS
+                .iter()
+                .any(|r| r.eq_ignore_ascii_case(&header.name));
+            if !well_formed || reserved {
+                return Err(SendEmailError::InvalidHeader {
+                    name: header.name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailMessage;
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::SendEmailError;
+    use claims::{assert_matches, assert_ok};
+
+    fn message() -> EmailMessage {
+        let recipient = SubscriberEmail::parse("reader@example.com".into()).unwrap();
+        EmailMessage::new(&recipient, "Subject", "<p>Body</p>", "Body")
+    }
+
+    #[test]
+    fn custom_headers_are_accepted() {
+        let message = message()
+            .header("X-Campaign", "summer")
+            .header("Reply-To", "editor@example.com");
+        assert_ok!(message.check_headers());
+    }
+
+    #[test]
+    fn headers_we_set_ourselves_cannot_be_overridden() {
+        for name in ["To", "bcc", "List-Unsubscribe", "Content-Type"] {
+            assert_matches!(
+                message().header(name, "value").check_headers(),
+                Err(SendEmailError::InvalidHeader { .. })
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        for (name, value) in [
+            ("", "value"),
+            ("X Campaign", "value"),
+            ("X-Campaign:", "value"),
+            ("X-Campaign", "summer\r\nBcc: someone@example.com"),
+        ] {
+            assert_matches!(
+                message().header(name, value).check_headers(),
+                Err(SendEmailError::InvalidHeader { .. })
+            );
+        }
+    }
+
+    #[test]
+    fn metadata_keys_are_unique() {
+        let message = message().metadata("issue", "1").metadata("issue", "2");
+        assert_eq!(message.metadata.len(), 1);
+        assert_eq!(message.metadata["issue"], "2");
+    }
+}
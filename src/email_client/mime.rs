@@ -0,0 +1,57 @@
+use lettre::message::{
+    header::{ContentType, HeaderName, HeaderValue},
+    Attachment as MimeAttachment, MultiPart, SinglePart,
+};
+use lettre::Message;
+
+use super::{Attachment, Email};
+
+// The email as a MIME message, for the transports that deliver raw messages (SMTP, and SES
+// when there are attachments):
+// - `multipart/alternative` with the text and HTML bodies;
+// - wrapped in `multipart/related` with the inline attachments, if any;
+// - wrapped in `multipart/mixed` with the other attachments, if any.
+// The message stream, tag and metadata are for the providers' APIs, a MIME message has no
+// standard place for them and they are left out.
+pub(super) fn mime_message(email: &Email) -> Result<Message, anyhow::Error> {
+    let mut builder = Message::builder()
+        .from(email.from.parse()?)
+        .to(email.to.parse()?)
+        .subject(&email.subject);
+    for header in &email.headers {
+        builder = builder.raw_header(HeaderValue::new(
+            HeaderName::new_from_ascii(header.name.clone())?,
+            header.value.clone(),
+        ));
+    }
+    let mut body =
+        MultiPart::alternative_plain_html(email.text_body.clone(), email.html_body.clone());
+    let (inline, attached): (Vec<_>, Vec<_>) = email
+        .attachments
+        .iter()
+        .partition(|a| a.content_id.is_some());
+    if !inline.is_empty() {
+        body = inline
+            .into_iter()
+            .try_fold(MultiPart::related().multipart(body), |related, a| {
+                Ok::<_, anyhow::Error>(related.singlepart(mime_part(a)?))
+            })?;
+    }
+    if !attached.is_empty() {
+        body = attached
+            .into_iter()
+            .try_fold(MultiPart::mixed().multipart(body), |mixed, a| {
+                Ok::<_, anyhow::Error>(mixed.singlepart(mime_part(a)?))
+            })?;
+    }
+    Ok(builder.multipart(body)?)
+}
+
+fn mime_part(attachment: &Attachment) -> Result<SinglePart, anyhow::Error> {
+    let content_type = ContentType::parse(&attachment.content_type)?;
+    let part = match &attachment.content_id {
+        Some(content_id) => MimeAttachment::new_inline(content_id.clone()),
+        None => MimeAttachment::new(attachment.filename.clone()),
+    };
+    Ok(part.body(attachment.content.clone(), content_type))
+}
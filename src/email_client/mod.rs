@@ -0,0 +1,933 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use tracing::Instrument;
+
+use crate::{domain::SubscriberEmail, util::error_chain_fmt};
+
+mod attachment;
+mod message;
+mod mime;
+mod outbox;
+mod postmark;
+mod rate_limit;
+mod retry;
+mod sendgrid;
+mod ses;
+mod smtp;
+
+pub use attachment::{Attachment, AttachmentError, AttachmentPolicy};
+pub use message::EmailMessage;
+pub use outbox::OutboxTransport;
+pub use postmark::PostmarkTransport;
+pub use rate_limit::RateLimiter;
+pub use retry::RetryPolicy;
+pub use sendgrid::SendGridTransport;
+pub use ses::SesTransport;
+pub use smtp::{SmtpTls, SmtpTransport};
+
+// A fully assembled email, independent of the provider that is going to deliver it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Email {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub headers: Vec<EmailHeader>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_stream: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+// How many emails `EmailClient` sends at once when a transport has no batch endpoint, on top
+// of the limits of the `RateLimiter`.
+const MAX_CONCURRENT_SENDS: usize = 10;
+
+// How an `Email` leaves the application, one implementation per provider.
+#[async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError>;
+
+    // Transports with a batch endpoint override this and `send_batch`.
+    fn supports_batch(&self) -> bool {
+        false
+    }
+
+    // The outcome of each email, in the same order as `emails`.
+    async fn send_batch(&self, emails: &[Email]) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await);
+        }
+        outcomes
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    // We could not get an answer from the provider: connection refused, timeout, ...
+    #[error("Failed to reach the email provider")]
+    Unreachable(#[source] anyhow::Error),
+    // The recipient address is malformed or does not exist.
+    #[error("The recipient address is invalid: {message}")]
+    InvalidRecipient { message: String },
+    // The provider refuses to deliver to this recipient anymore (previous hard bounce, spam
+    // complaint, ...).
+    #[error("The recipient is marked as inactive by the email provider: {message}")]
+    InactiveRecipient { message: String },
+    #[error("Too many requests sent to the email provider")]
+    RateLimited {
+        // From the `Retry-After` header, if any.
+        retry_after: Option<std::time::Duration>,
+    },
+    // The API token is missing, invalid or not allowed to send.
+    #[error("The email provider did not accept our credentials: {message}")]
+    Unauthorized { message: String },
+    // The provider failed on its side (5xx).
+    #[error("The email provider is unavailable (status {status})")]
+    ProviderUnavailable { status: u16, body: String },
+    // Any other non-2xx answer of an HTTP API.
+    #[error("The email provider rejected the email with status {status}")]
+    Rejected { status: u16, body: String },
+    // The SMTP server answered with an error reply code.
+    #[error("The SMTP server rejected the email")]
+    SmtpRejected(#[source] anyhow::Error),
+    // Caught before anything is sent, see `AttachmentPolicy`.
+    #[error("The attachments of the email are not acceptable")]
+    InvalidAttachment(#[source] AttachmentError),
+    // Caught before anything is sent, see `EmailMessage::header`.
+    #[error("The header {name} cannot be set on an email")]
+    InvalidHeader { name: String },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl SendEmailError {
+    // Whether trying again later has a chance to succeed: the provider could not be reached,
+    // failed on its side or asked us to slow down. Any other rejection is returned right away.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SendEmailError::Unreachable(_) | SendEmailError::ProviderUnavailable { .. } => true,
+            SendEmailError::RateLimited { retry_after } => retry_after.is_some(),
+            // 4xx SMTP reply codes are transient failures, 5xx are permanent.
+            SendEmailError::SmtpRejected(e) => e
+                .downcast_ref::<lettre::transport::smtp::Error>()
+                .is_some_and(|e| e.is_transient()),
+            SendEmailError::InvalidRecipient { .. }
+            | SendEmailError::InactiveRecipient { .. }
+            | SendEmailError::Unauthorized { .. }
+            | SendEmailError::Rejected { .. }
+            | SendEmailError::InvalidAttachment(_)
+            | SendEmailError::InvalidHeader { .. }
+            | SendEmailError::UnexpectedError(_) => false,
+        }
+    }
+
+    // Whether sending to this recipient again is pointless, i.e. the address bounced.
+    pub fn is_undeliverable_recipient(&self) -> bool {
+        matches!(
+            self,
+            SendEmailError::InvalidRecipient { .. } | SendEmailError::InactiveRecipient { .. }
+        )
+    }
+
+    // The same failure, for another email of a batch that failed as a whole. The source of the
+    // errors we cannot clone is kept as a message.
+    fn replicate(&self) -> SendEmailError {
+        match self {
+            SendEmailError::Unreachable(e) => {
+                SendEmailError::Unreachable(anyhow::anyhow!("{:#}", e))
+            }
+            SendEmailError::InvalidRecipient { message } => SendEmailError::InvalidRecipient {
+                message: message.clone(),
+            },
+            SendEmailError::InactiveRecipient { message } => SendEmailError::InactiveRecipient {
+                message: message.clone(),
+            },
+            SendEmailError::RateLimited { retry_after } => SendEmailError::RateLimited {
+                retry_after: *retry_after,
+            },
+            SendEmailError::Unauthorized { message } => SendEmailError::Unauthorized {
+                message: message.clone(),
+            },
+            SendEmailError::ProviderUnavailable { status, body } => {
+                SendEmailError::ProviderUnavailable {
+                    status: *status,
+                    body: body.clone(),
+                }
+            }
+            SendEmailError::Rejected { status, body } => SendEmailError::Rejected {
+                status: *status,
+                body: body.clone(),
+            },
+            SendEmailError::SmtpRejected(e) => {
+                SendEmailError::SmtpRejected(anyhow::anyhow!("{:#}", e))
+            }
+            SendEmailError::InvalidAttachment(e) => SendEmailError::InvalidAttachment(e.clone()),
+            SendEmailError::InvalidHeader { name } => {
+                SendEmailError::InvalidHeader { name: name.clone() }
+            }
+            SendEmailError::UnexpectedError(e) => {
+                SendEmailError::UnexpectedError(anyhow::anyhow!("{:#}", e))
+            }
+        }
+    }
+
+    fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            SendEmailError::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// Errors returned by `reqwest` itself (as opposed to error statuses) mean the request did not
+// go through.
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        SendEmailError::Unreachable(e.into())
+    }
+}
+
+// Shared by the transports talking to an HTTP API.
+fn http_client(timeout: std::time::Duration) -> reqwest::Client {
+    reqwest::Client::builder().timeout(timeout).build().unwrap()
+}
+
+// Hands the response back if its status is a success.
+async fn check_http_response(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, SendEmailError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    // Only the delay in seconds form is supported, not the HTTP date one.
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .map(std::time::Duration::from_secs);
+    let body = response.text().await.unwrap_or_default();
+    Err(classify_http_error(status.as_u16(), body, retry_after))
+}
+
+// What can be told from the status code alone, transports knowing the error format of their
+// provider refine it further.
+fn classify_http_error(
+    status: u16,
+    body: String,
+    retry_after: Option<std::time::Duration>,
+) -> SendEmailError {
+    match status {
+        401 | 403 => SendEmailError::Unauthorized { message: body },
+        429 => SendEmailError::RateLimited { retry_after },
+        500.. => SendEmailError::ProviderUnavailable { status, body },
+        _ => SendEmailError::Rejected { status, body },
+    }
+}
+
+pub struct BatchRecipient {
+    pub email: SubscriberEmail,
+    pub unsubscribe_link: String,
+}
+
+// Clones share the same transport and rate limiter.
+#[derive(Clone)]
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Arc<dyn EmailTransport>,
+    retry_policy: RetryPolicy,
+    rate_limiter: Arc<RateLimiter>,
+    attachment_policy: AttachmentPolicy,
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            sender,
+            transport: Arc::new(transport),
+            retry_policy: RetryPolicy::no_retry(),
+            rate_limiter: Arc::new(RateLimiter::unlimited()),
+            attachment_policy: AttachmentPolicy::default(),
+        }
+    }
+
+    pub fn with_attachment_policy(self, attachment_policy: AttachmentPolicy) -> Self {
+        Self {
+            attachment_policy,
+            ..self
+        }
+    }
+
+    pub fn with_rate_limiter(self, rate_limiter: RateLimiter) -> Self {
+        Self {
+            rate_limiter: Arc::new(rate_limiter),
+            ..self
+        }
+    }
+
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy,
+            ..self
+        }
+    }
+
+    // Checks the message (custom headers, attachments) before anything is sent, then delivers
+    // it following the retry policy.
+    pub async fn send(&self, message: EmailMessage) -> Result<(), SendEmailError> {
+        self.check(&message)?;
+        let email = self.assemble(message);
+        self.send_with_retries(&email).await
+    }
+
+    // Shorthand for `send`, when nothing but the content and the unsubscribe link is needed.
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: &str,
+    ) -> Result<(), SendEmailError> {
+        let message = EmailMessage::new(recipient, subject, html_content, text_content)
+            .unsubscribe_link(unsubscribe_link);
+        self.send(message).await
+    }
+
+    // The same content for every recipient, see `send_all`.
+    pub async fn send_batch(
+        &self,
+        recipients: &[BatchRecipient],
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Vec<Result<(), SendEmailError>> {
+        let messages = recipients
+            .iter()
+            .map(|r| {
+                EmailMessage::new(&r.email, subject, html_content, text_content)
+                    .unsubscribe_link(&r.unsubscribe_link)
+            })
+            .collect();
+        self.send_all(messages).await
+    }
+
+    // `send` for many messages, in as few requests as the transport allows. The outcome of each
+    // message is returned in the same order as `messages`; the ones that failed for a retryable
+    // reason are retried together, following the retry policy.
+    pub async fn send_all(&self, messages: Vec<EmailMessage>) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes: Vec<Option<Result<(), SendEmailError>>> = std::iter::repeat_with(|| None)
+            .take(messages.len())
+            .collect();
+        // Invalid messages fail right away, the others are assembled and remember where their
+        // outcome goes.
+        let mut emails = vec![];
+        let mut positions = vec![];
+        for (i, message) in messages.into_iter().enumerate() {
+            match self.check(&message) {
+                Ok(()) => {
+                    emails.push(self.assemble(message));
+                    positions.push(i);
+                }
+                Err(e) => outcomes[i] = Some(Err(e)),
+            }
+        }
+        let mut pending: Vec<usize> = (0..emails.len()).collect();
+        let mut attempt = 1;
+        while !pending.is_empty() {
+            let span = tracing::info_span!(
+                "Email batch delivery attempt",
+                attempt,
+                max_attempts = self.retry_policy.max_attempts,
+                batch_size = pending.len()
+            );
+            let batch: Vec<_> = pending.iter().map(|&i| emails[i].clone()).collect();
+            let batch_outcomes = self.deliver_batch(&batch).instrument(span).await;
+            let mut to_retry = vec![];
+            let mut retry_after = None;
+            for (i, outcome) in pending.into_iter().zip(batch_outcomes) {
+                match outcome {
+                    Err(e) if e.is_retryable() => {
+                        retry_after = retry_after.max(e.retry_after());
+                        to_retry.push((i, e));
+                    }
+                    outcome => outcomes[positions[i]] = Some(outcome),
+                }
+            }
+            if to_retry.is_empty() {
+                break;
+            }
+            let Some(delay) = self.retry_policy.delay_after(attempt, retry_after) else {
+                for (i, e) in to_retry {
+                    outcomes[positions[i]] = Some(Err(e));
+                }
+                break;
+            };
+            tracing::warn!(
+                failed = to_retry.len(),
+                attempt,
+                delay_milliseconds = delay.as_millis() as u64,
+                "Failed to send part of an email batch, retrying"
+            );
+            tokio::time::sleep(delay).await;
+            pending = to_retry.into_iter().map(|(i, _)| i).collect();
+            attempt += 1;
+        }
+        outcomes
+            .into_iter()
+            .map(|o| o.expect("Every email of the batch has an outcome"))
+            .collect()
+    }
+
+    // Custom headers and attachments are checked before anything is sent.
+    fn check(&self, message: &EmailMessage) -> Result<(), SendEmailError> {
+        message.check_headers()?;
+        self.attachment_policy
+            .check(&message.attachments)
+            .map_err(SendEmailError::InvalidAttachment)
+    }
+
+    // A single attempt, within the limits of the rate limiter.
+    async fn deliver(&self, email: &Email) -> Result<(), SendEmailError> {
+        let _permit = self.rate_limiter.acquire(1).await;
+        let outcome = self.transport.send(email).await;
+        if let Err(SendEmailError::RateLimited { retry_after }) = &outcome {
+            self.rate_limiter.slow_down(*retry_after);
+        }
+        outcome
+    }
+
+    async fn deliver_batch(&self, emails: &[Email]) -> Vec<Result<(), SendEmailError>> {
+        if !self.transport.supports_batch() {
+            // Built up front: a closure in the stream would borrow `self` for any lifetime, which
+            // keeps the future from being `Send`.
+            let deliveries: Vec<_> = emails.iter().map(|email| self.deliver(email)).collect();
+            return futures::stream::iter(deliveries)
+                .buffered(MAX_CONCURRENT_SENDS)
+                .collect()
+                .await;
+        }
+        let _permit = self.rate_limiter.acquire(emails.len() as u32).await;
+        let outcomes = self.transport.send_batch(emails).await;
+        let retry_after = outcomes.iter().find_map(|o| match o {
+            Err(SendEmailError::RateLimited { retry_after }) => Some(*retry_after),
+            _ => None,
+        });
+        if let Some(retry_after) = retry_after {
+            self.rate_limiter.slow_down(retry_after);
+        }
+        outcomes
+    }
+
+    // Emails with an unsubscribe link carry it both in the body and in the `List-Unsubscribe`
+    // headers (RFC 2369) that allow mail clients to offer one-click unsubscription (RFC 8058).
+    fn assemble(&self, message: EmailMessage) -> Email {
+        let mut email = Email {
+            from: self.sender.as_ref().to_owned(),
+            to: message.recipient.as_ref().to_owned(),
+            html_body: message.html_body(),
+            subject: message.subject,
+            text_body: message.text_content,
+            headers: vec![],
+            attachments: message.attachments,
+            message_stream: message.message_stream,
+            tag: message.tag,
+            metadata: message.metadata,
+        };
+        if let Some(unsubscribe_link) = message.unsubscribe_link {
+            email.text_body = format!("{}\n\nUnsubscribe: {}", email.text_body, unsubscribe_link);
+            email.headers = vec![
+                EmailHeader {
+                    name: "List-Unsubscribe".into(),
+                    value: format!("<{}>", unsubscribe_link),
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post".into(),
+                    value: "List-Unsubscribe=One-Click".into(),
+                },
+            ];
+        }
+        email.headers.extend(message.headers);
+        email
+    }
+
+    async fn send_with_retries(&self, email: &Email) -> Result<(), SendEmailError> {
+        let mut attempt = 1;
+        loop {
+            let span = tracing::info_span!(
+                "Email delivery attempt",
+                attempt,
+                max_attempts = self.retry_policy.max_attempts
+            );
+            let error = match self.deliver(email).instrument(span).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            if !error.is_retryable() {
+                return Err(error);
+            }
+            let Some(delay) = self.retry_policy.delay_after(attempt, error.retry_after()) else {
+                return Err(error);
+            };
+            tracing::warn!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                attempt,
+                delay_milliseconds = delay.as_millis() as u64,
+                "Failed to send email, retrying"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{
+        Attachment, AttachmentError, BatchRecipient, Email, EmailClient, EmailMessage,
+        EmailTransport, OutboxTransport, RateLimiter, RetryPolicy, SendEmailError,
+    };
+    use async_trait::async_trait;
+    use claims::{assert_err, assert_matches, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake,
+    };
+
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    const UNSUBSCRIBE_LINK: &str = "https://my-newsletter.com/subscriptions/unsubscribe?token=abc";
+
+    // Answers with the given statuses in order, `200` once they run out.
+    #[derive(Clone, Default)]
+    struct ScriptedTransport {
+        statuses: Arc<Mutex<VecDeque<u16>>>,
+        attempts: Arc<Mutex<u32>>,
+    }
+
+    impl ScriptedTransport {
+        fn new(statuses: &[u16]) -> Self {
+            Self {
+                statuses: Arc::new(Mutex::new(statuses.iter().copied().collect())),
+                ..Self::default()
+            }
+        }
+
+        fn attempts(&self) -> u32 {
+            *self.attempts.lock().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl EmailTransport for ScriptedTransport {
+        async fn send(&self, _email: &Email) -> Result<(), SendEmailError> {
+            *self.attempts.lock().unwrap() += 1;
+            match self.statuses.lock().unwrap().pop_front() {
+                None | Some(200) => Ok(()),
+                Some(status) => Err(super::classify_http_error(status, String::new(), None)),
+            }
+        }
+    }
+
+    // Tests Helpers
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+    fn retrying_client(transport: ScriptedTransport) -> EmailClient {
+        EmailClient::new(email(), transport).with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: Duration::from_millis(1),
+        })
+    }
+    async fn send(email_client: &EmailClient) -> Result<(), SendEmailError> {
+        email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                UNSUBSCRIBE_LINK,
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn send_email_hands_the_email_over_to_the_transport() {
+        // Arrange
+        let outbox = OutboxTransport::in_memory();
+        let sender = email();
+        let email_client = EmailClient::new(sender.clone(), outbox.clone());
+        let recipient = email();
+        let subject = subject();
+
+        // Act
+        let outcome = email_client
+            .send_email(
+                &recipient,
+                &subject,
+                &content(),
+                &content(),
+                UNSUBSCRIBE_LINK,
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let sent = outbox.sent_emails();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].from, sender.as_ref());
+        assert_eq!(sent[0].to, recipient.as_ref());
+        assert_eq!(sent[0].subject, subject);
+    }
+
+    #[tokio::test]
+    async fn send_email_includes_the_unsubscribe_link_and_headers() {
+        // Arrange
+        let outbox = OutboxTransport::in_memory();
+        let email_client = EmailClient::new(email(), outbox.clone());
+
+        // Act
+        email_client
+            .send_email(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                UNSUBSCRIBE_LINK,
+            )
+            .await
+            .unwrap();
+
+        // Assert
+        let sent = &outbox.sent_emails()[0];
+        assert!(sent.html_body.contains(UNSUBSCRIBE_LINK));
+        assert!(sent.text_body.contains(UNSUBSCRIBE_LINK));
+        let headers: Vec<_> = sent
+            .headers
+            .iter()
+            .map(|h| (h.name.as_str(), h.value.as_str()))
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                (
+                    "List-Unsubscribe",
+                    format!("<{}>", UNSUBSCRIBE_LINK).as_str()
+                ),
+                ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_hands_the_optional_fields_over_to_the_transport() {
+        // Arrange
+        let outbox = OutboxTransport::in_memory();
+        let email_client = EmailClient::new(email(), outbox.clone());
+        let message = EmailMessage::new(&email(), subject(), content(), content())
+            .message_stream("broadcast")
+            .tag("newsletter-42")
+            .metadata("newsletter_issue_id", "42")
+            .header("X-Campaign", "summer")
+            .attachment(Attachment::new(
+                "issue.pdf",
+                "application/pdf",
+                b"%PDF".to_vec(),
+            ))
+            .attachment(Attachment::inline(
+                "logo",
+                "logo.png",
+                "image/png",
+                vec![1, 2, 3],
+            ));
+
+        // Act
+        let outcome = email_client.send(message).await;
+
+        // Assert
+        assert_ok!(outcome);
+        let sent = &outbox.sent_emails()[0];
+        assert_eq!(sent.message_stream.as_deref(), Some("broadcast"));
+        assert_eq!(sent.tag.as_deref(), Some("newsletter-42"));
+        assert_eq!(sent.metadata["newsletter_issue_id"], "42");
+        assert_eq!(sent.headers[0].name, "X-Campaign");
+        assert_eq!(sent.attachments.len(), 2);
+        assert_eq!(sent.attachments[0].filename, "issue.pdf");
+        assert_eq!(sent.attachments[1].content_id.as_deref(), Some("logo"));
+    }
+
+    #[tokio::test]
+    async fn send_leaves_out_the_unsubscribe_link_if_there_is_none() {
+        // Arrange
+        let outbox = OutboxTransport::in_memory();
+        let email_client = EmailClient::new(email(), outbox.clone());
+        let text = content();
+
+        // Act
+        email_client
+            .send(EmailMessage::new(&email(), subject(), content(), &text))
+            .await
+            .unwrap();
+
+        // Assert
+        let sent = &outbox.sent_emails()[0];
+        assert_eq!(sent.text_body, text);
+        assert!(sent.headers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_rejects_attachments_outside_the_policy_without_sending() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[]);
+        let email_client = EmailClient::new(email(), transport.clone());
+        let attachment = Attachment::new("run.exe", "application/x-msdownload", vec![0]);
+        let message =
+            EmailMessage::new(&email(), subject(), content(), content()).attachment(attachment);
+
+        // Act
+        let outcome = email_client.send(message).await;
+
+        // Assert
+        assert_matches!(
+            outcome,
+            Err(SendEmailError::InvalidAttachment(
+                AttachmentError::ContentTypeNotAllowed { .. }
+            ))
+        );
+        assert_eq!(transport.attempts(), 0);
+    }
+
+    #[tokio::test]
+    async fn send_rejects_reserved_headers_without_sending() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[]);
+        let email_client = EmailClient::new(email(), transport.clone());
+        let message = EmailMessage::new(&email(), subject(), content(), content())
+            .header("Bcc", "someone@example.com");
+
+        // Act
+        let outcome = email_client.send(message).await;
+
+        // Assert
+        assert_matches!(outcome, Err(SendEmailError::InvalidHeader { .. }));
+        assert_eq!(transport.attempts(), 0);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_server_errors_until_it_succeeds() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[503, 500]);
+        let email_client = retrying_client(transport.clone());
+
+        // Act
+        let outcome = send(&email_client).await;
+
+        // Assert
+        assert_ok!(outcome);
+        assert_eq!(transport.attempts(), 3);
+    }
+
+    #[tokio::test]
+    async fn send_email_gives_up_after_max_attempts() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[500, 500, 500, 500]);
+        let email_client = retrying_client(transport.clone());
+
+        // Act
+        let outcome = send(&email_client).await;
+
+        // Assert
+        assert_matches!(
+            outcome,
+            Err(SendEmailError::ProviderUnavailable { status: 500, .. })
+        );
+        assert_eq!(transport.attempts(), 3);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_client_errors() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[422]);
+        let email_client = retrying_client(transport.clone());
+
+        // Act
+        let outcome = send(&email_client).await;
+
+        // Assert
+        assert_err!(outcome);
+        assert_eq!(transport.attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_without_a_retry_policy() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[503]);
+        let email_client = EmailClient::new(email(), transport.clone());
+
+        // Act
+        let outcome = send(&email_client).await;
+
+        // Assert
+        assert_err!(outcome);
+        assert_eq!(transport.attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_each_recipient() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[200, 503, 422]);
+        let email_client = retrying_client(transport.clone());
+        let recipients: Vec<_> = (0..3)
+            .map(|_| BatchRecipient {
+                email: email(),
+                unsubscribe_link: UNSUBSCRIBE_LINK.into(),
+            })
+            .collect();
+
+        // Act
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 3);
+        assert_ok!(&outcomes[0]);
+        // Retried on its own, and delivered the second time around.
+        assert_ok!(&outcomes[1]);
+        assert_matches!(
+            &outcomes[2],
+            Err(SendEmailError::Rejected { status: 422, .. })
+        );
+        assert_eq!(transport.attempts(), 4);
+    }
+
+    #[tokio::test]
+    async fn send_batch_gives_each_recipient_their_own_email() {
+        // Arrange
+        let outbox = OutboxTransport::in_memory();
+        let email_client = EmailClient::new(email(), outbox.clone());
+        let recipients: Vec<_> = (0..2)
+            .map(|i| BatchRecipient {
+                email: email(),
+                unsubscribe_link: format!("{}{}", UNSUBSCRIBE_LINK, i),
+            })
+            .collect();
+
+        // Act
+        let outcomes = email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(outcomes.iter().all(|o| o.is_ok()));
+        let sent = outbox.sent_emails();
+        for (recipient, sent) in recipients.iter().zip(&sent) {
+            assert_eq!(sent.to, recipient.email.as_ref());
+            assert!(sent.text_body.contains(&recipient.unsubscribe_link));
+        }
+    }
+
+    #[tokio::test]
+    async fn send_all_fails_invalid_messages_without_sending_them() {
+        // Arrange
+        let transport = ScriptedTransport::new(&[200, 200]);
+        let email_client = EmailClient::new(email(), transport.clone());
+        let messages = vec![
+            EmailMessage::new(&email(), subject(), content(), content()),
+            EmailMessage::new(&email(), subject(), content(), content())
+                .header("Bcc", "someone@example.com"),
+            EmailMessage::new(&email(), subject(), content(), content()),
+        ];
+
+        // Act
+        let outcomes = email_client.send_all(messages).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 3);
+        assert_ok!(&outcomes[0]);
+        assert_matches!(&outcomes[1], Err(SendEmailError::InvalidHeader { .. }));
+        assert_ok!(&outcomes[2]);
+        assert_eq!(transport.attempts(), 2);
+    }
+
+    // Keeps track of how many sends are in flight at most.
+    #[derive(Clone, Default)]
+    struct SlowTransport {
+        in_flight: Arc<Mutex<(u32, u32)>>,
+    }
+
+    #[async_trait]
+    impl EmailTransport for SlowTransport {
+        async fn send(&self, _email: &Email) -> Result<(), SendEmailError> {
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.in_flight.lock().unwrap().0 -= 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn send_batch_stays_within_the_concurrency_cap() {
+        // Arrange
+        let transport = SlowTransport::default();
+        let email_client = EmailClient::new(email(), transport.clone())
+            .with_rate_limiter(RateLimiter::new(1000, 2));
+        let recipients: Vec<_> = (0..6)
+            .map(|_| BatchRecipient {
+                email: email(),
+                unsubscribe_link: UNSUBSCRIBE_LINK.into(),
+            })
+            .collect();
+
+        // Act
+        email_client
+            .send_batch(&recipients, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_eq!(transport.in_flight.lock().unwrap().1, 2);
+    }
+}
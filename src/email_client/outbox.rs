@@ -0,0 +1,104 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use super::{Email, EmailTransport, SendEmailError};
+
+// Does not deliver anything, for local development: emails are kept in memory and, when a
+// directory is configured, written there as JSON files to be looked at.
+// Clones share the same outbox.
+#[derive(Clone, Default)]
+pub struct OutboxTransport {
+    directory: Option<PathBuf>,
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl OutboxTransport {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn in_directory(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: Some(directory.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn sent_emails(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl EmailTransport for OutboxTransport {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        if let Some(directory) = self.directory.clone() {
+            let path = directory.join(format!(
+                "{}-{}.json",
+                chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+                Uuid::new_v4()
+            ));
+            let content = serde_json::to_vec_pretty(email).context("Failed to serialize email")?;
+            let file_path = path.clone();
+            tokio::task::spawn_blocking(move || {
+                std::fs::create_dir_all(&directory)?;
+                std::fs::write(&file_path, content)
+            })
+            .await
+            .context("Failed to write email to the outbox")?
+            .context("Failed to write email to the outbox")?;
+            tracing::info!(path = %path.display(), "Email written to the outbox");
+        }
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutboxTransport;
+    use crate::email_client::{Email, EmailTransport};
+
+    fn email() -> Email {
+        Email {
+            from: "newsletter@example.com".into(),
+            to: "reader@example.com".into(),
+            subject: "Subject".into(),
+            html_body: "<p>Body</p>".into(),
+            text_body: "Body".into(),
+            headers: vec![],
+            attachments: vec![],
+            message_stream: None,
+            tag: None,
+            metadata: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn emails_are_written_to_the_outbox_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let outbox = OutboxTransport::in_directory(&directory);
+
+        // Act
+        outbox.send(&email()).await.unwrap();
+
+        // Assert
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|f| f.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let stored: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&files[0]).unwrap()).unwrap();
+        assert_eq!(stored["to"], "reader@example.com");
+        assert_eq!(outbox.sent_emails().len(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
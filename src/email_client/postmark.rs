@@ -0,0 +1,590 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{check_http_response, http_client, Email, EmailTransport, SendEmailError};
+
+// https://postmarkapp.com/developer/api/email-api
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: Url,
+    server_token: Secret<String>,
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        server_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: http_client(timeout),
+            base_url: Url::parse(&base_url).expect("Failed to parse base url"),
+            server_token,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let url = self
+            .base_url
+            .join("/email")
+            .expect("Failed to join /email to the base url");
+        let response = self
+            .http_client
+            .post(url)
+            .header("X-Postmark-Server-Token", self.server_token.expose_secret())
+            .json(&SendEmailRequest::new(email))
+            .send()
+            .await?;
+        check_http_response(response)
+            .await
+            .map_err(refine_with_error_code)?;
+        Ok(())
+    }
+
+    fn supports_batch(&self) -> bool {
+        true
+    }
+
+    async fn send_batch(&self, emails: &[Email]) -> Vec<Result<(), SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                // The request as a whole failed, so did every email in it.
+                Err(e) => outcomes.extend(chunk.iter().map(|_| Err(e.replicate()))),
+            }
+        }
+        outcomes
+    }
+}
+
+// https://postmarkapp.com/developer/api/email-api#send-batch-emails
+const MAX_BATCH_SIZE: usize = 500;
+
+impl PostmarkTransport {
+    async fn send_chunk(
+        &self,
+        emails: &[Email],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let url = self
+            .base_url
+            .join("/email/batch")
+            .expect("Failed to join /email/batch to the base url");
+        let request_body: Vec<_> = emails.iter().map(SendEmailRequest::new).collect();
+        let response = self
+            .http_client
+            .post(url)
+            .header("X-Postmark-Server-Token", self.server_token.expose_secret())
+            .json(&request_body)
+            .send()
+            .await?;
+        let response = check_http_response(response)
+            .await
+            .map_err(refine_with_error_code)?;
+        // One entry per email, in the order they were sent.
+        let results: Vec<PostmarkError> = response
+            .json()
+            .await
+            .map_err(|e| SendEmailError::UnexpectedError(e.into()))?;
+        if results.len() != emails.len() {
+            return Err(SendEmailError::UnexpectedError(anyhow::anyhow!(
+                "Postmark returned {} results for a batch of {} emails",
+                results.len(),
+                emails.len()
+            )));
+        }
+        Ok(results
+            .into_iter()
+            .map(|r| match r.error_code {
+                0 => Ok(()),
+                // Postmark answers with a 422 when the same email is sent on its own.
+                _ => Err(refine_with_error_code(SendEmailError::Rejected {
+                    status: 422,
+                    body: serde_json::to_string(&r).unwrap_or_default(),
+                })),
+            })
+            .collect())
+    }
+}
+
+// Postmark answers errors with a JSON body carrying its own, more precise, error code.
+// https://postmarkapp.com/developer/api/overview#error-codes
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkError {
+    error_code: u32,
+    message: String,
+}
+
+fn refine_with_error_code(error: SendEmailError) -> SendEmailError {
+    let body = match &error {
+        SendEmailError::Unauthorized { message: body } | SendEmailError::Rejected { body, .. } => {
+            body
+        }
+        _ => return error,
+    };
+    let Ok(PostmarkError {
+        error_code,
+        message,
+    }) = serde_json::from_str(body)
+    else {
+        return error;
+    };
+    match error_code {
+        // Bad or missing server token, or sender signature not confirmed.
+        10 | 400 | 401 => SendEmailError::Unauthorized { message },
+        // "Invalid email request", the recipient is only to blame if its address is the field
+        // that failed validation, e.g. "Error parsing 'To': Illegal email address 'foo'".
+        300 if message.contains("'To'") => SendEmailError::InvalidRecipient { message },
+        406 => SendEmailError::InactiveRecipient { message },
+        _ => error,
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    headers: Vec<EmailHeader<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<PostmarkAttachment<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_stream: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(email: &'a Email) -> Self {
+        Self {
+            from: &email.from,
+            to: &email.to,
+            subject: &email.subject,
+            html_body: &email.html_body,
+            text_body: &email.text_body,
+            headers: email
+                .headers
+                .iter()
+                .map(|h| EmailHeader {
+                    name: &h.name,
+                    value: &h.value,
+                })
+                .collect(),
+            attachments: email
+                .attachments
+                .iter()
+                .map(|a| PostmarkAttachment {
+                    name: &a.filename,
+                    content: a.base64_content(),
+                    content_type: &a.content_type,
+                    content_id: a.content_id.as_ref().map(|id| format!("cid:{}", id)),
+                })
+                .collect(),
+            message_stream: email.message_stream.as_deref(),
+            tag: email.tag.as_deref(),
+            metadata: &email.metadata,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct EmailHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+// https://postmarkapp.com/developer/user-guide/send-email-with-api/send-an-email-with-attachments
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAttachment<'a> {
+    name: &'a str,
+    content: String,
+    content_type: &'a str,
+    // Only for inline attachments, e.g. `cid:logo`.
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::PostmarkTransport;
+    use crate::email_client::{Attachment, Email, EmailHeader, EmailTransport, SendEmailError};
+    use claims::{assert_err, assert_matches, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_partial_json, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            // Try to parse the body as a json `Value`
+            let result: Result<serde_json::Value, serde_json::Error> =
+                serde_json::from_slice(&request.body);
+            let Ok(body) = result else {
+                return false;
+            };
+            body.get("From").is_some()
+                && body.get("To").is_some()
+                && body.get("Subject").is_some()
+                && body.get("HtmlBody").is_some()
+                && body.get("TextBody").is_some()
+                && body.get("Headers").is_some()
+        }
+    }
+
+    // Tests Helpers
+    fn email() -> Email {
+        Email {
+            from: SafeEmail().fake(),
+            to: SafeEmail().fake(),
+            subject: Sentence(1..2).fake(),
+            html_body: Paragraph(1..10).fake(),
+            text_body: Paragraph(1..10).fake(),
+            headers: vec![EmailHeader {
+                name: "List-Unsubscribe".into(),
+                value: "<https://my-newsletter.com/unsubscribe>".into(),
+            }],
+            attachments: vec![],
+            message_stream: None,
+            tag: None,
+            metadata: Default::default(),
+        }
+    }
+    fn transport(base_url: String) -> PostmarkTransport {
+        PostmarkTransport::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            // custom matcher
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = transport.send(&email()).await;
+    }
+
+    #[tokio::test]
+    async fn attachments_are_sent_base64_encoded() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+        let email = Email {
+            attachments: vec![
+                Attachment::new("issue.pdf", "application/pdf", b"%PDF".to_vec()),
+                Attachment::inline("logo", "logo.png", "image/png", vec![1, 2, 3]),
+            ],
+            ..email()
+        };
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Attachments": [
+                    {
+                        "Name": "issue.pdf",
+                        "Content": "JVBERg==",
+                        "ContentType": "application/pdf",
+                    },
+                    {
+                        "Name": "logo.png",
+                        "Content": "AQID",
+                        "ContentType": "image/png",
+                        "ContentID": "cid:logo",
+                    },
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport.send(&email).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn the_message_stream_tag_and_metadata_are_sent_along() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+        let email = Email {
+            message_stream: Some("broadcast".into()),
+            tag: Some("newsletter-1".into()),
+            metadata: [("subscriber_id".to_string(), "42".to_string())].into(),
+            ..email()
+        };
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "MessageStream": "broadcast",
+                "Tag": "newsletter-1",
+                "Metadata": {"subscriber_id": "42"},
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport.send(&email).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport.send(&email()).await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+
+        // mock server returns a 500
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let output = transport.send(&email()).await;
+
+        assert_matches!(
+            output,
+            Err(SendEmailError::ProviderUnavailable { status: 500, .. })
+        );
+    }
+
+    #[tokio::test]
+    async fn send_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let output = transport.send(&email()).await;
+
+        assert_err!(&output);
+        assert_matches!(output, Err(SendEmailError::Unreachable(_)));
+    }
+
+    #[tokio::test]
+    async fn send_reports_the_retry_after_delay_of_the_server() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "2"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let output = transport.send(&email()).await;
+
+        assert_matches!(
+            output,
+            Err(SendEmailError::RateLimited {
+                retry_after: Some(delay),
+            }) if delay == Duration::from_secs(2)
+        );
+    }
+
+    async fn send_error(status: u16, body: serde_json::Value) -> SendEmailError {
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(status).set_body_json(body))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        transport(mock_server.uri())
+            .send(&email())
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn send_reports_an_invalid_recipient() {
+        let error = send_error(
+            422,
+            serde_json::json!({
+                "ErrorCode": 300,
+                "Message": "Error parsing 'To': Illegal email address 'foo'. It must contain the '@' symbol."
+            }),
+        )
+        .await;
+
+        assert_matches!(error, SendEmailError::InvalidRecipient { .. });
+        assert!(error.is_undeliverable_recipient());
+        assert!(!error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_reports_an_inactive_recipient() {
+        let error = send_error(
+            422,
+            serde_json::json!({
+                "ErrorCode": 406,
+                "Message": "You tried to send to recipient(s) that have been marked as inactive."
+            }),
+        )
+        .await;
+
+        assert_matches!(error, SendEmailError::InactiveRecipient { .. });
+        assert!(error.is_undeliverable_recipient());
+    }
+
+    #[tokio::test]
+    async fn send_reports_a_bad_server_token() {
+        let error = send_error(
+            401,
+            serde_json::json!({
+                "ErrorCode": 10,
+                "Message": "Bad or missing Server API token."
+            }),
+        )
+        .await;
+
+        assert_matches!(error, SendEmailError::Unauthorized { message } if message == "Bad or missing Server API token.");
+    }
+
+    #[tokio::test]
+    async fn send_keeps_other_validation_errors_as_rejections() {
+        let error = send_error(
+            422,
+            serde_json::json!({
+                "ErrorCode": 300,
+                "Message": "Provide either email TextBody or HtmlBody or both."
+            }),
+        )
+        .await;
+
+        assert_matches!(error, SendEmailError::Rejected { status: 422, .. });
+    }
+
+    #[tokio::test]
+    async fn send_batch_uses_the_batch_endpoint_and_reports_each_email() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+        let emails = vec![email(), email(), email()];
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .and(header_exists("X-Postmark-Server-Token"))
+            .and(body_partial_json(serde_json::json!([
+                {"To": emails[0].to},
+                {"To": emails[1].to},
+                {"To": emails[2].to},
+            ])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK"},
+                {
+                    "ErrorCode": 406,
+                    "Message": "You tried to send to recipient(s) that have been marked as inactive."
+                },
+                {"ErrorCode": 0, "Message": "OK"},
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = transport.send_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 3);
+        assert_ok!(&outcomes[0]);
+        assert_matches!(&outcomes[1], Err(SendEmailError::InactiveRecipient { .. }));
+        assert_ok!(&outcomes[2]);
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_email_if_the_request_fails() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let transport = transport(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = transport.send_batch(&[email(), email()]).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert_matches!(
+                outcome,
+                Err(SendEmailError::ProviderUnavailable { status: 500, .. })
+            );
+        }
+    }
+}
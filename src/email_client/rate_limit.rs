@@ -0,0 +1,188 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+// How long we hold off when the provider throttles us without saying for how long.
+const DEFAULT_SLOW_DOWN: Duration = Duration::from_secs(1);
+
+// Keeps the outbound traffic within what the provider accepts: a token bucket refilled at
+// `messages_per_second` (one token per email, bursts of up to a second worth of emails) and a
+// cap on the number of requests in flight.
+// Shared by every clone of an `EmailClient`, all the emails of the process count against it.
+pub struct RateLimiter {
+    // `None` when there is no rate limit.
+    bucket: Option<Mutex<TokenBucket>>,
+    requests: Semaphore,
+}
+
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    // Set when the provider asked us to back off, nothing goes out before then.
+    paused_until: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    // Takes `n` tokens if there are enough, otherwise returns how long to wait before asking
+    // again. A batch larger than the bucket goes out once the bucket is full and leaves it in
+    // debt, which the following emails pay for.
+    fn try_take(&mut self, n: u32, now: Instant) -> Result<(), Duration> {
+        if let Some(paused_until) = self.paused_until {
+            if paused_until > now {
+                return Err(paused_until - now);
+            }
+            self.paused_until = None;
+            self.refilled_at = now;
+        }
+        self.refill(now);
+        let needed = (n as f64).min(self.capacity);
+        if self.tokens >= needed {
+            self.tokens -= n as f64;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((needed - self.tokens) / self.rate))
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(messages_per_second: u32, max_concurrent_requests: u32) -> Self {
+        let rate = messages_per_second.max(1) as f64;
+        Self {
+            bucket: Some(Mutex::new(TokenBucket {
+                rate,
+                capacity: rate,
+                tokens: rate,
+                refilled_at: Instant::now(),
+                paused_until: None,
+            })),
+            requests: Semaphore::new(max_concurrent_requests.max(1) as usize),
+        }
+    }
+
+    // No limit at all.
+    pub fn unlimited() -> Self {
+        Self {
+            bucket: None,
+            requests: Semaphore::new(Semaphore::MAX_PERMITS),
+        }
+    }
+
+    // Waits until a request carrying `messages` emails can go out. The request counts as in
+    // flight until the returned permit is dropped.
+    pub async fn acquire(&self, messages: u32) -> SemaphorePermit<'_> {
+        let permit = self
+            .requests
+            .acquire()
+            .await
+            .expect("The semaphore is never closed");
+        let Some(bucket) = &self.bucket else {
+            return permit;
+        };
+        loop {
+            let outcome = bucket.lock().unwrap().try_take(messages, Instant::now());
+            match outcome {
+                Ok(()) => return permit,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    // The provider throttled us (429): pause every sender for `retry_after`, then start again
+    // from an empty bucket.
+    pub fn slow_down(&self, retry_after: Option<Duration>) {
+        let Some(bucket) = &self.bucket else {
+            return;
+        };
+        let now = Instant::now();
+        let until = now + retry_after.unwrap_or(DEFAULT_SLOW_DOWN);
+        let mut bucket = bucket.lock().unwrap();
+        bucket.paused_until = Some(bucket.paused_until.map_or(until, |p| p.max(until)));
+        bucket.tokens = 0.0;
+        tracing::warn!(
+            pause_milliseconds = (until - now).as_millis() as u64,
+            "The email provider is throttling us, slowing down"
+        );
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimiter, TokenBucket};
+    use claims::{assert_err, assert_ok};
+    use std::time::{Duration, Instant};
+
+    fn milliseconds(d: Duration) -> f64 {
+        (d.as_secs_f64() * 1000.0).round()
+    }
+
+    fn bucket(rate: f64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            rate,
+            capacity: rate,
+            tokens: rate,
+            refilled_at: now,
+            paused_until: None,
+        }
+    }
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_then_makes_us_wait() {
+        let now = Instant::now();
+        let mut bucket = bucket(10.0, now);
+        for _ in 0..10 {
+            assert_ok!(bucket.try_take(1, now));
+        }
+        let wait = bucket.try_take(1, now).unwrap_err();
+        assert_eq!(milliseconds(wait), 100.0);
+        assert_ok!(bucket.try_take(1, now + Duration::from_millis(101)));
+    }
+
+    #[test]
+    fn a_batch_larger_than_the_bucket_leaves_it_in_debt() {
+        let now = Instant::now();
+        let mut bucket = bucket(10.0, now);
+        assert_ok!(bucket.try_take(30, now));
+        // 20 tokens of debt plus the one we are asking for.
+        let wait = bucket.try_take(1, now).unwrap_err();
+        assert_eq!(milliseconds(wait), 2100.0);
+    }
+
+    #[test]
+    fn nothing_goes_out_while_paused() {
+        let now = Instant::now();
+        let mut bucket = bucket(10.0, now);
+        bucket.paused_until = Some(now + Duration::from_secs(2));
+        assert_err!(bucket.try_take(1, now + Duration::from_secs(1)));
+        assert_ok!(bucket.try_take(1, now + Duration::from_secs(2)));
+    }
+
+    #[tokio::test]
+    async fn requests_in_flight_are_capped() {
+        let limiter = RateLimiter::new(1000, 2);
+        let first = limiter.acquire(1).await;
+        let _second = limiter.acquire(1).await;
+        let third = tokio::time::timeout(Duration::from_millis(50), limiter.acquire(1)).await;
+        assert_err!(third);
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_millis(50), limiter.acquire(1)).await;
+        assert!(third.is_ok());
+    }
+}
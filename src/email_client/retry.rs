@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+// How many times, and how far apart, `EmailClient` tries to deliver an email.
+// The n-th retry waits `base_delay * 2^(n - 1)`, plus up to `jitter` so that clients failing
+// together do not retry in lockstep, and never more than `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    // A single attempt, nothing is retried.
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    // How long to wait after `attempt` (starting at 1) failed, `None` if we should give up.
+    // A `Retry-After` from the provider takes precedence over the backoff, unless it asks us to
+    // wait longer than `max_delay`.
+    pub fn delay_after(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_delay).then_some(retry_after);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1));
+        let jitter = if self.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+        };
+        Some(backoff.saturating_add(jitter).min(self.max_delay))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::no_retry()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use claims::{assert_none, assert_some_eq};
+    use std::time::Duration;
+
+    fn policy(jitter: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn delays_grow_exponentially_up_to_the_max_delay() {
+        let policy = policy(Duration::ZERO);
+        let delays: Vec<_> = (1..5)
+            .map(|attempt| policy.delay_after(attempt, None).unwrap())
+            .collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 500].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn there_is_no_delay_after_the_last_attempt() {
+        assert_none!(policy(Duration::ZERO).delay_after(5, None));
+        assert_none!(RetryPolicy::no_retry().delay_after(1, None));
+    }
+
+    #[test]
+    fn jitter_is_added_to_the_backoff() {
+        let policy = policy(Duration::from_millis(50));
+        for _ in 0..100 {
+            let delay = policy.delay_after(1, None).unwrap();
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn retry_after_overrides_the_backoff() {
+        let policy = policy(Duration::from_millis(50));
+        assert_some_eq!(
+            policy.delay_after(1, Some(Duration::from_millis(300))),
+            Duration::from_millis(300)
+        );
+    }
+
+    #[test]
+    fn we_give_up_if_retry_after_exceeds_the_max_delay() {
+        assert_none!(policy(Duration::ZERO).delay_after(1, Some(Duration::from_secs(60))));
+    }
+}
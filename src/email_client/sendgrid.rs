@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{check_http_response, http_client, Email, EmailTransport, SendEmailError};
+
+// https://www.twilio.com/docs/sendgrid/api-reference/mail-send/mail-send
+pub struct SendGridTransport {
+    http_client: Client,
+    base_url: Url,
+    api_key: Secret<String>,
+}
+
+impl SendGridTransport {
+    pub fn new(base_url: String, api_key: Secret<String>, timeout: std::time::Duration) -> Self {
+        Self {
+            http_client: http_client(timeout),
+            base_url: Url::parse(&base_url).expect("Failed to parse base url"),
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailTransport for SendGridTransport {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let url = self
+            .base_url
+            .join("/v3/mail/send")
+            .expect("Failed to join /v3/mail/send to the base url");
+        let request_body = MailSendRequest {
+            personalizations: vec![Personalization {
+                to: vec![Address { email: &email.to }],
+            }],
+            from: Address { email: &email.from },
+            subject: &email.subject,
+            // SendGrid requires `text/plain` to come first.
+            content: vec![
+                Content {
+                    r#type: "text/plain",
+                    value: &email.text_body,
+                },
+                Content {
+                    r#type: "text/html",
+                    value: &email.html_body,
+                },
+            ],
+            headers: email
+                .headers
+                .iter()
+                .map(|h| (h.name.as_str(), h.value.as_str()))
+                .collect(),
+            attachments: email
+                .attachments
+                .iter()
+                .map(|a| SendGridAttachment {
+                    content: a.base64_content(),
+                    r#type: &a.content_type,
+                    filename: &a.filename,
+                    disposition: if a.content_id.is_some() {
+                        "inline"
+                    } else {
+                        "attachment"
+                    },
+                    content_id: a.content_id.as_deref(),
+                })
+                .collect(),
+            categories: email.tag.iter().map(String::as_str).collect(),
+            custom_args: &email.metadata,
+        };
+        let response = self
+            .http_client
+            .post(url)
+            .bearer_auth(self.api_key.expose_secret())
+            .json(&request_body)
+            .send()
+            .await?;
+        check_http_response(response).await?;
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct MailSendRequest<'a> {
+    personalizations: Vec<Personalization<'a>>,
+    from: Address<'a>,
+    subject: &'a str,
+    content: Vec<Content<'a>>,
+    headers: BTreeMap<&'a str, &'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<SendGridAttachment<'a>>,
+    // Our tag, SendGrid has no notion of message stream.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    categories: Vec<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    custom_args: &'a BTreeMap<String, String>,
+}
+
+#[derive(serde::Serialize)]
+struct Personalization<'a> {
+    to: Vec<Address<'a>>,
+}
+
+#[derive(serde::Serialize)]
+struct Address<'a> {
+    email: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct Content<'a> {
+    r#type: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct SendGridAttachment<'a> {
+    content: String,
+    r#type: &'a str,
+    filename: &'a str,
+    disposition: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_id: Option<&'a str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendGridTransport;
+    use crate::email_client::{Attachment, Email, EmailHeader, EmailTransport, SendEmailError};
+    use claims::{assert_matches, assert_ok};
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_json, body_partial_json, header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn email() -> Email {
+        Email {
+            from: "newsletter@example.com".into(),
+            to: "reader@example.com".into(),
+            subject: "Subject".into(),
+            html_body: "<p>Body</p>".into(),
+            text_body: "Body".into(),
+            headers: vec![EmailHeader {
+                name: "List-Unsubscribe".into(),
+                value: "<https://example.com/unsubscribe>".into(),
+            }],
+            attachments: vec![],
+            message_stream: None,
+            tag: None,
+            metadata: Default::default(),
+        }
+    }
+
+    fn transport(base_url: String) -> SendGridTransport {
+        SendGridTransport::new(
+            base_url,
+            Secret::new("my-api-key".into()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v3/mail/send"))
+            .and(method("POST"))
+            .and(header("Authorization", "Bearer my-api-key"))
+            .and(body_json(serde_json::json!({
+                "personalizations": [{"to": [{"email": "reader@example.com"}]}],
+                "from": {"email": "newsletter@example.com"},
+                "subject": "Subject",
+                "content": [
+                    {"type": "text/plain", "value": "Body"},
+                    {"type": "text/html", "value": "<p>Body</p>"},
+                ],
+                "headers": {"List-Unsubscribe": "<https://example.com/unsubscribe>"},
+            })))
+            // SendGrid replies with `202 Accepted`
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email()).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn attachments_are_sent_with_their_disposition() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email = Email {
+            attachments: vec![
+                Attachment::new("issue.pdf", "application/pdf", b"%PDF".to_vec()),
+                Attachment::inline("logo", "logo.png", "image/png", vec![1, 2, 3]),
+            ],
+            ..email()
+        };
+        Mock::given(path("/v3/mail/send"))
+            .and(body_partial_json(serde_json::json!({
+                "attachments": [
+                    {
+                        "content": "JVBERg==",
+                        "type": "application/pdf",
+                        "filename": "issue.pdf",
+                        "disposition": "attachment",
+                    },
+                    {
+                        "content": "AQID",
+                        "type": "image/png",
+                        "filename": "logo.png",
+                        "disposition": "inline",
+                        "content_id": "logo",
+                    },
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn the_tag_and_metadata_are_sent_as_category_and_custom_args() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email = Email {
+            message_stream: Some("broadcast".into()),
+            tag: Some("newsletter-1".into()),
+            metadata: [("subscriber_id".to_string(), "42".to_string())].into(),
+            ..email()
+        };
+        Mock::given(path("/v3/mail/send"))
+            .and(body_partial_json(serde_json::json!({
+                "categories": ["newsletter-1"],
+                "custom_args": {"subscriber_id": "42"},
+            })))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email).await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert!(body.get("message_stream").is_none());
+    }
+
+    #[tokio::test]
+    async fn send_fails_if_the_server_returns_an_error() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email()).await;
+
+        // Assert
+        assert_matches!(outcome, Err(SendEmailError::Unauthorized { .. }));
+    }
+}
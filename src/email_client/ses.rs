@@ -0,0 +1,469 @@
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Url};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use super::{
+    check_http_response, http_client, mime::mime_message, Email, EmailTransport, SendEmailError,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SEND_EMAIL_PATH: &str = "/v2/email/outbound-emails";
+
+// Amazon SES v2 `SendEmail`, authenticated with AWS Signature Version 4.
+// https://docs.aws.amazon.com/ses/latest/APIReference-V2/API_SendEmail.html
+pub struct SesTransport {
+    http_client: Client,
+    base_url: Url,
+    region: String,
+    access_key_id: String,
+    secret_access_key: Secret<String>,
+}
+
+impl SesTransport {
+    pub fn new(
+        base_url: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: http_client(timeout),
+            base_url: Url::parse(&base_url).expect("Failed to parse base url"),
+            region,
+            access_key_id,
+            secret_access_key,
+        }
+    }
+}
+
+#[async_trait]
+impl EmailTransport for SesTransport {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let url = self
+            .base_url
+            .join(SEND_EMAIL_PATH)
+            .expect("Failed to join the SES path to the base url");
+        let request_body = serde_json::to_vec(&SendEmailRequest::new(email)?)
+            .map_err(|e| SendEmailError::UnexpectedError(e.into()))?;
+        // The `Host` header is signed, it must match what `reqwest` derives from the url.
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let signer = SigV4 {
+            access_key_id: &self.access_key_id,
+            secret_access_key: &self.secret_access_key,
+            region: &self.region,
+            service: "ses",
+        };
+        let authorization = signer.authorization(
+            "POST",
+            SEND_EMAIL_PATH,
+            &[
+                ("content-type", "application/json"),
+                ("host", &host),
+                ("x-amz-date", &amz_date),
+            ],
+            &request_body,
+            now,
+        );
+        let response = self
+            .http_client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Amz-Date", amz_date)
+            .header("Authorization", authorization)
+            .body(request_body)
+            .send()
+            .await?;
+        check_http_response(response).await?;
+        Ok(())
+    }
+}
+
+// https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html
+struct SigV4<'a> {
+    access_key_id: &'a str,
+    secret_access_key: &'a Secret<String>,
+    region: &'a str,
+    service: &'a str,
+}
+
+impl SigV4<'_> {
+    // `headers` must be lowercase, sorted by name, and include every header that is signed.
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        now: DateTime<Utc>,
+    ) -> String {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        // Method, path, query string (none), headers, signed headers, payload hash.
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method,
+            path,
+            canonical_headers,
+            signed_headers,
+            hex::encode(Sha256::digest(payload))
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = [
+            self.region.as_bytes(),
+            self.service.as_bytes(),
+            b"aws4_request",
+        ]
+        .iter()
+        .fold(
+            hmac_sha256(
+                format!("AWS4{}", self.secret_access_key.expose_secret()).as_bytes(),
+                date.as_bytes(),
+            ),
+            |key, data| hmac_sha256(&key, data),
+        );
+        let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id, scope, signed_headers, signature
+        )
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from_email_address: &'a str,
+    destination: Destination<'a>,
+    content: Content<'a>,
+    // Our tag and metadata, SES has no notion of message stream.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    email_tags: Vec<MessageTag<'a>>,
+}
+
+impl<'a> SendEmailRequest<'a> {
+    fn new(email: &'a Email) -> Result<Self, anyhow::Error> {
+        // Simple content has no room for attachments, we build the MIME message ourselves.
+        let content = if email.attachments.is_empty() {
+            Content::Simple(SimpleContent {
+                subject: Text {
+                    data: &email.subject,
+                },
+                body: Body {
+                    text: Text {
+                        data: &email.text_body,
+                    },
+                    html: Text {
+                        data: &email.html_body,
+                    },
+                },
+                headers: email
+                    .headers
+                    .iter()
+                    .map(|h| Header {
+                        name: &h.name,
+                        value: &h.value,
+                    })
+                    .collect(),
+            })
+        } else {
+            Content::Raw(RawContent {
+                data: STANDARD.encode(mime_message(email)?.formatted()),
+            })
+        };
+        Ok(Self {
+            from_email_address: &email.from,
+            destination: Destination {
+                to_addresses: vec![&email.to],
+            },
+            content,
+            email_tags: email
+                .tag
+                .iter()
+                .map(|tag| MessageTag {
+                    name: "tag",
+                    value: tag,
+                })
+                .chain(
+                    email
+                        .metadata
+                        .iter()
+                        .map(|(name, value)| MessageTag { name, value }),
+                )
+                .collect(),
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Destination<'a> {
+    to_addresses: Vec<&'a str>,
+}
+
+#[derive(serde::Serialize)]
+enum Content<'a> {
+    Simple(SimpleContent<'a>),
+    Raw(RawContent),
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawContent {
+    // The whole MIME message, base64 encoded.
+    data: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SimpleContent<'a> {
+    subject: Text<'a>,
+    body: Body<'a>,
+    headers: Vec<Header<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Body<'a> {
+    text: Text<'a>,
+    html: Text<'a>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Text<'a> {
+    data: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageTag<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Header<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SesTransport, SigV4};
+    use crate::email_client::{Attachment, Email, EmailHeader, EmailTransport, SendEmailError};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_matches, assert_ok};
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, body_json, body_partial_json, header_exists, header_regex, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn email() -> Email {
+        Email {
+            from: "newsletter@example.com".into(),
+            to: "reader@example.com".into(),
+            subject: "Subject".into(),
+            html_body: "<p>Body</p>".into(),
+            text_body: "Body".into(),
+            headers: vec![EmailHeader {
+                name: "List-Unsubscribe".into(),
+                value: "<https://example.com/unsubscribe>".into(),
+            }],
+            attachments: vec![],
+            message_stream: None,
+            tag: None,
+            metadata: Default::default(),
+        }
+    }
+
+    fn transport(base_url: String) -> SesTransport {
+        SesTransport::new(
+            base_url,
+            "eu-west-1".into(),
+            "AKIDEXAMPLE".into(),
+            Secret::new("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    // `get-vanilla` from the AWS Signature Version 4 test suite.
+    #[test]
+    fn signatures_match_the_aws_test_suite() {
+        let secret = Secret::new("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string());
+        let signer = SigV4 {
+            access_key_id: "AKIDEXAMPLE",
+            secret_access_key: &secret,
+            region: "us-east-1",
+            service: "service",
+        };
+        let authorization = signer.authorization(
+            "GET",
+            "/",
+            &[
+                ("host", "example.amazonaws.com"),
+                ("x-amz-date", "20150830T123600Z"),
+            ],
+            b"",
+            Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap(),
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+            SignedHeaders=host;x-amz-date, \
+            Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_sends_a_signed_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v2/email/outbound-emails"))
+            .and(method("POST"))
+            .and(header_exists("X-Amz-Date"))
+            .and(header_regex(
+                "Authorization",
+                concat!(
+                    r"^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/\d{8}/eu-west-1/ses/aws4_request, ",
+                    r"SignedHeaders=content-type;host;x-amz-date, Signature=[0-9a-f]{64}$",
+                ),
+            ))
+            .and(body_json(serde_json::json!({
+                "FromEmailAddress": "newsletter@example.com",
+                "Destination": {"ToAddresses": ["reader@example.com"]},
+                "Content": {"Simple": {
+                    "Subject": {"Data": "Subject"},
+                    "Body": {"Text": {"Data": "Body"}, "Html": {"Data": "<p>Body</p>"}},
+                    "Headers": [
+                        {"Name": "List-Unsubscribe", "Value": "<https://example.com/unsubscribe>"}
+                    ],
+                }},
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email()).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn emails_with_attachments_are_sent_as_raw_mime_messages() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v2/email/outbound-emails"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email = Email {
+            attachments: vec![Attachment::new(
+                "issue.pdf",
+                "application/pdf",
+                b"%PDF".to_vec(),
+            )],
+            ..email()
+        };
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email).await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert!(body["Content"].get("Simple").is_none());
+        let raw = STANDARD
+            .decode(body["Content"]["Raw"]["Data"].as_str().unwrap())
+            .unwrap();
+        let raw = String::from_utf8(raw).unwrap();
+        assert!(raw.contains("Subject: Subject"));
+        assert!(raw.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(raw.contains("Content-Type: multipart/mixed"));
+        assert!(raw.contains("filename=\"issue.pdf\""));
+    }
+
+    #[tokio::test]
+    async fn the_tag_and_metadata_are_sent_as_email_tags() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(path("/v2/email/outbound-emails"))
+            .and(body_partial_json(serde_json::json!({
+                "EmailTags": [
+                    {"Name": "tag", "Value": "newsletter-1"},
+                    {"Name": "subscriber_id", "Value": "42"},
+                ],
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let email = Email {
+            tag: Some("newsletter-1".into()),
+            metadata: [("subscriber_id".to_string(), "42".to_string())].into(),
+            ..email()
+        };
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email).await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_fails_if_the_server_returns_an_error() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(403))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = transport(mock_server.uri()).send(&email()).await;
+
+        // Assert
+        assert_matches!(outcome, Err(SendEmailError::Unauthorized { .. }));
+    }
+}
@@ -0,0 +1,228 @@
+use async_trait::async_trait;
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use super::{mime::mime_message, Email, EmailTransport, SendEmailError};
+
+// How the connection to the relay is secured.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    // Plain text, only meant for a relay on the local machine or network (e.g. Mailpit).
+    None,
+    // Upgrade a plain text connection with `STARTTLS`, usually on port 587.
+    StartTls,
+    // TLS from the start, usually on port 465.
+    Tls,
+}
+
+// Delivers through a generic SMTP relay.
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, Secret<String>)>,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = match tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        };
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &Email) -> Result<(), SendEmailError> {
+        let message = mime_message(email).map_err(SendEmailError::UnexpectedError)?;
+        self.mailer.send(message).await.map_err(|e| {
+            // The server answered with an error code, as opposed to not answering at all.
+            if e.is_permanent() || e.is_transient() {
+                SendEmailError::SmtpRejected(e.into())
+            } else {
+                SendEmailError::Unreachable(e.into())
+            }
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SmtpTls, SmtpTransport};
+    use crate::email_client::{Attachment, Email, EmailHeader, EmailTransport, SendEmailError};
+    use claims::{assert_matches, assert_ok};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    fn email() -> Email {
+        Email {
+            from: "newsletter@example.com".into(),
+            to: "reader@example.com".into(),
+            subject: "Subject".into(),
+            html_body: "<p>Body</p>".into(),
+            text_body: "Body".into(),
+            headers: vec![EmailHeader {
+                name: "List-Unsubscribe".into(),
+                value: "<https://example.com/unsubscribe>".into(),
+            }],
+            attachments: vec![],
+            message_stream: None,
+            tag: None,
+            metadata: Default::default(),
+        }
+    }
+
+    // A bare-bones SMTP server handling a single session. Replies `rcpt_reply` to `RCPT TO`,
+    // and returns the commands and message data it received.
+    async fn smtp_stand_in(rcpt_reply: &'static str) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut transcript = String::new();
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    "250 Queued\r\n"
+                } else {
+                    match line.get(..4).map(|c| c.to_ascii_uppercase()).as_deref() {
+                        Some("EHLO") => "250 localhost\r\n",
+                        Some("RCPT") => rcpt_reply,
+                        Some("DATA") => {
+                            in_data = true;
+                            "354 End data with <CR><LF>.<CR><LF>\r\n"
+                        }
+                        Some("QUIT") => {
+                            writer.write_all(b"221 Bye\r\n").await.unwrap();
+                            break;
+                        }
+                        _ => "250 OK\r\n",
+                    }
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+            transcript
+        });
+        (port, handle)
+    }
+
+    fn transport(port: u16) -> SmtpTransport {
+        SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            SmtpTls::None,
+            None,
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_delivers_the_email_to_the_relay() {
+        // Arrange
+        let (port, server) = smtp_stand_in("250 OK\r\n").await;
+
+        // Act
+        let outcome = transport(port).send(&email()).await;
+
+        // Assert
+        assert_ok!(outcome);
+        let transcript = server.await.unwrap();
+        assert!(transcript.contains("MAIL FROM:<newsletter@example.com>"));
+        assert!(transcript.contains("RCPT TO:<reader@example.com>"));
+        assert!(transcript.contains("Subject: Subject"));
+        assert!(transcript.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(transcript.contains("Content-Type: text/plain"));
+        assert!(transcript.contains("Content-Type: text/html"));
+    }
+
+    #[tokio::test]
+    async fn attachments_are_sent_as_mime_parts() {
+        // Arrange
+        let (port, server) = smtp_stand_in("250 OK\r\n").await;
+        let email = Email {
+            attachments: vec![
+                Attachment::new("issue.pdf", "application/pdf", b"%PDF".to_vec()),
+                Attachment::inline("logo", "logo.png", "image/png", vec![1, 2, 3]),
+            ],
+            ..email()
+        };
+
+        // Act
+        let outcome = transport(port).send(&email).await;
+
+        // Assert
+        assert_ok!(outcome);
+        let transcript = server.await.unwrap();
+        assert!(transcript.contains("Content-Type: multipart/mixed"));
+        assert!(transcript.contains("Content-Type: multipart/related"));
+        assert!(transcript.contains("Content-Type: multipart/alternative"));
+        assert!(transcript.contains("filename=\"issue.pdf\""));
+        assert!(transcript.contains("Content-ID: <logo>"));
+    }
+
+    #[tokio::test]
+    async fn send_fails_if_the_relay_rejects_the_recipient() {
+        // Arrange
+        let (port, _server) = smtp_stand_in("550 No such user\r\n").await;
+
+        // Act
+        let outcome = transport(port).send(&email()).await;
+
+        // Assert
+        assert_matches!(outcome, Err(SendEmailError::SmtpRejected(_)));
+    }
+
+    #[tokio::test]
+    async fn send_fails_if_the_relay_cannot_be_reached() {
+        // Arrange
+        // Grab a free port, and close it right away.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        // Act
+        let outcome = transport(port).send(&email()).await;
+
+        // Assert
+        assert_matches!(outcome, Err(SendEmailError::Unreachable(_)));
+    }
+}
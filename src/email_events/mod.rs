@@ -0,0 +1,198 @@
+mod postmark;
+mod sendgrid;
+mod ses;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::SubscriptionStatus,
+    subscriber_status::{set_subscriber_status, StatusUpdateError},
+};
+
+// What happened to an email we sent, as far as its recipient is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailEventKind {
+    Delivery,
+    // The provider may try again, or the next email may go through (e.g. a full mailbox).
+    SoftBounce,
+    // The address does not exist, or refuses our emails for good.
+    HardBounce,
+    // The recipient marked the email as spam.
+    Complaint,
+}
+
+impl EmailEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailEventKind::Delivery => "delivery",
+            EmailEventKind::SoftBounce => "soft_bounce",
+            EmailEventKind::HardBounce => "hard_bounce",
+            EmailEventKind::Complaint => "complaint",
+        }
+    }
+
+    // Where the event leaves the subscriber, if it changes anything.
+    pub fn subscription_status(&self) -> Option<SubscriptionStatus> {
+        match self {
+            EmailEventKind::HardBounce => Some(SubscriptionStatus::Bounced),
+            EmailEventKind::Complaint => Some(SubscriptionStatus::Complained),
+            EmailEventKind::Delivery | EmailEventKind::SoftBounce => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmailEvent {
+    pub provider: &'static str,
+    pub kind: EmailEventKind,
+    pub recipient: String,
+    // Identifies the event itself, a webhook sent again carries the same one.
+    pub provider_event_id: String,
+    pub provider_message_id: Option<String>,
+    // When the provider does not tell, the time we heard about it.
+    pub occurred_at: DateTime<Utc>,
+    // The event as sent by the provider.
+    pub payload: serde_json::Value,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("The webhook payload is not one we know how to read")]
+pub struct UnknownPayload(#[source] pub anyhow::Error);
+
+// Read the events out of a webhook sent by one of the providers we support, told apart by the
+// shape of the payload:
+// - Postmark posts one object per event, with a `RecordType`;
+// - SendGrid posts an array of events;
+// - SES notifies through SNS, which wraps the event in an object with a `Type` and a `TopicArn`.
+// Events we have no use for (opens, clicks, deferrals, ...) are left out.
+pub fn parse_webhook(body: &[u8]) -> Result<Vec<EmailEvent>, UnknownPayload> {
+    let payload: serde_json::Value = serde_json::from_slice(body)
+        .context("The payload is not valid JSON")
+        .map_err(UnknownPayload)?;
+    let events = if payload.is_array() {
+        sendgrid::parse(payload)
+    } else if payload.get("RecordType").is_some() {
+        postmark::parse(payload)
+    } else if payload.get("Type").is_some() && payload.get("TopicArn").is_some() {
+        ses::parse(payload)
+    } else {
+        Err(anyhow::anyhow!("The payload matches none of the providers"))
+    };
+    events.map_err(UnknownPayload)
+}
+
+// Providers send their timestamps in RFC 3339.
+fn parse_timestamp(timestamp: Option<&str>) -> DateTime<Utc> {
+    timestamp
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+// For events the provider does not give an id to: what the event is about. The same event,
+// sent again, gets the same id.
+fn derive_event_id(
+    kind: EmailEventKind,
+    provider_message_id: Option<&str>,
+    recipient: &str,
+    occurred_at: DateTime<Utc>,
+) -> String {
+    format!(
+        "{}:{}:{}:{}",
+        kind.as_str(),
+        provider_message_id.unwrap_or_default(),
+        recipient,
+        occurred_at.to_rfc3339()
+    )
+}
+
+// Store the event and, for hard bounces and complaints, take the subscriber out of future sends.
+// A status the subscriber cannot move to is not an error: a complaint about an address that
+// already bounced changes nothing.
+#[tracing::instrument(
+    name = "Record an email event",
+    skip(pool, event),
+    fields(provider = event.provider, kind = event.kind.as_str())
+)]
+pub async fn record_email_event(pool: &PgPool, event: &EmailEvent) -> Result<(), anyhow::Error> {
+    // The event is stored along with its effect on the subscriber, or not at all: a webhook
+    // retried after a failure is applied in full.
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let subscriber_id = sqlx::query!(
+        "SELECT id FROM subscriptions WHERE email = $1",
+        event.recipient
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up the subscriber of an email event")?
+    .map(|r| r.id);
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            id, provider, kind, recipient, subscriber_id, provider_event_id,
+            provider_message_id, occurred_at, received_at, payload
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now(), $9)
+        ON CONFLICT (provider, provider_event_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.provider,
+        event.kind.as_str(),
+        event.recipient,
+        subscriber_id,
+        event.provider_event_id,
+        event.provider_message_id,
+        event.occurred_at,
+        event.payload.to_string(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store an email event")?
+    .rows_affected();
+    if inserted == 0 {
+        tracing::info!("The email event was already recorded");
+        return Ok(());
+    }
+    if let (Some(subscriber_id), Some(next)) = (subscriber_id, event.kind.subscription_status()) {
+        match set_subscriber_status(&mut transaction, subscriber_id, next).await {
+            Ok(()) => {}
+            // Deleted in the meantime.
+            Err(StatusUpdateError::UnknownSubscriber(_)) => {}
+            Err(StatusUpdateError::IllegalTransition(e)) => {
+                tracing::info!(error.message = %e, "The email event leaves the subscriber as is");
+            }
+            Err(StatusUpdateError::UnexpectedError(e)) => return Err(e),
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record an email event")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_webhook, EmailEventKind};
+    use claims::assert_err;
+
+    #[test]
+    fn payloads_of_unknown_providers_are_rejected() {
+        assert_err!(parse_webhook(b"not json"));
+        assert_err!(parse_webhook(br#"{"event": "bounce"}"#));
+    }
+
+    #[test]
+    fn hard_bounces_and_complaints_end_the_subscription() {
+        assert!(EmailEventKind::HardBounce.subscription_status().is_some());
+        assert!(EmailEventKind::Complaint.subscription_status().is_some());
+        assert!(EmailEventKind::SoftBounce.subscription_status().is_none());
+        assert!(EmailEventKind::Delivery.subscription_status().is_none());
+    }
+}
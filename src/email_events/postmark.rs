@@ -0,0 +1,138 @@
+use anyhow::Context;
+
+use super::{derive_event_id, parse_timestamp, EmailEvent, EmailEventKind};
+
+// https://postmarkapp.com/developer/webhooks/webhooks-overview
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Record {
+    record_type: String,
+    // Bounces and spam complaints.
+    #[serde(rename = "ID")]
+    id: Option<u64>,
+    // The kind of bounce, e.g. `HardBounce` or `Transient`.
+    #[serde(rename = "Type")]
+    bounce_type: Option<String>,
+    // Bounces and spam complaints.
+    email: Option<String>,
+    // Deliveries.
+    recipient: Option<String>,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    bounced_at: Option<String>,
+    delivered_at: Option<String>,
+}
+
+pub(super) fn parse(payload: serde_json::Value) -> Result<Vec<EmailEvent>, anyhow::Error> {
+    let record: Record =
+        serde_json::from_value(payload.clone()).context("Invalid Postmark webhook")?;
+    let (kind, recipient, timestamp) = match record.record_type.as_str() {
+        "Bounce" => {
+            let kind = match record.bounce_type.as_deref() {
+                Some("HardBounce" | "BadEmailAddress" | "ManuallyDeactivated") => {
+                    EmailEventKind::HardBounce
+                }
+                Some("SpamComplaint") => EmailEventKind::Complaint,
+                _ => EmailEventKind::SoftBounce,
+            };
+            (kind, record.email, record.bounced_at)
+        }
+        "SpamComplaint" => (EmailEventKind::Complaint, record.email, record.bounced_at),
+        "Delivery" => (
+            EmailEventKind::Delivery,
+            record.recipient,
+            record.delivered_at,
+        ),
+        _ => return Ok(vec![]),
+    };
+    let recipient = recipient.context("The Postmark webhook has no recipient")?;
+    let occurred_at = parse_timestamp(timestamp.as_deref());
+    // Deliveries have no id of their own.
+    let provider_event_id = match record.id {
+        Some(id) => id.to_string(),
+        None => derive_event_id(kind, record.message_id.as_deref(), &recipient, occurred_at),
+    };
+    Ok(vec![EmailEvent {
+        provider: "postmark",
+        kind,
+        recipient,
+        provider_event_id,
+        provider_message_id: record.message_id,
+        occurred_at,
+        payload,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::email_events::EmailEventKind;
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+
+    fn bounce(bounce_type: &str) -> serde_json::Value {
+        serde_json::json!({
+            "RecordType": "Bounce",
+            "ID": 4323372036854775807_u64,
+            "Type": bounce_type,
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Email": "reader@example.com",
+            "BouncedAt": "2024-07-30T16:33:54Z",
+        })
+    }
+
+    #[test]
+    fn hard_bounces_are_told_apart_from_soft_ones() {
+        let events = assert_ok!(parse(bounce("HardBounce")));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, EmailEventKind::HardBounce);
+        assert_eq!(events[0].recipient, "reader@example.com");
+        assert_eq!(
+            events[0].provider_message_id.as_deref(),
+            Some("883953f4-6105-42a2-a16a-77a8eac79483")
+        );
+        assert_eq!(events[0].provider_event_id, "4323372036854775807");
+        assert_eq!(
+            events[0].occurred_at,
+            Utc.with_ymd_and_hms(2024, 7, 30, 16, 33, 54).unwrap()
+        );
+        let events = assert_ok!(parse(bounce("Transient")));
+        assert_eq!(events[0].kind, EmailEventKind::SoftBounce);
+    }
+
+    #[test]
+    fn spam_complaints_and_deliveries_are_read() {
+        let complaint = serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Type": "SpamComplaint",
+            "Email": "reader@example.com",
+            "BouncedAt": "2024-07-30T16:33:54Z",
+        });
+        assert_eq!(
+            assert_ok!(parse(complaint))[0].kind,
+            EmailEventKind::Complaint
+        );
+        let delivery = serde_json::json!({
+            "RecordType": "Delivery",
+            "Recipient": "reader@example.com",
+            "DeliveredAt": "2024-07-30T16:33:54.9070259Z",
+        });
+        assert_eq!(
+            assert_ok!(parse(delivery))[0].kind,
+            EmailEventKind::Delivery
+        );
+    }
+
+    #[test]
+    fn other_record_types_are_left_out() {
+        let open = serde_json::json!({"RecordType": "Open", "Recipient": "reader@example.com"});
+        assert!(assert_ok!(parse(open)).is_empty());
+    }
+
+    #[test]
+    fn a_bounce_without_recipient_is_rejected() {
+        assert_err!(parse(
+            serde_json::json!({"RecordType": "Bounce", "Type": "HardBounce"})
+        ));
+    }
+}
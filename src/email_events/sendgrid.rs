@@ -0,0 +1,98 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+
+use super::{derive_event_id, EmailEvent, EmailEventKind};
+
+// https://www.twilio.com/docs/sendgrid/for-developers/tracking-events/event
+#[derive(serde::Deserialize)]
+struct Event {
+    email: String,
+    event: String,
+    // Bounces only, `bounce` or `blocked`.
+    #[serde(rename = "type")]
+    bounce_type: Option<String>,
+    // Seconds since the epoch.
+    timestamp: Option<i64>,
+    sg_message_id: Option<String>,
+    sg_event_id: Option<String>,
+}
+
+pub(super) fn parse(payload: serde_json::Value) -> Result<Vec<EmailEvent>, anyhow::Error> {
+    let serde_json::Value::Array(payload) = payload else {
+        anyhow::bail!("A SendGrid webhook is an array of events");
+    };
+    let mut events = vec![];
+    for payload in payload {
+        let event: Event =
+            serde_json::from_value(payload.clone()).context("Invalid SendGrid event")?;
+        let kind = match (event.event.as_str(), event.bounce_type.as_deref()) {
+            ("delivered", _) => EmailEventKind::Delivery,
+            // Blocks are usually temporary, e.g. our IP being on a blocklist.
+            ("bounce", Some("blocked")) => EmailEventKind::SoftBounce,
+            ("bounce", _) => EmailEventKind::HardBounce,
+            ("spamreport", _) => EmailEventKind::Complaint,
+            _ => continue,
+        };
+        let occurred_at = event
+            .timestamp
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .unwrap_or_else(Utc::now);
+        let provider_event_id = event.sg_event_id.unwrap_or_else(|| {
+            derive_event_id(
+                kind,
+                event.sg_message_id.as_deref(),
+                &event.email,
+                occurred_at,
+            )
+        });
+        events.push(EmailEvent {
+            provider: "sendgrid",
+            kind,
+            recipient: event.email,
+            provider_event_id,
+            provider_message_id: event.sg_message_id,
+            occurred_at,
+            payload,
+        });
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::email_events::EmailEventKind;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn every_event_of_interest_is_read() {
+        let payload = serde_json::json!([
+            {"email": "a@example.com", "event": "delivered", "timestamp": 1722357234},
+            {"email": "b@example.com", "event": "bounce", "type": "bounce", "sg_message_id": "x"},
+            {"email": "c@example.com", "event": "bounce", "type": "blocked"},
+            {"email": "d@example.com", "event": "spamreport"},
+            {"email": "e@example.com", "event": "open"},
+        ]);
+        let events = assert_ok!(parse(payload));
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|e| (e.recipient.as_str(), e.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("a@example.com", EmailEventKind::Delivery),
+                ("b@example.com", EmailEventKind::HardBounce),
+                ("c@example.com", EmailEventKind::SoftBounce),
+                ("d@example.com", EmailEventKind::Complaint),
+            ]
+        );
+        assert_eq!(events[0].occurred_at.timestamp(), 1722357234);
+        assert_eq!(events[1].provider_message_id.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn events_without_recipient_are_rejected() {
+        assert_err!(parse(serde_json::json!([{"event": "bounce"}])));
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Context;
+
+use super::{derive_event_id, parse_timestamp, EmailEvent, EmailEventKind};
+
+// SES publishes its notifications to an SNS topic, which posts them to us.
+// https://docs.aws.amazon.com/sns/latest/dg/sns-message-and-json-formats.html
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SnsMessage {
+    #[serde(rename = "Type")]
+    message_type: String,
+    message_id: Option<String>,
+    // The SES notification, as a JSON string.
+    message: String,
+    #[serde(rename = "SubscribeURL")]
+    subscribe_url: Option<String>,
+}
+
+// https://docs.aws.amazon.com/ses/latest/dg/notification-contents.html
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Notification {
+    // `notificationType` for identity notifications, `eventType` for configuration sets.
+    #[serde(alias = "eventType")]
+    notification_type: String,
+    mail: Mail,
+    bounce: Option<Bounce>,
+    complaint: Option<Complaint>,
+    delivery: Option<Delivery>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Mail {
+    message_id: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bounce {
+    // `Permanent`, `Transient` or `Undetermined`.
+    bounce_type: String,
+    bounced_recipients: Vec<Recipient>,
+    timestamp: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Complaint {
+    complained_recipients: Vec<Recipient>,
+    timestamp: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Recipient {
+    email_address: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Delivery {
+    recipients: Vec<String>,
+    timestamp: Option<String>,
+}
+
+pub(super) fn parse(payload: serde_json::Value) -> Result<Vec<EmailEvent>, anyhow::Error> {
+    let message: SnsMessage = serde_json::from_value(payload).context("Invalid SNS message")?;
+    if message.message_type == "SubscriptionConfirmation" {
+        // SNS starts posting notifications once the subscription is confirmed, by visiting
+        // `SubscribeURL`. We leave that to whoever sets the topic up.
+        tracing::warn!(
+            subscribe_url = message.subscribe_url.as_deref(),
+            "SNS asks to confirm the subscription of the email webhook"
+        );
+        return Ok(vec![]);
+    }
+    if message.message_type != "Notification" {
+        return Ok(vec![]);
+    }
+    let payload: serde_json::Value =
+        serde_json::from_str(&message.message).context("Invalid SES notification")?;
+    let notification: Notification =
+        serde_json::from_value(payload.clone()).context("Invalid SES notification")?;
+    let (kind, recipients, timestamp) = match notification.notification_type.as_str() {
+        "Bounce" => {
+            let bounce = notification.bounce.context("A bounce without details")?;
+            let kind = if bounce.bounce_type == "Permanent" {
+                EmailEventKind::HardBounce
+            } else {
+                EmailEventKind::SoftBounce
+            };
+            let recipients = bounce.bounced_recipients.into_iter();
+            (
+                kind,
+                recipients.map(|r| r.email_address).collect(),
+                bounce.timestamp,
+            )
+        }
+        "Complaint" => {
+            let complaint = notification
+                .complaint
+                .context("A complaint without details")?;
+            let recipients = complaint.complained_recipients.into_iter();
+            (
+                EmailEventKind::Complaint,
+                recipients.map(|r| r.email_address).collect(),
+                complaint.timestamp,
+            )
+        }
+        "Delivery" => {
+            let delivery = notification
+                .delivery
+                .context("A delivery without details")?;
+            (
+                EmailEventKind::Delivery,
+                delivery.recipients,
+                delivery.timestamp,
+            )
+        }
+        _ => return Ok(vec![]),
+    };
+    let occurred_at = parse_timestamp(timestamp.as_deref());
+    // One notification covers every recipient of the email.
+    let events = recipients
+        .into_iter()
+        .map(|recipient: String| {
+            // SNS identifies the notification, the recipient tells its events apart.
+            let provider_event_id = match &message.message_id {
+                Some(id) => format!("{}:{}", id, recipient),
+                None => derive_event_id(
+                    kind,
+                    notification.mail.message_id.as_deref(),
+                    &recipient,
+                    occurred_at,
+                ),
+            };
+            EmailEvent {
+                provider: "ses",
+                kind,
+                recipient,
+                provider_event_id,
+                provider_message_id: notification.mail.message_id.clone(),
+                occurred_at,
+                payload: payload.clone(),
+            }
+        })
+        .collect();
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::email_events::EmailEventKind;
+    use claims::{assert_err, assert_ok};
+
+    fn sns(notification: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "Type": "Notification",
+            "MessageId": "22b80b92-fdea-4c2c-8f9d-bdfb0c7bf324",
+            "TopicArn": "arn:aws:sns:eu-west-1:123456789012:ses-events",
+            "Message": notification.to_string(),
+        })
+    }
+
+    #[test]
+    fn permanent_bounces_are_hard_bounces() {
+        let payload = sns(serde_json::json!({
+            "notificationType": "Bounce",
+            "mail": {"messageId": "0000014a"},
+            "bounce": {
+                "bounceType": "Permanent",
+                "bouncedRecipients": [
+                    {"emailAddress": "a@example.com"},
+                    {"emailAddress": "b@example.com"},
+                ],
+                "timestamp": "2024-07-30T16:33:54.000Z",
+            },
+        }));
+        let events = assert_ok!(parse(payload));
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.kind == EmailEventKind::HardBounce));
+        assert_eq!(events[1].recipient, "b@example.com");
+        assert_eq!(events[0].provider_message_id.as_deref(), Some("0000014a"));
+        // One notification, one event per recipient.
+        assert_ne!(events[0].provider_event_id, events[1].provider_event_id);
+    }
+
+    #[test]
+    fn complaints_and_deliveries_of_configuration_sets_are_read() {
+        let complaint = sns(serde_json::json!({
+            "eventType": "Complaint",
+            "mail": {},
+            "complaint": {"complainedRecipients": [{"emailAddress": "a@example.com"}]},
+        }));
+        assert_eq!(
+            assert_ok!(parse(complaint))[0].kind,
+            EmailEventKind::Complaint
+        );
+        let delivery = sns(serde_json::json!({
+            "notificationType": "Delivery",
+            "mail": {},
+            "delivery": {"recipients": ["a@example.com"]},
+        }));
+        assert_eq!(
+            assert_ok!(parse(delivery))[0].kind,
+            EmailEventKind::Delivery
+        );
+    }
+
+    #[test]
+    fn subscription_confirmations_carry_no_event() {
+        let payload = serde_json::json!({
+            "Type": "SubscriptionConfirmation",
+            "TopicArn": "arn:aws:sns:eu-west-1:123456789012:ses-events",
+            "Message": "You have chosen to subscribe to the topic",
+            "SubscribeURL": "https://sns.eu-west-1.amazonaws.com/?Action=ConfirmSubscription",
+        });
+        assert!(assert_ok!(parse(payload)).is_empty());
+    }
+
+    #[test]
+    fn notifications_that_are_not_json_are_rejected() {
+        let mut payload = sns(serde_json::json!({}));
+        payload["Message"] = "not json".into();
+        assert_err!(parse(payload));
+    }
+}
@@ -0,0 +1,337 @@
+mod translations;
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
+use minijinja::{
+    value::Kwargs, AutoEscape, Environment, ErrorKind, Output, State, UndefinedBehavior, Value,
+};
+
+use crate::domain::SubscriberLocale;
+use translations::Translations;
+
+// The emails we know how to write. Each one is made of three templates in the templates
+// directory: `<name>.subject`, `<name>.html` and `<name>.txt`.
+// Besides their own context, every template gets the `locale` it is rendered in and a `t`
+// function translating messages to that locale, see `Translations`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplate {
+    // Context: `name`, `confirmation_link`.
+    Confirmation,
+    // Context: `name`.
+    Welcome,
+    // Wraps the content of an issue. Context: `title`, `html_content` (trusted, to be marked as
+    // safe), `text_content` and, if opens are tracked, `tracking_pixel` (its URL).
+    Newsletter,
+    // Context: `name`.
+    UnsubscribeConfirmation,
+}
+
+impl EmailTemplate {
+    pub const ALL: [EmailTemplate; 4] = [
+        EmailTemplate::Confirmation,
+        EmailTemplate::Welcome,
+        EmailTemplate::Newsletter,
+        EmailTemplate::UnsubscribeConfirmation,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmailTemplate::Confirmation => "confirmation",
+            EmailTemplate::Welcome => "welcome",
+            EmailTemplate::Newsletter => "newsletter",
+            EmailTemplate::UnsubscribeConfirmation => "unsubscribe_confirmation",
+        }
+    }
+}
+
+const VARIANTS: [&str; 3] = ["subject", "html", "txt"];
+
+#[derive(Debug)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+// Every template is loaded and parsed upfront, a missing or broken one is reported when the
+// application starts rather than when the first email goes out.
+// Values (translated messages included) are escaped in the `.html` templates, unless marked as
+// safe.
+#[derive(Clone, Debug)]
+pub struct EmailTemplates {
+    env: Arc<Environment<'static>>,
+}
+
+impl EmailTemplates {
+    pub fn load(
+        directory: impl AsRef<Path>,
+        locales_directory: impl AsRef<Path>,
+    ) -> Result<Self, anyhow::Error> {
+        let directory = directory.as_ref();
+        let translations = Translations::load(locales_directory.as_ref())?;
+        let mut env = Environment::new();
+        // A typo in a variable name should fail loudly, not render as an empty string.
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_formatter(format_value);
+        env.add_function("t", move |state: &State, key: &str, args: Kwargs| {
+            translations.translate(state, key, args)
+        });
+        for template in EmailTemplate::ALL {
+            for variant in VARIANTS {
+                let file_name = format!("{}.{}", template.name(), variant);
+                let path = directory.join(&file_name);
+                let source = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read email template {}", path.display()))?;
+                env.add_template_owned(file_name, source).with_context(|| {
+                    format!("Failed to parse email template {}", path.display())
+                })?;
+            }
+        }
+        Ok(Self { env: Arc::new(env) })
+    }
+
+    pub fn render(
+        &self,
+        template: EmailTemplate,
+        locale: &SubscriberLocale,
+        context: impl serde::Serialize,
+    ) -> Result<RenderedEmail, anyhow::Error> {
+        let context = minijinja::context! {
+            locale => locale.as_ref(),
+            ..Value::from_serialize(&context)
+        };
+        let render = |variant: &str| {
+            let name = format!("{}.{}", template.name(), variant);
+            self.env
+                .get_template(&name)
+                .and_then(|t| t.render(&context))
+                .with_context(|| format!("Failed to render email template {}", name))
+        };
+        Ok(RenderedEmail {
+            // Trailing new lines are the norm in a file, not in a subject.
+            subject: render("subject")?.trim().to_owned(),
+            html_body: render("html")?,
+            text_body: render("txt")?,
+        })
+    }
+}
+
+// The default HTML escaping also escapes `/`, which mangles the links of our emails: only the
+// characters that matter in text and (quoted) attribute values are escaped.
+fn format_value(out: &mut Output, state: &State, value: &Value) -> Result<(), minijinja::Error> {
+    match state.auto_escape() {
+        AutoEscape::Html if !value.is_safe() => {
+            let mut escaped = String::new();
+            for c in value.to_string().chars() {
+                match c {
+                    '&' => escaped.push_str("&amp;"),
+                    '<' => escaped.push_str("&lt;"),
+                    '>' => escaped.push_str("&gt;"),
+                    '"' => escaped.push_str("&quot;"),
+                    '\'' => escaped.push_str("&#x27;"),
+                    c => escaped.push(c),
+                }
+            }
+            out.write_str(&escaped).map_err(|e| {
+                minijinja::Error::new(ErrorKind::WriteFailure, "Failed to write").with_source(e)
+            })
+        }
+        _ => minijinja::escape_formatter(out, state, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailTemplate, EmailTemplates};
+    use crate::domain::SubscriberLocale;
+    use claims::{assert_err, assert_ok};
+    use minijinja::Value;
+    use std::path::PathBuf;
+
+    const TEMPLATES_DIRECTORY: &str = "templates/email";
+    const LOCALES_DIRECTORY: &str = "templates/locales";
+
+    // A copy of the real templates we can break.
+    fn copy_of_the_templates() -> PathBuf {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        for entry in std::fs::read_dir(TEMPLATES_DIRECTORY).unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::copy(&path, directory.join(path.file_name().unwrap())).unwrap();
+        }
+        directory
+    }
+
+    fn templates() -> EmailTemplates {
+        EmailTemplates::load(TEMPLATES_DIRECTORY, LOCALES_DIRECTORY).unwrap()
+    }
+
+    fn locale(locale: &str) -> SubscriberLocale {
+        SubscriberLocale::parse(locale.into()).unwrap()
+    }
+
+    #[test]
+    fn the_shipped_templates_load() {
+        assert_ok!(EmailTemplates::load(TEMPLATES_DIRECTORY, LOCALES_DIRECTORY));
+    }
+
+    #[test]
+    fn a_missing_template_is_rejected() {
+        let directory = copy_of_the_templates();
+        std::fs::remove_file(directory.join("welcome.txt")).unwrap();
+
+        assert_err!(EmailTemplates::load(&directory, LOCALES_DIRECTORY));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_template_that_does_not_parse_is_rejected() {
+        let directory = copy_of_the_templates();
+        std::fs::write(directory.join("newsletter.html"), "{% if title %}").unwrap();
+
+        assert_err!(EmailTemplates::load(&directory, LOCALES_DIRECTORY));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_but_not_in_plain_text() {
+        let email = templates()
+            .render(
+                EmailTemplate::Welcome,
+                &SubscriberLocale::default(),
+                serde_json::json!({ "name": "<b>Ursula</b> & co" }),
+            )
+            .unwrap();
+
+        assert!(email
+            .html_body
+            .contains("&lt;b&gt;Ursula&lt;/b&gt; &amp; co"));
+        assert!(email.text_body.contains("<b>Ursula</b> & co"));
+    }
+
+    #[test]
+    fn links_are_left_usable() {
+        let link = "https://my-newsletter.com/subscriptions/confirm?subscription_token=abc";
+        let email = templates()
+            .render(
+                EmailTemplate::Confirmation,
+                &SubscriberLocale::default(),
+                serde_json::json!({ "name": "Ursula", "confirmation_link": link }),
+            )
+            .unwrap();
+
+        assert!(email.html_body.contains(&format!("href=\"{}\"", link)));
+        assert!(email.text_body.contains(link));
+    }
+
+    #[test]
+    fn safe_values_are_not_escaped() {
+        let email = templates()
+            .render(
+                EmailTemplate::Newsletter,
+                &SubscriberLocale::default(),
+                minijinja::context! {
+                    title => "Issue #1",
+                    html_content => Value::from_safe_string("<p>Hello</p>".into()),
+                    text_content => "Hello",
+                },
+            )
+            .unwrap();
+
+        assert_eq!(email.subject, "Issue #1");
+        assert!(email.html_body.contains("<p>Hello</p>"));
+        assert!(!email.html_body.contains("<img"));
+    }
+
+    #[test]
+    fn the_tracking_pixel_is_only_added_if_there_is_one() {
+        let render = |tracking_pixel: Option<&str>| {
+            templates()
+                .render(
+                    EmailTemplate::Newsletter,
+                    &SubscriberLocale::default(),
+                    minijinja::context! {
+                        title => "Issue #1",
+                        html_content => "Hello",
+                        text_content => "Hello",
+                        tracking_pixel => tracking_pixel,
+                    },
+                )
+                .unwrap()
+                .html_body
+        };
+
+        assert!(render(Some("http://127.0.0.1/t/o/token")).contains("<img src=\""));
+        assert!(!render(None).contains("<img"));
+    }
+
+    #[test]
+    fn rendering_fails_if_a_value_is_missing() {
+        assert_err!(templates().render(
+            EmailTemplate::Welcome,
+            &SubscriberLocale::default(),
+            serde_json::json!({})
+        ));
+    }
+
+    #[test]
+    fn every_template_renders_in_every_shipped_locale() {
+        let templates = templates();
+        let context = minijinja::context! {
+            name => "Ursula",
+            confirmation_link => "https://my-newsletter.com/confirm",
+            title => "Issue #1",
+            html_content => Value::from_safe_string("<p>Hello</p>".into()),
+            text_content => "Hello",
+        };
+        for entry in std::fs::read_dir(LOCALES_DIRECTORY).unwrap() {
+            let path = entry.unwrap().path();
+            let locale = locale(path.file_stem().unwrap().to_str().unwrap());
+            for template in EmailTemplate::ALL {
+                assert_ok!(templates.render(template, &locale, &context));
+            }
+        }
+    }
+
+    #[test]
+    fn emails_are_written_in_the_locale_of_the_subscriber() {
+        let context = minijinja::context! {
+            name => "Ursula",
+            confirmation_link => "https://my-newsletter.com/confirm",
+        };
+        let templates = templates();
+
+        let french = templates
+            .render(EmailTemplate::Confirmation, &locale("fr"), &context)
+            .unwrap();
+        let english = templates
+            .render(EmailTemplate::Confirmation, &locale("en"), &context)
+            .unwrap();
+        assert_eq!(french.subject, "Confirmez votre inscription");
+        assert_ne!(french.text_body, english.text_body);
+        // No catalog for `fr-CA`, but there is one for French.
+        let canadian = templates
+            .render(EmailTemplate::Confirmation, &locale("fr-CA"), &context)
+            .unwrap();
+        assert_eq!(canadian.subject, french.subject);
+    }
+
+    #[test]
+    fn locales_without_a_catalog_fall_back_to_english() {
+        let context = minijinja::context! {
+            name => "Ursula",
+            confirmation_link => "https://my-newsletter.com/confirm",
+        };
+        let templates = templates();
+
+        let german = templates
+            .render(EmailTemplate::Confirmation, &locale("de"), &context)
+            .unwrap();
+        let english = templates
+            .render(EmailTemplate::Confirmation, &locale("en"), &context)
+            .unwrap();
+        assert_eq!(german.subject, english.subject);
+        assert_eq!(german.html_body, english.html_body);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
+use minijinja::{value::Kwargs, ErrorKind, State, Value};
+
+use crate::domain::SubscriberLocale;
+
+// What every other catalog falls back to: it has to translate every message.
+const DEFAULT_LOCALE: &str = "en";
+
+// One catalog per locale, `<locale>.json` in the locales directory: a flat map from message
+// keys to messages, in which `{argument}` is replaced by the argument of the same name.
+// A message missing from the catalog of `pt-BR` is looked up in the one of `pt`, then in the
+// default one.
+#[derive(Debug)]
+pub struct Translations {
+    catalogs: HashMap<String, HashMap<String, String>>,
+}
+
+impl Translations {
+    pub fn load(directory: &Path) -> Result<Self, anyhow::Error> {
+        let mut catalogs = HashMap::new();
+        let entries = std::fs::read_dir(directory).with_context(|| {
+            format!(
+                "Failed to read the locales directory {}",
+                directory.display()
+            )
+        })?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let locale = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .map(|s| SubscriberLocale::parse(s.to_owned()))
+                .with_context(|| format!("Unexpected catalog name {}", path.display()))?
+                .map_err(anyhow::Error::msg)?;
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read catalog {}", path.display()))?;
+            let catalog: HashMap<String, String> = serde_json::from_str(&source)
+                .with_context(|| format!("Failed to parse catalog {}", path.display()))?;
+            catalogs.insert(locale.as_ref().to_owned(), catalog);
+        }
+        let default = catalogs.get(DEFAULT_LOCALE).with_context(|| {
+            format!(
+                "There is no catalog for the default locale ({}) in {}",
+                DEFAULT_LOCALE,
+                directory.display()
+            )
+        })?;
+        // A key nobody falls back to is most likely a typo.
+        for (locale, catalog) in &catalogs {
+            if let Some(key) = catalog.keys().find(|key| !default.contains_key(*key)) {
+                anyhow::bail!(
+                    "The catalog of {} translates {}, which is not in the default catalog",
+                    locale,
+                    key
+                );
+            }
+        }
+        Ok(Self { catalogs })
+    }
+
+    fn lookup(&self, locale: &str, key: &str) -> Option<&str> {
+        let language = locale.split('-').next().unwrap_or(locale);
+        [locale, language, DEFAULT_LOCALE]
+            .into_iter()
+            .find_map(|locale| self.catalogs.get(locale)?.get(key))
+            .map(String::as_str)
+    }
+
+    // The `t` function of the templates, e.g. `{{ t("greeting", name=name) }}`. Translates to
+    // the `locale` of the rendering context.
+    pub fn translate(
+        &self,
+        state: &State,
+        key: &str,
+        args: Kwargs,
+    ) -> Result<String, minijinja::Error> {
+        let locale = state.lookup("locale");
+        let locale = locale
+            .as_ref()
+            .and_then(Value::as_str)
+            .unwrap_or(DEFAULT_LOCALE);
+        let message = self.lookup(locale, key).ok_or_else(|| {
+            minijinja::Error::new(
+                ErrorKind::UndefinedError,
+                format!("There is no message {} to translate", key),
+            )
+        })?;
+        interpolate(message, &args)
+    }
+}
+
+// Every placeholder must have an argument, and (as checked by minijinja) every argument must be
+// used.
+fn interpolate(message: &str, args: &Kwargs) -> Result<String, minijinja::Error> {
+    let mut interpolated = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find('{') {
+        interpolated.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| {
+            minijinja::Error::new(
+                ErrorKind::InvalidOperation,
+                format!("Unclosed placeholder in {}", message),
+            )
+        })? + start;
+        let name = &rest[start + 1..end];
+        let value: Value = args.get(name)?;
+        // Strict mode does not catch undefined values passed as arguments.
+        if value.is_undefined() {
+            return Err(minijinja::Error::new(
+                ErrorKind::UndefinedError,
+                format!("{} is undefined in {}", name, message),
+            ));
+        }
+        interpolated.push_str(&value.to_string());
+        rest = &rest[end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Translations;
+    use claims::{assert_err, assert_some_eq};
+    use std::{collections::HashMap, path::PathBuf};
+
+    fn translations(catalogs: &[(&str, &[(&str, &str)])]) -> Translations {
+        Translations {
+            catalogs: catalogs
+                .iter()
+                .map(|(locale, messages)| {
+                    let messages = messages
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect::<HashMap<_, _>>();
+                    (locale.to_string(), messages)
+                })
+                .collect(),
+        }
+    }
+
+    fn directory_with(files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        for (name, content) in files {
+            std::fs::write(directory.join(name), content).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn lookups_fall_back_to_the_language_then_to_the_default_locale() {
+        let translations = translations(&[
+            ("en", &[("a", "en a"), ("b", "en b"), ("c", "en c")]),
+            ("pt", &[("a", "pt a"), ("b", "pt b")]),
+            ("pt-BR", &[("a", "pt-BR a")]),
+        ]);
+
+        assert_some_eq!(translations.lookup("pt-BR", "a"), "pt-BR a");
+        assert_some_eq!(translations.lookup("pt-BR", "b"), "pt b");
+        assert_some_eq!(translations.lookup("pt-BR", "c"), "en c");
+        assert_some_eq!(translations.lookup("de", "a"), "en a");
+    }
+
+    #[test]
+    fn a_catalog_is_required_for_the_default_locale() {
+        let directory = directory_with(&[("fr.json", r#"{"a": "fr a"}"#)]);
+
+        assert_err!(Translations::load(&directory));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn keys_missing_from_the_default_catalog_are_rejected() {
+        let directory = directory_with(&[
+            ("en.json", r#"{"greeting": "Hi"}"#),
+            ("fr.json", r#"{"greting": "Salut"}"#),
+        ]);
+
+        assert_err!(Translations::load(&directory));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn catalogs_are_indexed_by_normalized_locale() {
+        let directory = directory_with(&[
+            ("en.json", r#"{"greeting": "Hi"}"#),
+            ("pt_br.json", r#"{"greeting": "Oi"}"#),
+        ]);
+
+        let translations = Translations::load(&directory).unwrap();
+        assert_some_eq!(translations.lookup("pt-BR", "greeting"), "Oi");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
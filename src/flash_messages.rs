@@ -0,0 +1,113 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    cookie::{Cookie, CookieJar, Key},
+    dev::Payload,
+    web, FromRequest, HttpRequest,
+};
+use secrecy::ExposeSecret;
+
+use crate::startup::HmacSecret;
+
+const FLASH_COOKIE_NAME: &str = "_flash";
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    Info,
+    Error,
+}
+
+// A one-off message carried over a redirect (e.g. "Authentication failed" after a `POST /login`).
+// It travels in a signed cookie, so it can not be tampered with to inject content into our pages.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct FlashMessage {
+    level: Level,
+    content: String,
+}
+
+impl FlashMessage {
+    pub fn info(content: impl Into<String>) -> Self {
+        Self {
+            level: Level::Info,
+            content: content.into(),
+        }
+    }
+
+    pub fn error(content: impl Into<String>) -> Self {
+        Self {
+            level: Level::Error,
+            content: content.into(),
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    // Build the signed cookie to attach to the outgoing (usually redirect) response.
+    pub fn into_cookie(self, secret: &HmacSecret) -> Cookie<'static> {
+        let value = serde_json::to_string(&[self]).expect("Failed to serialize flash message");
+        let mut jar = CookieJar::new();
+        jar.signed_mut(&signing_key(secret))
+            .add(flash_cookie(value));
+        jar.get(FLASH_COOKIE_NAME)
+            .expect("The flash cookie was just added")
+            .clone()
+    }
+}
+
+// Messages carried by the incoming request. Pages rendering them must attach
+// `IncomingFlashMessages::removal_cookie` to their response so they are shown only once.
+pub struct IncomingFlashMessages(Vec<FlashMessage>);
+
+impl IncomingFlashMessages {
+    pub fn iter(&self) -> impl Iterator<Item = &FlashMessage> {
+        self.0.iter()
+    }
+
+    pub fn removal_cookie() -> Cookie<'static> {
+        let mut cookie = flash_cookie(String::new());
+        cookie.make_removal();
+        cookie
+    }
+}
+
+impl FromRequest for IncomingFlashMessages {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let messages = match (
+            req.cookie(FLASH_COOKIE_NAME),
+            req.app_data::<web::Data<HmacSecret>>(),
+        ) {
+            (Some(cookie), Some(secret)) => {
+                let mut jar = CookieJar::new();
+                jar.add_original(cookie);
+                // A cookie with an invalid signature is ignored, as if it wasn't there.
+                jar.signed(&signing_key(secret))
+                    .get(FLASH_COOKIE_NAME)
+                    .and_then(|cookie| serde_json::from_str(cookie.value()).ok())
+                    .unwrap_or_default()
+            }
+            _ => Vec::new(),
+        };
+        ready(Ok(Self(messages)))
+    }
+}
+
+fn flash_cookie(value: String) -> Cookie<'static> {
+    Cookie::build(FLASH_COOKIE_NAME, value)
+        .path("/")
+        .http_only(true)
+        .finish()
+}
+
+fn signing_key(secret: &HmacSecret) -> Key {
+    Key::from(secret.0.expose_secret().as_bytes())
+}
@@ -0,0 +1,88 @@
+use actix_web::http::header::HeaderMap;
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    // Read the optional `Idempotency-Key` header. A request without it is processed as usual,
+    // a request with an invalid one is rejected.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>, String> {
+        let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+            return Ok(None);
+        };
+        let value = value
+            .to_str()
+            .map_err(|_| format!("The '{}' header is not valid UTF8.", IDEMPOTENCY_KEY_HEADER))?;
+        Self::try_from(value.to_owned()).map(Some)
+    }
+}
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.is_empty() {
+            return Err("The idempotency key cannot be empty".into());
+        }
+        // Keys are stored, bound their size. Long enough for a UUID with some room to spare.
+        let max_length = 50;
+        if s.len() >= max_length {
+            return Err(format!(
+                "The idempotency key must be shorter than {} characters",
+                max_length
+            ));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl From<IdempotencyKey> for String {
+    fn from(k: IdempotencyKey) -> Self {
+        k.0
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use claims::{assert_err, assert_none, assert_ok, assert_some};
+
+    #[test]
+    fn an_empty_key_is_rejected() {
+        assert_err!(IdempotencyKey::try_from("".to_string()));
+    }
+
+    #[test]
+    fn a_key_of_50_characters_or_more_is_rejected() {
+        assert_err!(IdempotencyKey::try_from("a".repeat(50)));
+    }
+
+    #[test]
+    fn a_uuid_is_a_valid_key() {
+        assert_ok!(IdempotencyKey::try_from(uuid::Uuid::new_v4().to_string()));
+    }
+
+    #[test]
+    fn the_header_is_optional() {
+        assert_none!(IdempotencyKey::from_headers(&HeaderMap::new()).unwrap());
+    }
+
+    #[test]
+    fn the_key_is_read_from_the_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("idempotency-key"),
+            HeaderValue::from_static("a-key"),
+        );
+        assert_some!(IdempotencyKey::from_headers(&headers).unwrap());
+    }
+}
@@ -0,0 +1,167 @@
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use sqlx::{
+    postgres::{PgHasArrayType, PgTypeInfo},
+    Executor, PgPool, Postgres, Transaction,
+};
+use uuid::Uuid;
+
+use super::IdempotencyKey;
+
+#[derive(Debug, sqlx::Type)]
+#[sqlx(type_name = "header_pair")]
+struct HeaderPairRecord {
+    name: String,
+    value: Vec<u8>,
+}
+
+// sqlx can not figure out the name of the array type of a composite type on its own.
+impl PgHasArrayType for HeaderPairRecord {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_header_pair")
+    }
+}
+
+struct SavedResponse {
+    request_hash: Option<Vec<u8>>,
+    response: HttpResponse,
+}
+
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> Result<Option<SavedResponse>, anyhow::Error> {
+    let saved_response = sqlx::query!(
+        r#"
+        SELECT
+            request_hash,
+            response_status_code as "response_status_code!",
+            response_headers as "response_headers!: Vec<HeaderPairRecord>",
+            response_body as "response_body!"
+        FROM idempotency
+        WHERE
+            user_id = $1 AND
+            idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(r) = saved_response {
+        let status_code = StatusCode::from_u16(r.response_status_code.try_into()?)?;
+        let mut response = HttpResponse::build(status_code);
+        for HeaderPairRecord { name, value } in r.response_headers {
+            response.append_header((name, value));
+        }
+        Ok(Some(SavedResponse {
+            request_hash: r.request_hash,
+            response: response.body(r.response_body),
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+// Store the response and commit the transaction handed out by `try_processing`.
+// Returns an equivalent response, the original one had its body consumed to be stored.
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> Result<HttpResponse, anyhow::Error> {
+    let (response_head, body) = http_response.into_parts();
+    // `MessageBody::Error` is not `Send` + `Sync`, so it does not play nicely with `anyhow`.
+    let body = to_bytes(body).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+    let status_code = response_head.status().as_u16() as i16;
+    let headers = {
+        let mut h = Vec::with_capacity(response_head.headers().len());
+        for (name, value) in response_head.headers().iter() {
+            let name = name.as_str().to_owned();
+            let value = value.as_bytes().to_owned();
+            h.push(HeaderPairRecord { name, value });
+        }
+        h
+    };
+    transaction
+        .execute(sqlx::query_unchecked!(
+            r#"
+            UPDATE idempotency
+            SET
+                response_status_code = $3,
+                response_headers = $4,
+                response_body = $5
+            WHERE
+                user_id = $1 AND
+                idempotency_key = $2
+            "#,
+            user_id,
+            idempotency_key.as_ref(),
+            status_code,
+            headers,
+            body.as_ref()
+        ))
+        .await?;
+    transaction.commit().await?;
+
+    let http_response = response_head.set_body(body).map_into_boxed_body();
+    Ok(http_response)
+}
+
+// Short-lived and never stored in bulk, boxing the transaction would buy nothing.
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    // Process the request inside the returned transaction, then hand it to `save_response`.
+    StartProcessing(Transaction<'static, Postgres>),
+    ReturnSavedResponse(HttpResponse),
+    // The key was used before for a request with a different hash, see `try_processing`.
+    RejectReusedKey,
+}
+
+// Claim the idempotency key for this request.
+//
+// # Implementation Notes
+//
+// The row is inserted inside a transaction that stays open until `save_response` commits it.
+// A concurrent request with the same key blocks on the `INSERT` until the first one is done:
+// if it committed, `ON CONFLICT DO NOTHING` inserts nothing and the saved response is replayed;
+// if it rolled back (e.g. the handler failed), the second request gets to process it instead.
+//
+// `request_hash` identifies the request on endpoints where the key alone does not, e.g. the
+// anonymous ones: the saved response is only replayed to a request with the same hash.
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    request_hash: Option<&[u8]>,
+) -> Result<NextAction, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO idempotency (
+            user_id,
+            idempotency_key,
+            request_hash,
+            created_at
+        )
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        request_hash
+    );
+    let n_inserted_rows = transaction.execute(query).await?.rows_affected();
+    if n_inserted_rows > 0 {
+        Ok(NextAction::StartProcessing(transaction))
+    } else {
+        let saved_response = get_saved_response(pool, idempotency_key, user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("We expected a saved response, we didn't find it"))?;
+        if request_hash.is_some() && saved_response.request_hash.as_deref() != request_hash {
+            return Ok(NextAction::RejectReusedKey);
+        }
+        Ok(NextAction::ReturnSavedResponse(saved_response.response))
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap},
+    time::Duration,
+};
+
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::{
+        SubscriberEmail, SubscriberLocale, SubscriptionStatus, TrackingLinks, UnsubscribeLinks,
+    },
+    email_client::{EmailClient, SendEmailError},
+    email_templates::EmailTemplates,
+    issue_rendering::{get_issue, IssueRecipient, IssueRenderer},
+    issue_scheduler::mark_sent_if_delivered,
+    startup::get_connection_pool,
+    subscriber_status::update_subscriber_status,
+};
+
+// A delivery failing for a transient reason is attempted again, up to this many times, the
+// n-th time `2^n` minutes later.
+const MAX_DELIVERY_RETRIES: i16 = 5;
+
+// How many deliveries are taken off the queue, and sent, at once. Providers with a batch API
+// get them in as few requests as they accept (up to 500 emails per request for Postmark).
+const BATCH_SIZE: i64 = 100;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: EmailClient,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_templates = configuration.email_templates.load()?;
+    let unsubscribe_links = configuration.application.unsubscribe_links();
+    let tracking_links = configuration.application.tracking_links();
+    worker_loop(
+        connection_pool,
+        email_client,
+        unsubscribe_links,
+        tracking_links,
+        email_templates,
+    )
+    .await
+}
+
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    unsubscribe_links: UnsubscribeLinks,
+    tracking_links: TrackingLinks,
+    email_templates: EmailTemplates,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match try_execute_task(
+            &pool,
+            &email_client,
+            &unsubscribe_links,
+            &tracking_links,
+            &email_templates,
+        )
+        .await
+        {
+            // Nothing to do, poll again later instead of hammering the database.
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(10)).await,
+            // Most likely a transient database error, back off for a bit.
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+// Sends up to `BATCH_SIZE` deliveries, in as few requests to the email provider as it allows.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    unsubscribe_links: &UnsubscribeLinks,
+    tracking_links: &TrackingLinks,
+    email_templates: &EmailTemplates,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, tasks) = dequeue_tasks(pool).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+    // `None` for issues that were deleted in the meantime.
+    let mut issues = HashMap::new();
+    // The tasks an email goes out for, along with their subscriber. The others are done with.
+    let mut deliveries = vec![];
+    let mut messages = vec![];
+    for task in &tasks {
+        let issue_id = task.newsletter_issue_id;
+        let Some((recipient, list_id)) = get_recipient(pool, task).await? else {
+            delete_task(&mut transaction, task).await?;
+            continue;
+        };
+        if let Entry::Vacant(entry) = issues.entry(issue_id) {
+            entry.insert(get_issue(pool, issue_id).await?);
+        }
+        let Some(issue) = &issues[&issue_id] else {
+            tracing::error!(
+                newsletter_issue_id = %issue_id,
+                "Skipping a delivery of a newsletter issue that does not exist",
+            );
+            delete_task(&mut transaction, task).await?;
+            continue;
+        };
+        // The other deliveries of the batch go on, this one would fail the same way next time.
+        let message = match IssueRenderer::new(email_templates, unsubscribe_links, tracking_links)
+            .render(issue_id, issue, &recipient, list_id)
+        {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Failed to render a newsletter issue. Skipping.",
+                );
+                delete_task(&mut transaction, task).await?;
+                continue;
+            }
+        };
+        deliveries.push((task.clone(), recipient.subscriber_id));
+        messages.push(message);
+    }
+    let outcomes = email_client.send_all(messages).await;
+    for ((task, subscriber_id), outcome) in deliveries.into_iter().zip(outcomes) {
+        match outcome {
+            Ok(()) => delete_task(&mut transaction, &task).await?,
+            Err(e) => {
+                handle_delivery_failure(pool, &mut transaction, subscriber_id, &task, e).await?
+            }
+        }
+    }
+    let issue_ids: BTreeSet<Uuid> = tasks.iter().map(|t| t.newsletter_issue_id).collect();
+    for issue_id in issue_ids {
+        mark_sent_if_delivered(&mut transaction, issue_id).await?;
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// Along with the list the issue reaches them through. `None` if there is nothing to send: the
+// subscriber might have left, or left the lists of the issue, since it was published.
+async fn get_recipient(
+    pool: &PgPool,
+    task: &DeliveryTask,
+) -> Result<Option<(IssueRecipient, Uuid)>, anyhow::Error> {
+    let issue_id = task.newsletter_issue_id;
+    let Some(ConfirmedSubscriber {
+        id: subscriber_id,
+        list_id,
+        name,
+        locale,
+    }) = get_confirmed_subscriber(pool, issue_id, &task.subscriber_email).await?
+    else {
+        tracing::info!(
+            newsletter_issue_id = %issue_id,
+            subscriber_email = %task.subscriber_email,
+            "Skipping a subscriber who is no longer confirmed"
+        );
+        return Ok(None);
+    };
+    match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => Ok(Some((
+            IssueRecipient {
+                subscriber_id,
+                email,
+                name,
+                // Stored locales were validated on the way in, this is only a safety net.
+                locale: SubscriberLocale::parse(locale).unwrap_or_default(),
+            },
+            list_id,
+        ))),
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid",
+            );
+            Ok(None)
+        }
+    }
+}
+
+// The email client already retried what could be retried right away, what is left is either
+// rescheduled for later, a recipient that bounced, or dropped.
+async fn handle_delivery_failure(
+    pool: &PgPool,
+    transaction: &mut PgTransaction,
+    subscriber_id: Uuid,
+    task: &DeliveryTask,
+    e: SendEmailError,
+) -> Result<(), anyhow::Error> {
+    if e.is_undeliverable_recipient() {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            subscriber_email = %task.subscriber_email,
+            "The recipient is undeliverable, marking the subscriber as bounced",
+        );
+        // The rest of the batch went out already: failing here would roll back their tasks,
+        // and send them again.
+        if let Err(e) =
+            update_subscriber_status(pool, subscriber_id, SubscriptionStatus::Bounced).await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                subscriber_email = %task.subscriber_email,
+                "Failed to mark the subscriber as bounced",
+            );
+        }
+    } else if e.is_retryable() && task.n_retries < MAX_DELIVERY_RETRIES {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            newsletter_issue_id = %task.newsletter_issue_id,
+            subscriber_email = %task.subscriber_email,
+            n_retries = task.n_retries,
+            "Failed to deliver issue to a confirmed subscriber. Retrying later.",
+        );
+        return reschedule_task(transaction, task, task.n_retries + 1).await;
+    } else {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            newsletter_issue_id = %task.newsletter_issue_id,
+            subscriber_email = %task.subscriber_email,
+            "Failed to deliver issue to a confirmed subscriber. \
+            Skipping.",
+        );
+    }
+    delete_task(transaction, task).await
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+#[derive(Clone)]
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+// The returned transaction holds a row-level lock on the tasks until they are deleted.
+// `SKIP LOCKED` makes concurrent workers pick different rows instead of waiting on these, so
+// each task is only ever processed by a single worker.
+#[tracing::instrument(skip_all)]
+async fn dequeue_tasks(pool: &PgPool) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        BATCH_SIZE
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok((transaction, tasks))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    n_retries: i16,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = $3,
+            execute_after = now() + make_interval(mins => $4)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_retries,
+        2i32.pow(n_retries as u32)
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    list_id: Uuid,
+    name: String,
+    locale: String,
+}
+
+// Somebody on several of the lists of the issue gets it through the one they joined first,
+// that's the list its unsubscribe link is for.
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        ConfirmedSubscriber,
+        r#"
+        SELECT s.id, m.list_id, s.name, s.locale
+        FROM subscriptions s
+        JOIN LATERAL (
+            SELECT m.list_id
+            FROM list_memberships m
+            JOIN newsletter_issue_lists l ON l.list_id = m.list_id
+            WHERE
+                m.subscriber_id = s.id AND
+                m.status = $2 AND
+                l.newsletter_issue_id = $3
+            ORDER BY m.subscribed_at, m.list_id
+            LIMIT 1
+        ) m ON TRUE
+        WHERE
+            s.email = $1 AND
+            s.status = $2
+        "#,
+        email,
+        SubscriptionStatus::Confirmed.as_str(),
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber)
+}
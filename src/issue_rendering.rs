@@ -0,0 +1,179 @@
+use anyhow::Context;
+use minijinja::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::{SubscriberEmail, SubscriberLocale, TrackedDelivery, TrackingLinks, UnsubscribeLinks},
+    email_client::EmailMessage,
+    email_templates::{EmailTemplate, EmailTemplates},
+};
+
+// Written in an issue, replaced by the name of each subscriber, e.g. `Hi {{ name }},`.
+const NAME_PLACEHOLDERS: [&str; 2] = ["{{ name }}", "{{name}}"];
+
+pub struct NewsletterIssue {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub tracking_enabled: bool,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content, tracking_enabled
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue")?;
+    Ok(issue)
+}
+
+// Who an issue is rendered for.
+pub struct IssueRecipient {
+    pub subscriber_id: Uuid,
+    pub email: SubscriberEmail,
+    pub name: String,
+    pub locale: SubscriberLocale,
+}
+
+// Turns an issue into the email a subscriber gets: their name filled in, their unsubscribe
+// link and, unless turned off, tracked links and pixel. Previews and test copies go through
+// it too, so that editors see what is sent.
+pub struct IssueRenderer<'a> {
+    email_templates: &'a EmailTemplates,
+    unsubscribe_links: &'a UnsubscribeLinks,
+    tracking_links: &'a TrackingLinks,
+}
+
+impl<'a> IssueRenderer<'a> {
+    pub fn new(
+        email_templates: &'a EmailTemplates,
+        unsubscribe_links: &'a UnsubscribeLinks,
+        tracking_links: &'a TrackingLinks,
+    ) -> Self {
+        Self {
+            email_templates,
+            unsubscribe_links,
+            tracking_links,
+        }
+    }
+
+    // The email delivered to `recipient`, who gets the issue through `list_id`: the list their
+    // unsubscribe link is for.
+    pub fn render(
+        &self,
+        issue_id: Uuid,
+        issue: &NewsletterIssue,
+        recipient: &IssueRecipient,
+        list_id: Uuid,
+    ) -> Result<EmailMessage, anyhow::Error> {
+        self.render_content(issue_id, issue, recipient, list_id, false)
+    }
+
+    // The same email, for previews and test copies: its links are preview links, they lead to
+    // the same places but record no open or click and unsubscribe nobody.
+    pub fn render_sample(
+        &self,
+        issue_id: Uuid,
+        issue: &NewsletterIssue,
+        recipient: &IssueRecipient,
+        list_id: Uuid,
+    ) -> Result<EmailMessage, anyhow::Error> {
+        self.render_content(issue_id, issue, recipient, list_id, true)
+    }
+
+    fn render_content(
+        &self,
+        issue_id: Uuid,
+        issue: &NewsletterIssue,
+        recipient: &IssueRecipient,
+        list_id: Uuid,
+        preview: bool,
+    ) -> Result<EmailMessage, anyhow::Error> {
+        let html_content = substitute_name(
+            &issue.html_content,
+            &htmlescape::encode_minimal(&recipient.name),
+        );
+        let text_content = substitute_name(&issue.text_content, &recipient.name);
+        let tracked = issue.tracking_enabled && self.tracking_links.is_enabled();
+        let (html_content, tracking_pixel) = if tracked {
+            let delivery = TrackedDelivery {
+                newsletter_issue_id: issue_id,
+                subscriber_id: recipient.subscriber_id,
+                preview,
+            };
+            (
+                self.tracking_links.track_links(&html_content, delivery),
+                Some(self.tracking_links.open_link(delivery)),
+            )
+        } else {
+            (html_content, None)
+        };
+        let email = self.email_templates.render(
+            EmailTemplate::Newsletter,
+            &recipient.locale,
+            minijinja::context! {
+                title => issue.title,
+                // Written by an author of the newsletter.
+                html_content => Value::from_safe_string(html_content),
+                text_content => text_content,
+                tracking_pixel => tracking_pixel,
+            },
+        )?;
+        let unsubscribe_link = if preview {
+            self.unsubscribe_links
+                .preview_link_for(recipient.subscriber_id, list_id)
+        } else {
+            self.unsubscribe_links
+                .link_for(recipient.subscriber_id, list_id)
+        };
+        Ok(EmailMessage::new(
+            &recipient.email,
+            email.subject,
+            email.html_body,
+            email.text_body,
+        )
+        .unsubscribe_link(unsubscribe_link)
+        .tag(format!("newsletter-{}", issue_id))
+        .metadata("newsletter_issue_id", issue_id.to_string())
+        .metadata("subscriber_id", recipient.subscriber_id.to_string()))
+    }
+}
+
+pub fn substitute_name(content: &str, name: &str) -> String {
+    NAME_PLACEHOLDERS
+        .iter()
+        .fold(content.to_owned(), |content, placeholder| {
+            content.replace(placeholder, name)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::substitute_name;
+
+    #[test]
+    fn both_spellings_of_the_placeholder_are_replaced() {
+        assert_eq!(
+            substitute_name("Hi {{ name }}! Bye {{name}}.", "Ursula"),
+            "Hi Ursula! Bye Ursula."
+        );
+    }
+
+    #[test]
+    fn content_without_placeholder_is_left_as_is() {
+        let content = "Hi {{ title }}, {name} and {{ names }}";
+        assert_eq!(substitute_name(content, "Ursula"), content);
+    }
+}
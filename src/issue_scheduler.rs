@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{Acquire, PgConnection, PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::{IllegalIssueTransition, IssueStatus, Segment},
+    segments::push_audience,
+    startup::get_connection_pool,
+    util::error_chain_fmt,
+};
+
+// How often the scheduler looks for issues due to be sent.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error)]
+pub enum IssueUpdateError {
+    #[error("There is no newsletter issue with id {0}")]
+    UnknownIssue(Uuid),
+    #[error(transparent)]
+    IllegalTransition(#[from] IllegalIssueTransition),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for IssueUpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// Move an issue to `next`, as long as `IssueStatus` allows it, and return the status it had.
+// The row stays locked until the transaction ends, so that whatever goes with the new status
+// (e.g. queuing the deliveries) is done once.
+#[tracing::instrument(name = "Update newsletter issue status", skip(transaction))]
+pub async fn update_issue_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    next: IssueStatus,
+) -> Result<IssueStatus, IssueUpdateError> {
+    let current = sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1 FOR UPDATE",
+        issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to read the newsletter issue status")?
+    .ok_or(IssueUpdateError::UnknownIssue(issue_id))?;
+    let current = IssueStatus::try_from(current.status).map_err(anyhow::Error::msg)?;
+    current.transition_to(next)?;
+    sqlx::query!(
+        "UPDATE newsletter_issues SET status = $1 WHERE newsletter_issue_id = $2",
+        next.as_str(),
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to update the newsletter issue status")?;
+    Ok(current)
+}
+
+// Publish the issue: one delivery task is enqueued per subscriber confirmed on any of its
+// lists and in its segment, if it has one (see `push_audience`). The actual sending is
+// performed by `issue_delivery_worker`.
+#[tracing::instrument(name = "Start the delivery of a newsletter issue", skip(transaction))]
+pub async fn start_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<(), IssueUpdateError> {
+    update_issue_status(transaction, issue_id, IssueStatus::Sending).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = now(), scheduled_for = NULL
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the publication of the newsletter issue")?;
+    let audience = sqlx::query!(
+        r#"
+        SELECT
+            segment::text AS segment,
+            ARRAY(
+                SELECT list_id FROM newsletter_issue_lists l
+                WHERE l.newsletter_issue_id = i.newsletter_issue_id
+            ) AS "list_ids!"
+        FROM newsletter_issues i
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("Failed to read the audience of the newsletter issue")?;
+    let segment = audience
+        .segment
+        .map(|segment| {
+            let segment = serde_json::from_str(&segment)?;
+            Segment::parse(&segment).map_err(anyhow::Error::msg)
+        })
+        .transpose()
+        .context("Failed to parse the segment of the newsletter issue")?;
+    let mut query = QueryBuilder::new(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) SELECT ",
+    );
+    query.push_bind(issue_id).push(", s.email");
+    push_audience(&mut query, &audience.list_ids, segment.as_ref());
+    query
+        .build()
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    // Nobody to send it to.
+    mark_sent_if_delivered(transaction, issue_id).await?;
+    Ok(())
+}
+
+// An issue is sent once none of its deliveries is left in the queue.
+#[tracing::instrument(skip(connection))]
+pub async fn mark_sent_if_delivered(
+    connection: &mut PgConnection,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = $2
+        WHERE
+            i.newsletter_issue_id = $1 AND
+            i.status = $3 AND
+            NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            )
+        "#,
+        issue_id,
+        IssueStatus::Sent.as_str(),
+        IssueStatus::Sending.as_str(),
+    )
+    .execute(connection)
+    .await
+    .context("Failed to mark the newsletter issue as sent")?;
+    Ok(())
+}
+
+pub async fn run_scheduler_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(connection_pool).await
+}
+
+async fn scheduler_loop(pool: PgPool) -> Result<(), anyhow::Error> {
+    loop {
+        // Failures are logged by `publish_due_issues`, we'll catch up on the next run.
+        let _ = publish_due_issues(&pool).await;
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// Start sending the scheduled issues whose time has come, returns how many did.
+// Issues are picked with `SKIP LOCKED`: running it from several instances at once is fine.
+// An issue that cannot be sent goes back to the drafts, for an editor to look at, instead of
+// blocking the ones due after it.
+#[tracing::instrument(skip_all, err)]
+pub async fn publish_due_issues(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let mut n_published = 0;
+    loop {
+        let mut transaction = pool
+            .begin()
+            .await
+            .context("Failed to acquire Postgres connection from the pool")?;
+        let Some(issue) = sqlx::query!(
+            r#"
+            SELECT newsletter_issue_id
+            FROM newsletter_issues
+            WHERE status = $1 AND scheduled_for <= now()
+            ORDER BY scheduled_for
+            FOR UPDATE
+            SKIP LOCKED
+            LIMIT 1
+            "#,
+            IssueStatus::Scheduled.as_str()
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("Failed to look for scheduled newsletter issues")?
+        else {
+            break;
+        };
+        let issue_id = issue.newsletter_issue_id;
+        // In a savepoint, so that a failure leaves the issue locked by `transaction`.
+        let mut attempt = transaction
+            .begin()
+            .await
+            .context("Failed to create a savepoint to publish a scheduled issue")?;
+        match start_delivery(&mut attempt, issue_id).await {
+            Ok(()) => {
+                attempt
+                    .commit()
+                    .await
+                    .context("Failed to release the savepoint of a scheduled issue")?;
+                tracing::info!(
+                    newsletter_issue_id = %issue_id,
+                    "Started sending a scheduled newsletter issue"
+                );
+                n_published += 1;
+            }
+            Err(e) => {
+                attempt
+                    .rollback()
+                    .await
+                    .context("Failed to roll back to the savepoint of a scheduled issue")?;
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %issue_id,
+                    "Failed to start sending a scheduled newsletter issue, moved it back to drafts"
+                );
+                move_back_to_drafts(&mut transaction, issue_id).await?;
+            }
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to publish a scheduled issue")?;
+    }
+    // Workers finishing the last deliveries of an issue at the same time may each see the
+    // other's task still queued, and leave it `sending`.
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = $1
+        WHERE
+            i.status = $2 AND
+            NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            )
+        "#,
+        IssueStatus::Sent.as_str(),
+        IssueStatus::Sending.as_str(),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to mark delivered newsletter issues as sent")?;
+    Ok(n_published)
+}
+
+// Like unscheduling it.
+async fn move_back_to_drafts(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    update_issue_status(transaction, issue_id, IssueStatus::Draft)
+        .await
+        .context("Failed to move a scheduled newsletter issue back to drafts")?;
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_for = NULL WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to unschedule a newsletter issue")?;
+    Ok(())
+}
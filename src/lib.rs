@@ -1,7 +1,22 @@
+pub mod authentication;
+pub mod cleanup_worker;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_events;
+pub mod email_templates;
+pub mod flash_messages;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_rendering;
+pub mod issue_scheduler;
+pub mod lists;
 pub mod routes;
+pub mod segments;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod subscriber_status;
 pub mod telemetry;
+pub mod transactional_email_worker;
 pub mod util;
@@ -0,0 +1,46 @@
+use sqlx::{PgConnection, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::{ListSlug, SubscriptionStatus};
+
+// The list named `slug`, or the default list when there is no slug.
+#[tracing::instrument(skip(connection))]
+pub async fn get_list_id(
+    connection: &mut PgConnection,
+    slug: Option<&ListSlug>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let list = sqlx::query!(
+        r#"
+        SELECT list_id
+        FROM lists
+        WHERE CASE WHEN $1::text IS NULL THEN is_default ELSE slug = $1 END
+        "#,
+        slug.map(|slug| slug.as_ref())
+    )
+    .fetch_optional(connection)
+    .await?;
+    Ok(list.map(|list| list.list_id))
+}
+
+// Adds the subscriber to the list, or moves them back to `status` if they were on it already.
+#[tracing::instrument(skip(transaction))]
+pub async fn upsert_list_membership(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    status: SubscriptionStatus,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, status)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (list_id, subscriber_id) DO UPDATE SET status = EXCLUDED.status
+        "#,
+        list_id,
+        subscriber_id,
+        status.as_str()
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -1,20 +1,72 @@
+use std::fmt::{Debug, Display};
+
+use tokio::task::JoinError;
 use zero2prod::{
+    cleanup_worker::run_cleanup_until_stopped,
     configuration::get_configuration,
+    issue_delivery_worker::run_worker_until_stopped,
+    issue_scheduler::run_scheduler_until_stopped,
     startup::Application,
     telemetry::{get_subscriber, init_subscriber},
+    transactional_email_worker,
 };
 
 // Procedural macro which initializes an async runtime that block on (drives) HttpServer::run
 // returned futures to completion.
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
+async fn main() -> anyhow::Result<()> {
     //  NOTE: Configure tracing
     let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
     init_subscriber(subscriber);
 
     let configuration = get_configuration().expect("Failed to read configuration.");
 
-    let server = Application::build(configuration).await?;
-    server.run_until_stopped().await?;
+    // A single email client, its rate limits apply to every email the process sends.
+    let email_client = configuration.email_client.clone().client();
+    let application = Application::build(configuration.clone(), email_client.clone()).await?;
+    // The API and the background tasks run side by side as separate tasks, the process
+    // shuts down as soon as any of them exits.
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(
+        configuration.clone(),
+        email_client.clone(),
+    ));
+    let transactional_email_task = tokio::spawn(
+        transactional_email_worker::run_worker_until_stopped(configuration.clone(), email_client),
+    );
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration.clone()));
+    let cleanup_task = tokio::spawn(run_cleanup_until_stopped(configuration));
+
+    tokio::select! {
+        o = application_task => report_exit("API", o),
+        o = worker_task => report_exit("Background worker", o),
+        o = transactional_email_task => report_exit("Transactional email worker", o),
+        o = scheduler_task => report_exit("Issue scheduler", o),
+        o = cleanup_task => report_exit("Cleanup task", o),
+    };
     Ok(())
 }
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    dev::Payload,
+    http::{header::ContentType, StatusCode},
+    web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::{SubscriberEmail, SubscriberLocale, TrackingLinks, UnsubscribeLinks},
+    email_client::EmailClient,
+    email_templates::EmailTemplates,
+    issue_rendering::{get_issue, IssueRecipient, IssueRenderer, NewsletterIssue},
+    util::error_chain_fmt,
+};
+
+// Test copies go to a handful of people, not to a list.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+#[derive(thiserror::Error)]
+pub enum IssuePreviewError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no newsletter issue with id {0}")]
+    UnknownIssue(Uuid),
+    #[error("There is no subscriber {0}")]
+    UnknownSubscriber(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for IssuePreviewError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for IssuePreviewError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IssuePreviewError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            IssuePreviewError::UnknownIssue(_) | IssuePreviewError::UnknownSubscriber(_) => {
+                StatusCode::NOT_FOUND
+            }
+            IssuePreviewError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewParameters {
+    // The id or the email address of the subscriber.
+    subscriber: String,
+}
+
+// The HTML body of the issue as `subscriber` gets it: with their name, their unsubscribe link
+// and the tracked links and pixel, unless tracking is off. They are all preview links, opening
+// the preview or following its links records nothing and unsubscribes nobody.
+#[tracing::instrument(
+    name = "Preview a newsletter issue",
+    skip(parameters, pool, renderer_data),
+    fields(subscriber = %parameters.subscriber)
+)]
+pub async fn preview_issue(
+    _user_id: UserId,
+    issue_id: web::Path<Uuid>,
+    parameters: web::Query<PreviewParameters>,
+    pool: web::Data<PgPool>,
+    renderer_data: RendererData,
+) -> Result<HttpResponse, IssuePreviewError> {
+    let issue_id = issue_id.into_inner();
+    let issue = get_existing_issue(&pool, issue_id).await?;
+    let (recipient, list_id) = get_recipient(&pool, issue_id, &parameters.subscriber).await?;
+    let message = renderer_data
+        .renderer()
+        .render_sample(issue_id, &issue, &recipient, list_id)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(message.html_body()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendData {
+    // Seed addresses the test copies are sent to.
+    addresses: Vec<String>,
+    // The id or the email address of the subscriber the copies are rendered for.
+    subscriber: String,
+}
+
+// What happened to the test copy sent to `address`.
+#[derive(serde::Serialize)]
+pub struct TestSendOutcome {
+    address: String,
+    sent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Send test copies of the issue, rendered for `subscriber`, to the seed `addresses`. Like
+// previews, their links record nothing and must not unsubscribe `subscriber`. They are tagged
+// `newsletter-test`, so that they are left out of the statistics of the issue.
+//
+// Every address gets its copy whatever happens to the others, the outcome of each one is
+// returned: a 200 if they were all sent, a 502 otherwise.
+#[tracing::instrument(
+    name = "Send test copies of a newsletter issue",
+    skip(body, pool, email_client, renderer_data)
+)]
+pub async fn test_send_issue(
+    _user_id: UserId,
+    issue_id: web::Path<Uuid>,
+    body: web::Json<TestSendData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    renderer_data: RendererData,
+) -> Result<HttpResponse, IssuePreviewError> {
+    let issue_id = issue_id.into_inner();
+    let TestSendData {
+        addresses,
+        subscriber,
+    } = body.into_inner();
+    if addresses.is_empty() || addresses.len() > MAX_TEST_RECIPIENTS {
+        return Err(IssuePreviewError::ValidationError(format!(
+            "Test copies are sent to 1 to {} addresses",
+            MAX_TEST_RECIPIENTS
+        )));
+    }
+    let addresses = addresses
+        .into_iter()
+        .map(SubscriberEmail::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(IssuePreviewError::ValidationError)?;
+    let issue = get_existing_issue(&pool, issue_id).await?;
+    let (mut recipient, list_id) = get_recipient(&pool, issue_id, &subscriber).await?;
+    let renderer = renderer_data.renderer();
+    let mut messages = Vec::with_capacity(addresses.len());
+    for address in &addresses {
+        recipient.email = address.clone();
+        messages.push(
+            renderer
+                .render_sample(issue_id, &issue, &recipient, list_id)?
+                .tag("newsletter-test"),
+        );
+    }
+    let outcomes: Vec<_> = addresses
+        .iter()
+        .zip(email_client.send_all(messages).await)
+        .map(|(address, outcome)| match outcome {
+            Ok(()) => TestSendOutcome {
+                address: address.as_ref().to_owned(),
+                sent: true,
+                error: None,
+            },
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    address = address.as_ref(),
+                    "Failed to send a test copy of the newsletter issue"
+                );
+                TestSendOutcome {
+                    address: address.as_ref().to_owned(),
+                    sent: false,
+                    error: Some(e.to_string()),
+                }
+            }
+        })
+        .collect();
+    let mut response = if outcomes.iter().all(|outcome| outcome.sent) {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::BadGateway()
+    };
+    Ok(response.json(outcomes))
+}
+
+// What `IssueRenderer` is built from, as registered on the application.
+pub struct RendererData {
+    email_templates: web::Data<EmailTemplates>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+    tracking_links: web::Data<TrackingLinks>,
+}
+
+impl RendererData {
+    fn renderer(&self) -> IssueRenderer<'_> {
+        IssueRenderer::new(
+            &self.email_templates,
+            &self.unsubscribe_links,
+            &self.tracking_links,
+        )
+    }
+}
+
+// Extracted like any `web::Data`, fails the same way if one of them is not registered.
+impl FromRequest for RendererData {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let extract = || -> Result<Self, Self::Error> {
+            Ok(Self {
+                email_templates: web::Data::extract(req).into_inner()?,
+                unsubscribe_links: web::Data::extract(req).into_inner()?,
+                tracking_links: web::Data::extract(req).into_inner()?,
+            })
+        };
+        ready(extract())
+    }
+}
+
+async fn get_existing_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<NewsletterIssue, IssuePreviewError> {
+    get_issue(pool, issue_id)
+        .await?
+        .ok_or(IssuePreviewError::UnknownIssue(issue_id))
+}
+
+// Subscribers are picked whatever their status, editors may preview as anyone. Their
+// unsubscribe link is for the list of the issue they joined first, like for a delivery, or for
+// the first list of the issue if they are on none of them.
+#[tracing::instrument(skip(pool))]
+async fn get_recipient(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber: &str,
+) -> Result<(IssueRecipient, Uuid), IssuePreviewError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT s.id, s.email, s.name, s.locale, l.list_id
+        FROM subscriptions s
+        JOIN LATERAL (
+            SELECT l.list_id
+            FROM newsletter_issue_lists l
+            LEFT JOIN list_memberships m
+                ON m.list_id = l.list_id AND m.subscriber_id = s.id
+            WHERE l.newsletter_issue_id = $2
+            ORDER BY m.subscribed_at NULLS LAST, l.list_id
+            LIMIT 1
+        ) l ON TRUE
+        WHERE s.email = $1 OR s.id::text = $1
+        "#,
+        subscriber,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber")?
+    .ok_or_else(|| IssuePreviewError::UnknownSubscriber(subscriber.to_owned()))?;
+    let recipient = IssueRecipient {
+        subscriber_id: row.id,
+        email: SubscriberEmail::parse(row.email).map_err(anyhow::Error::msg)?,
+        name: row.name,
+        locale: SubscriberLocale::parse(row.locale).unwrap_or_default(),
+    };
+    Ok((recipient, row.list_id))
+}
@@ -0,0 +1,18 @@
+use actix_web::{http::header::LOCATION, web, HttpResponse};
+
+use crate::{
+    authentication::UserId, flash_messages::FlashMessage, session_state::TypedSession,
+    startup::HmacSecret,
+};
+
+pub async fn log_out(
+    _user_id: UserId,
+    session: TypedSession,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    session.log_out();
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(FlashMessage::info("You have successfully logged out.").into_cookie(&hmac_secret))
+        .finish()
+}
@@ -0,0 +1,9 @@
+mod dashboard;
+mod issues;
+mod logout;
+mod password;
+
+pub use dashboard::admin_dashboard;
+pub use issues::*;
+pub use logout::log_out;
+pub use password::*;
@@ -0,0 +1,118 @@
+use actix_web::{error::InternalError, http::header::LOCATION, web, HttpResponse};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    authentication::{self, validate_credentials, AuthError, Credentials, UserId},
+    flash_messages::FlashMessage,
+    routes::admin::dashboard::get_username,
+    startup::HmacSecret,
+    util::error_chain_fmt,
+};
+
+// Bounds recommended by OWASP, counted in graphemes like `SubscriberName`.
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ChangePasswordError {
+    #[error("You entered two different new passwords - the field values must match.")]
+    PasswordMismatch,
+    #[error(
+        "The new password must be between {} and {} characters long.",
+        MIN_PASSWORD_LENGTH,
+        MAX_PASSWORD_LENGTH
+    )]
+    InvalidLength,
+    #[error("The current password is incorrect.")]
+    InvalidCurrentPassword(#[source] anyhow::Error),
+    #[error("The new password must be different from the current one.")]
+    PasswordReused,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ChangePasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(name = "Change password", skip_all, fields(user_id = %*user_id))]
+pub async fn change_password(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: UserId,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, InternalError<ChangePasswordError>> {
+    try_change_password(form.0, &pool, user_id)
+        .await
+        .map_err(|e| change_password_redirect(e, &hmac_secret))?;
+    Ok(password_redirect(
+        FlashMessage::info("Your password has been changed."),
+        &hmac_secret,
+    ))
+}
+
+async fn try_change_password(
+    form: FormData,
+    pool: &PgPool,
+    user_id: UserId,
+) -> Result<(), ChangePasswordError> {
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        return Err(ChangePasswordError::PasswordMismatch);
+    }
+    let new_password_length = form.new_password.expose_secret().graphemes(true).count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&new_password_length) {
+        return Err(ChangePasswordError::InvalidLength);
+    }
+
+    let username = get_username(*user_id, pool).await?;
+    let credentials = Credentials {
+        username,
+        password: form.current_password.clone(),
+    };
+    validate_credentials(credentials, pool)
+        .await
+        .map_err(|e| match e {
+            AuthError::InvalidCredentials(_) => {
+                ChangePasswordError::InvalidCurrentPassword(e.into())
+            }
+            AuthError::UnexpectedError(_) => ChangePasswordError::UnexpectedError(e.into()),
+        })?;
+    // Only checked once we know `current_password` is right, otherwise this would leak
+    // information about the current password.
+    if form.new_password.expose_secret() == form.current_password.expose_secret() {
+        return Err(ChangePasswordError::PasswordReused);
+    }
+
+    authentication::change_password(*user_id, form.new_password, pool).await?;
+    Ok(())
+}
+
+// Every expected failure is shown to the user on the form, unexpected ones are a 500.
+fn change_password_redirect(
+    e: ChangePasswordError,
+    hmac_secret: &HmacSecret,
+) -> InternalError<ChangePasswordError> {
+    let response = match e {
+        ChangePasswordError::UnexpectedError(_) => HttpResponse::InternalServerError().finish(),
+        _ => password_redirect(FlashMessage::error(e.to_string()), hmac_secret),
+    };
+    InternalError::from_response(e, response)
+}
+
+fn password_redirect(message: FlashMessage, hmac_secret: &HmacSecret) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/admin/password"))
+        .cookie(message.into_cookie(hmac_secret))
+        .finish()
+}
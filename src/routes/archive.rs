@@ -0,0 +1,124 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::{domain::IssueStatus, issue_rendering::substitute_name, util::e500};
+
+// What the name placeholders of an issue read in the archive, where nobody in particular is
+// reading it.
+const ARCHIVE_READER_NAME: &str = "reader";
+
+// Public list of the issues sent so far, newest first.
+pub async fn archive(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let issues = get_sent_issues(&pool).await.map_err(e500)?;
+    let mut items = String::new();
+    for issue in issues {
+        items.push_str(&format!(
+            r#"        <li><a href="/archive/{}">{}</a> ({})</li>
+"#,
+            htmlescape::encode_minimal(&issue.slug),
+            htmlescape::encode_minimal(&issue.title),
+            issue
+                .published_at
+                .map(|t| t.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        ));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter archive</title>
+</head>
+<body>
+    <h1>Newsletter archive</h1>
+    <ul>
+{}    </ul>
+</body>
+</html>"#,
+            items
+        )))
+}
+
+// The HTML of a sent issue. Drafts, scheduled and cancelled issues are not public, they are a
+// 404 like unknown slugs.
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(issue) = get_sent_issue(&slug, &pool).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{}</title>
+</head>
+<body>
+    <p><a href="/archive">Newsletter archive</a></p>
+    <h1>{}</h1>
+{}
+</body>
+</html>"#,
+            htmlescape::encode_minimal(&issue.title),
+            htmlescape::encode_minimal(&issue.title),
+            substitute_name(&issue.html_content, ARCHIVE_READER_NAME)
+        )))
+}
+
+struct ArchivedIssue {
+    title: String,
+    slug: String,
+    published_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[tracing::instrument(name = "Get sent newsletter issues", skip(pool))]
+async fn get_sent_issues(pool: &PgPool) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT title, slug, published_at
+        FROM newsletter_issues
+        WHERE status = $1
+        ORDER BY published_at DESC
+        "#,
+        IssueStatus::Sent.as_str()
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the sent newsletter issues")?;
+    Ok(issues)
+}
+
+struct ArchivedIssueContent {
+    title: String,
+    html_content: String,
+}
+
+#[tracing::instrument(name = "Get a sent newsletter issue", skip(pool))]
+async fn get_sent_issue(
+    slug: &str,
+    pool: &PgPool,
+) -> Result<Option<ArchivedIssueContent>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        ArchivedIssueContent,
+        r#"
+        SELECT title, html_content
+        FROM newsletter_issues
+        WHERE slug = $1 AND status = $2
+        "#,
+        slug,
+        IssueStatus::Sent.as_str()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the newsletter issue")?;
+    Ok(issue)
+}
@@ -0,0 +1,93 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::newsletters::{authenticate_editor, EditorAuthError};
+use crate::{domain::ListSlug, session_state::TypedSession, util::error_chain_fmt};
+
+#[derive(thiserror::Error)]
+pub enum ListError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    AuthError(#[from] EditorAuthError),
+    #[error("There is a list named {0} already")]
+    SlugTaken(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ListError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ListError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            ListError::SlugTaken(_) => HttpResponse::new(StatusCode::CONFLICT),
+            ListError::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            ListError::AuthError(e) => e.error_response(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListData {
+    // Used to subscribe to the list and to send issues to it, see `ListSlug`.
+    slug: String,
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ListSummary {
+    list_id: Uuid,
+    slug: String,
+    name: String,
+}
+
+#[tracing::instrument(
+    name = "Create a list",
+    skip(body, pool, request, session),
+    fields(list_slug = %body.slug)
+)]
+pub async fn create_list(
+    body: web::Json<ListData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, ListError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let ListData { slug, name } = body.into_inner();
+    let slug = ListSlug::parse(slug).map_err(ListError::ValidationError)?;
+    let name = name.trim().to_owned();
+    if name.is_empty() {
+        return Err(ListError::ValidationError("A list needs a name".into()));
+    }
+    let list_id = Uuid::new_v4();
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        list_id,
+        slug.as_ref(),
+        name
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the new list")?
+    .rows_affected();
+    if n_inserted_rows == 0 {
+        return Err(ListError::SlugTaken(slug.as_ref().to_owned()));
+    }
+    Ok(HttpResponse::Created().json(ListSummary {
+        list_id,
+        slug: slug.as_ref().to_owned(),
+        name,
+    }))
+}
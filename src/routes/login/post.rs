@@ -0,0 +1,75 @@
+use actix_web::{error::InternalError, http::header::LOCATION, web, HttpResponse};
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{validate_credentials, AuthError, Credentials},
+    flash_messages::FlashMessage,
+    session_state::TypedSession,
+    startup::HmacSecret,
+    util::{error_chain_fmt, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum LoginError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Something went wrong")]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for LoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(
+    skip(form, pool, session, hmac_secret),
+    fields(username = tracing::field::Empty, user_id = tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            session.renew();
+            session
+                .insert_user_id(user_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into()), &hmac_secret))?;
+            Ok(see_other("/admin/dashboard"))
+        }
+        Err(e) => {
+            let e = match e {
+                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
+            };
+            Err(login_redirect(e, &hmac_secret))
+        }
+    }
+}
+
+// Send the user back to the login form, with the error shown as a flash message.
+// `InternalError` keeps the original error around for our logs while the user gets the redirect.
+fn login_redirect(e: LoginError, hmac_secret: &HmacSecret) -> InternalError<LoginError> {
+    let response = HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login"))
+        .cookie(FlashMessage::error(e.to_string()).into_cookie(hmac_secret))
+        .finish();
+    InternalError::from_response(e, response)
+}
@@ -1,7 +1,29 @@
+mod admin;
+mod archive;
 mod health_check;
+mod lists;
+mod login;
+mod newsletter_issues;
+mod newsletters;
+mod segments;
+mod subscriber_tags;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
 
+pub use admin::*;
+pub use archive::*;
 pub use health_check::*;
+pub use lists::*;
+pub use login::*;
+pub use newsletter_issues::*;
+pub use newsletters::*;
+pub use segments::*;
+pub use subscriber_tags::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
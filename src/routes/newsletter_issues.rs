@@ -0,0 +1,296 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::newsletters::{
+    authenticate_editor, get_issue_list_ids, get_issue_segment, insert_newsletter_issue,
+    set_issue_lists, BodyData, PublishError,
+};
+use crate::{
+    domain::{IssueSlug, IssueStatus},
+    issue_scheduler::{start_delivery, update_issue_status},
+    session_state::TypedSession,
+};
+
+// What editors get back after each step of the lifecycle of an issue, see `IssueStatus`.
+#[derive(serde::Serialize)]
+pub struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    slug: String,
+    status: String,
+    // The slugs of the lists it is sent to.
+    lists: Vec<String>,
+    // The filter on the subscribers of these lists, normalized, see `Segment`.
+    segment: Option<serde_json::Value>,
+    // RFC 3339, set for scheduled issues.
+    scheduled_for: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleData {
+    // RFC 3339, e.g. `2024-08-20T09:00:00Z`. Times already past are sent on the next run of
+    // the scheduler.
+    scheduled_for: String,
+}
+
+#[tracing::instrument(
+    name = "Create a newsletter issue draft",
+    skip(body, pool, request, session),
+    fields(issue_title = %body.title)
+)]
+pub async fn create_draft(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let mut transaction = begin(&pool).await?;
+    let list_ids = get_issue_list_ids(&mut transaction, body.lists.as_deref()).await?;
+    let segment = get_issue_segment(body.segment.as_ref())?;
+    let (issue_id, _) = insert_newsletter_issue(&mut transaction, &body, &list_ids, segment)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    let summary = get_issue_summary(&mut transaction, issue_id).await?;
+    commit(transaction).await?;
+    Ok(HttpResponse::Created().json(summary))
+}
+
+// Only drafts can be edited, scheduled issues have to be unscheduled first.
+#[tracing::instrument(
+    name = "Edit a newsletter issue draft",
+    skip(body, pool, request, session),
+    fields(issue_title = %body.title)
+)]
+pub async fn edit_draft(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let issue_id = issue_id.into_inner();
+    let mut transaction = begin(&pool).await?;
+    // Draft to draft is not a transition, check it here. Locked until the update is done.
+    let status = sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1 FOR UPDATE",
+        issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to read the newsletter issue status")?
+    .ok_or(PublishError::UnknownIssue(issue_id))?
+    .status;
+    let status = IssueStatus::try_from(status).map_err(anyhow::Error::msg)?;
+    if status != IssueStatus::Draft {
+        return Err(PublishError::NotADraft(status));
+    }
+    let list_ids = get_issue_list_ids(&mut transaction, body.lists.as_deref()).await?;
+    let segment = get_issue_segment(body.segment.as_ref())?;
+    set_issue_lists(&mut transaction, issue_id, &list_ids)
+        .await
+        .context("Failed to update the lists of the newsletter issue")?;
+    // The title may have changed.
+    let slug = IssueSlug::new(&body.title, issue_id);
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            tracking_enabled = $5,
+            slug = $6,
+            segment = $7::text::jsonb
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+        body.title,
+        body.content.text,
+        body.content.html,
+        body.tracking.unwrap_or(true),
+        slug.as_ref(),
+        segment.map(|segment| segment.to_json().to_string())
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the newsletter issue draft")?;
+    let summary = get_issue_summary(&mut transaction, issue_id).await?;
+    commit(transaction).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+// Scheduling an issue again moves it to the new time.
+#[tracing::instrument(
+    name = "Schedule a newsletter issue",
+    skip(body, pool, request, session)
+)]
+pub async fn schedule_issue(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<ScheduleData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let issue_id = issue_id.into_inner();
+    let scheduled_for = DateTime::parse_from_rfc3339(&body.scheduled_for)
+        .map_err(|e| {
+            PublishError::ValidationError(format!(
+                "{} is not a valid RFC 3339 date: {}",
+                body.scheduled_for, e
+            ))
+        })?
+        .with_timezone(&Utc);
+    let mut transaction = begin(&pool).await?;
+    update_issue_status(&mut transaction, issue_id, IssueStatus::Scheduled).await?;
+    set_scheduled_for(&mut transaction, issue_id, Some(scheduled_for)).await?;
+    let summary = get_issue_summary(&mut transaction, issue_id).await?;
+    commit(transaction).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+// Back to a draft, to be edited or sent at another time.
+#[tracing::instrument(name = "Unschedule a newsletter issue", skip(pool, request, session))]
+pub async fn unschedule_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    change_status(
+        issue_id.into_inner(),
+        IssueStatus::Draft,
+        &pool,
+        &request,
+        &session,
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Cancel a newsletter issue", skip(pool, request, session))]
+pub async fn cancel_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    change_status(
+        issue_id.into_inner(),
+        IssueStatus::Cancelled,
+        &pool,
+        &request,
+        &session,
+    )
+    .await
+}
+
+// Send a draft or a scheduled issue right away. Like `publish_newsletter`, delivery happens
+// in the background.
+#[tracing::instrument(name = "Send a newsletter issue", skip(pool, request, session))]
+pub async fn send_issue(
+    issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let issue_id = issue_id.into_inner();
+    let mut transaction = begin(&pool).await?;
+    start_delivery(&mut transaction, issue_id).await?;
+    let summary = get_issue_summary(&mut transaction, issue_id).await?;
+    commit(transaction).await?;
+    Ok(HttpResponse::Accepted().json(summary))
+}
+
+async fn change_status(
+    issue_id: Uuid,
+    next: IssueStatus,
+    pool: &PgPool,
+    request: &HttpRequest,
+    session: &TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    authenticate_editor(session, request, pool).await?;
+    let mut transaction = begin(pool).await?;
+    update_issue_status(&mut transaction, issue_id, next).await?;
+    set_scheduled_for(&mut transaction, issue_id, None).await?;
+    let summary = get_issue_summary(&mut transaction, issue_id).await?;
+    commit(transaction).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, PublishError> {
+    Ok(pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?)
+}
+
+async fn commit(transaction: Transaction<'_, Postgres>) -> Result<(), PublishError> {
+    Ok(transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a newsletter issue")?)
+}
+
+async fn set_scheduled_for(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    scheduled_for: Option<DateTime<Utc>>,
+) -> Result<(), PublishError> {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_for = $2 WHERE newsletter_issue_id = $1",
+        issue_id,
+        scheduled_for
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to update when the newsletter issue is scheduled for")?;
+    Ok(())
+}
+
+async fn get_issue_summary(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<IssueSummary, PublishError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT slug, status, scheduled_for, segment::text AS segment
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("Failed to read the newsletter issue")?;
+    let lists = sqlx::query!(
+        r#"
+        SELECT l.slug
+        FROM lists l
+        JOIN newsletter_issue_lists i ON i.list_id = l.list_id
+        WHERE i.newsletter_issue_id = $1
+        ORDER BY l.slug
+        "#,
+        issue_id
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to read the lists of the newsletter issue")?;
+    let segment = row
+        .segment
+        .map(|segment| serde_json::from_str(&segment))
+        .transpose()
+        .context("Failed to parse the segment of the newsletter issue")?;
+    Ok(IssueSummary {
+        newsletter_issue_id: issue_id,
+        slug: row.slug,
+        status: row.status,
+        lists: lists.into_iter().map(|list| list.slug).collect(),
+        segment,
+        scheduled_for: row.scheduled_for.map(|t| t.to_rfc3339()),
+    })
+}
@@ -0,0 +1,386 @@
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::{basic_authentication, validate_credentials, AuthError},
+    domain::{IllegalIssueTransition, IssueSlug, IssueStatus, ListSlug, Segment},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    issue_scheduler::{start_delivery, IssueUpdateError},
+    lists::get_list_id,
+    session_state::TypedSession,
+    util::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    pub(super) title: String,
+    pub(super) content: Content,
+    // Opens and clicks are tracked unless turned off, here or for every issue.
+    pub(super) tracking: Option<bool>,
+    // The slugs of the lists to send it to, the default list when missing. Somebody on several
+    // of them gets it once.
+    pub(super) lists: Option<Vec<String>>,
+    // Restricts the recipients to the subscribers of the lists matching this filter, see
+    // `Segment`.
+    pub(super) segment: Option<serde_json::Value>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    pub(super) html: String,
+    pub(super) text: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    AuthError(#[from] EditorAuthError),
+    #[error(transparent)]
+    AudienceError(#[from] AudienceError),
+    #[error("There is no newsletter issue with id {0}")]
+    UnknownIssue(Uuid),
+    #[error(transparent)]
+    IllegalTransition(#[from] IllegalIssueTransition),
+    #[error("Only drafts can be edited, this newsletter issue is {0}")]
+    NotADraft(IssueStatus),
+    #[error("The idempotency key was already used for another newsletter issue")]
+    IdempotencyKeyReused,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<IssueUpdateError> for PublishError {
+    fn from(e: IssueUpdateError) -> Self {
+        match e {
+            IssueUpdateError::UnknownIssue(issue_id) => PublishError::UnknownIssue(issue_id),
+            IssueUpdateError::IllegalTransition(e) => PublishError::IllegalTransition(e),
+            IssueUpdateError::UnexpectedError(e) => PublishError::UnexpectedError(e),
+        }
+    }
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PublishError {
+    // `error_response` instead of `status_code` since a 401 must carry a `WWW-Authenticate`
+    // header telling the caller which scheme to use.
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PublishError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            PublishError::UnknownIssue(_) => HttpResponse::new(StatusCode::NOT_FOUND),
+            PublishError::IdempotencyKeyReused => {
+                HttpResponse::new(StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            // The issue is not in a state that allows it, e.g. editing an issue already sent.
+            PublishError::IllegalTransition(_) | PublishError::NotADraft(_) => {
+                HttpResponse::new(StatusCode::CONFLICT)
+            }
+            PublishError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            PublishError::AuthError(e) => e.error_response(),
+            PublishError::AudienceError(e) => e.error_response(),
+        }
+    }
+}
+
+// Why `authenticate_editor` failed. The errors of the editor routes wrap it with `#[from]` and
+// leave the response to it.
+#[derive(thiserror::Error)]
+pub enum EditorAuthError {
+    #[error("Authentication failed")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<AuthError> for EditorAuthError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => EditorAuthError::InvalidCredentials(e.into()),
+            AuthError::UnexpectedError(_) => EditorAuthError::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl std::fmt::Debug for EditorAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for EditorAuthError {
+    // A 401 must carry a `WWW-Authenticate` header telling the caller which scheme to use.
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            EditorAuthError::InvalidCredentials(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+            EditorAuthError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+// The issue is stored and sent right away, see `start_delivery`. We reply with `202 Accepted`
+// since delivery has not happened yet when the response goes out.
+// Issues can also be drafted and scheduled, see `create_draft`.
+// An optional `Idempotency-Key` header makes retries safe: a repeated request with the same key
+// gets the saved response back instead of publishing the issue a second time, reusing it for
+// another issue is a 422.
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, request, session),
+    fields(
+        issue_title = %body.title,
+        user_id = tracing::field::Empty
+    )
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, PublishError> {
+    let user_id = authenticate_editor(&session, &request, &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let idempotency_key =
+        IdempotencyKey::from_headers(request.headers()).map_err(PublishError::ValidationError)?;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            let request_hash = request_hash(&body)?;
+            match try_processing(&pool, idempotency_key, user_id, Some(&request_hash)).await? {
+                NextAction::StartProcessing(t) => t,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+                NextAction::RejectReusedKey => return Err(PublishError::IdempotencyKeyReused),
+            }
+        }
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire Postgres connection from the pool")?,
+    };
+    let list_ids = get_issue_list_ids(&mut transaction, body.lists.as_deref()).await?;
+    let segment = get_issue_segment(body.segment.as_ref())?;
+    let (issue_id, _) = insert_newsletter_issue(&mut transaction, &body, &list_ids, segment)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    start_delivery(&mut transaction, issue_id).await?;
+    let response = HttpResponse::Accepted().finish();
+    match idempotency_key {
+        Some(idempotency_key) => {
+            Ok(
+                save_response(transaction, &idempotency_key, user_id, response)
+                    .await
+                    .context("Failed to save the response to an idempotent request")?,
+            )
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue")?;
+            Ok(response)
+        }
+    }
+}
+
+// Identifies the issue to publish. Keys are scoped to the editor, but a script could still
+// reuse one for another issue by mistake.
+fn request_hash(body: &BodyData) -> Result<Vec<u8>, anyhow::Error> {
+    let mut hasher = Sha256::new();
+    for field in [
+        body.title.clone(),
+        body.content.text.clone(),
+        body.content.html.clone(),
+        body.tracking.unwrap_or(true).to_string(),
+        serde_json::to_string(&body.lists).context("Failed to hash the lists")?,
+        // Objects are sorted by key, whatever their order in the request.
+        serde_json::to_string(&body.segment).context("Failed to hash the segment")?,
+    ] {
+        // Length-prefixed, to keep `ab` + `c` apart from `a` + `bc`.
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field.as_bytes());
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+// Browsers are authenticated by their session cookie, scripts by `Basic` auth.
+pub(super) async fn authenticate_editor(
+    session: &TypedSession,
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, EditorAuthError> {
+    match session
+        .get_user_id()
+        .context("Failed to read the user id from the session")?
+    {
+        Some(user_id) => Ok(user_id),
+        None => Ok(authenticate_with_basic_auth(request, pool).await?),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(username = tracing::field::Empty))]
+async fn authenticate_with_basic_auth(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    let credentials =
+        basic_authentication(request.headers()).map_err(AuthError::InvalidCredentials)?;
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    validate_credentials(credentials, pool).await
+}
+
+// Stored as a draft, returns its id and slug.
+#[tracing::instrument(skip_all)]
+pub(super) async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue: &BodyData,
+    list_ids: &[Uuid],
+    segment: Option<Segment>,
+) -> Result<(Uuid, IssueSlug), sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let slug = IssueSlug::new(&issue.title, newsletter_issue_id);
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            tracking_enabled,
+            status,
+            slug,
+            segment
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8::text::jsonb)
+        "#,
+        newsletter_issue_id,
+        issue.title,
+        issue.content.text,
+        issue.content.html,
+        issue.tracking.unwrap_or(true),
+        IssueStatus::Draft.as_str(),
+        slug.as_ref(),
+        segment.map(|segment| segment.to_json().to_string())
+    );
+    transaction.execute(query).await?;
+    set_issue_lists(transaction, newsletter_issue_id, list_ids).await?;
+    Ok((newsletter_issue_id, slug))
+}
+
+// Why the lists or the segment of an issue cannot be used, see `get_issue_list_ids` and
+// `get_issue_segment`.
+#[derive(thiserror::Error)]
+pub enum AudienceError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AudienceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AudienceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AudienceError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AudienceError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// The ids of the lists named by an issue, the default list if it names none.
+pub(super) async fn get_issue_list_ids(
+    transaction: &mut Transaction<'_, Postgres>,
+    lists: Option<&[String]>,
+) -> Result<Vec<Uuid>, AudienceError> {
+    let slugs = match lists {
+        None => vec![None],
+        Some([]) => {
+            return Err(AudienceError::ValidationError(
+                "An issue is sent to at least one list".into(),
+            ))
+        }
+        Some(slugs) => slugs
+            .iter()
+            .map(|slug| ListSlug::parse(slug.clone()).map(Some))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(AudienceError::ValidationError)?,
+    };
+    let mut list_ids = Vec::with_capacity(slugs.len());
+    for slug in slugs {
+        let list_id = get_list_id(transaction, slug.as_ref())
+            .await
+            .context("Failed to look up the lists of the newsletter issue")?
+            .ok_or_else(|| {
+                AudienceError::ValidationError(match &slug {
+                    Some(slug) => format!("There is no list {}", slug.as_ref()),
+                    None => "There is no default list".into(),
+                })
+            })?;
+        list_ids.push(list_id);
+    }
+    list_ids.sort();
+    list_ids.dedup();
+    Ok(list_ids)
+}
+
+pub(super) fn get_issue_segment(
+    segment: Option<&serde_json::Value>,
+) -> Result<Option<Segment>, AudienceError> {
+    segment
+        .map(Segment::parse)
+        .transpose()
+        .map_err(AudienceError::ValidationError)
+}
+
+#[tracing::instrument(skip(transaction))]
+pub(super) async fn set_issue_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, list_id FROM UNNEST($2::uuid[]) AS list_id
+        "#,
+        issue_id,
+        list_ids
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+
+use super::newsletters::{
+    authenticate_editor, get_issue_list_ids, get_issue_segment, AudienceError, EditorAuthError,
+};
+use crate::{segments::count_audience, session_state::TypedSession, util::error_chain_fmt};
+
+#[derive(thiserror::Error)]
+pub enum SegmentError {
+    #[error(transparent)]
+    AuthError(#[from] EditorAuthError),
+    #[error(transparent)]
+    AudienceError(#[from] AudienceError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SegmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SegmentError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SegmentError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            SegmentError::AuthError(e) => e.error_response(),
+            SegmentError::AudienceError(e) => e.error_response(),
+        }
+    }
+}
+
+// The same audience an issue would have with these `lists` and `segment`, see `BodyData`.
+#[derive(serde::Deserialize)]
+pub struct SegmentPreviewData {
+    lists: Option<Vec<String>>,
+    segment: Option<serde_json::Value>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SegmentPreview {
+    matching_subscribers: i64,
+}
+
+// How many subscribers an issue would be sent to, to check a segment before using it.
+#[tracing::instrument(name = "Preview a segment", skip(body, pool, request, session))]
+pub async fn preview_segment(
+    body: web::Json<SegmentPreviewData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, SegmentError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let segment = get_issue_segment(body.segment.as_ref())?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let list_ids = get_issue_list_ids(&mut transaction, body.lists.as_deref()).await?;
+    let matching_subscribers = count_audience(&mut transaction, &list_ids, segment.as_ref())
+        .await
+        .context("Failed to count the subscribers matching the segment")?;
+    Ok(HttpResponse::Ok().json(SegmentPreview {
+        matching_subscribers,
+    }))
+}
@@ -0,0 +1,172 @@
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use super::newsletters::{authenticate_editor, EditorAuthError};
+use crate::{domain::SubscriberTag, session_state::TypedSession, util::error_chain_fmt};
+
+#[derive(thiserror::Error)]
+pub enum SubscriberTagError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    AuthError(#[from] EditorAuthError),
+    #[error("There is no subscriber with id {0}")]
+    UnknownSubscriber(Uuid),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SubscriberTagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for SubscriberTagError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscriberTagError::ValidationError(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            SubscriberTagError::UnknownSubscriber(_) => HttpResponse::new(StatusCode::NOT_FOUND),
+            SubscriberTagError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            SubscriberTagError::AuthError(e) => e.error_response(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct TagsData {
+    tags: Vec<String>,
+}
+
+// Every endpoint replies with all the tags of the subscriber, sorted.
+#[derive(serde::Serialize)]
+pub struct SubscriberTags {
+    subscriber_id: Uuid,
+    tags: Vec<String>,
+}
+
+#[tracing::instrument(name = "List the tags of a subscriber", skip(pool, request, session))]
+pub async fn get_subscriber_tags(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, SubscriberTagError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = begin(&pool).await?;
+    let tags = get_tags(&mut transaction, subscriber_id).await?;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+// Tagging a subscriber with a tag they have already is a no-op.
+#[tracing::instrument(
+    name = "Tag a subscriber",
+    skip(body, pool, request, session),
+    fields(tags = ?body.tags)
+)]
+pub async fn add_subscriber_tags(
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<TagsData>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, SubscriberTagError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let subscriber_id = subscriber_id.into_inner();
+    let tags = body
+        .into_inner()
+        .tags
+        .into_iter()
+        .map(|tag| SubscriberTag::parse(tag).map(|tag| tag.as_ref().to_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(SubscriberTagError::ValidationError)?;
+    let mut transaction = begin(&pool).await?;
+    // Fails on the foreign key otherwise, and we would rather tell a missing subscriber apart.
+    get_tags(&mut transaction, subscriber_id).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_tags (subscriber_id, tag)
+        SELECT $1, tag FROM UNNEST($2::text[]) AS tag
+        ON CONFLICT (subscriber_id, tag) DO NOTHING
+        "#,
+        subscriber_id,
+        &tags
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the tags of the subscriber")?;
+    let tags = get_tags(&mut transaction, subscriber_id).await?;
+    commit(transaction).await?;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+// Removing a tag the subscriber does not have is a no-op.
+#[tracing::instrument(name = "Untag a subscriber", skip(pool, request, session))]
+pub async fn remove_subscriber_tag(
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+    session: TypedSession,
+) -> Result<HttpResponse, SubscriberTagError> {
+    authenticate_editor(&session, &request, &pool).await?;
+    let (subscriber_id, tag) = path.into_inner();
+    let tag = SubscriberTag::parse(tag).map_err(SubscriberTagError::ValidationError)?;
+    let mut transaction = begin(&pool).await?;
+    sqlx::query!(
+        "DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = $2",
+        subscriber_id,
+        tag.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the tag of the subscriber")?;
+    let tags = get_tags(&mut transaction, subscriber_id).await?;
+    commit(transaction).await?;
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+// The subscriber is locked until the transaction ends, so that tagging them does not race
+// with their removal.
+async fn get_tags(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<SubscriberTags, SubscriberTagError> {
+    sqlx::query!(
+        "SELECT id FROM subscriptions WHERE id = $1 FOR SHARE",
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to look up the subscriber")?
+    .ok_or(SubscriberTagError::UnknownSubscriber(subscriber_id))?;
+    let tags = sqlx::query!(
+        "SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag",
+        subscriber_id
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to read the tags of the subscriber")?;
+    Ok(SubscriberTags {
+        subscriber_id,
+        tags: tags.into_iter().map(|row| row.tag).collect(),
+    })
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, SubscriberTagError> {
+    Ok(pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?)
+}
+
+async fn commit(transaction: Transaction<'_, Postgres>) -> Result<(), SubscriberTagError> {
+    Ok(transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update the tags of a subscriber")?)
+}
@@ -1,14 +1,22 @@
-use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use actix_web::{
+    http::{header::ACCEPT_LANGUAGE, StatusCode},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
 use anyhow::Context;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
-    startup::AppBaseUrl,
+    domain::{
+        ListSlug, NewSubscriber, SubscriberEmail, SubscriberLocale, SubscriberName,
+        SubscriptionStatus,
+    },
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    lists::{get_list_id, upsert_list_membership},
+    transactional_email_worker::{enqueue_email, TransactionalEmail},
     util::error_chain_fmt,
 };
 
@@ -16,6 +24,11 @@ use crate::{
 pub struct FormData {
     name: String,
     email: String,
+    // e.g. `fr` or `pt-BR`. Taken from `Accept-Language` when missing, English if neither is
+    // usable.
+    locale: Option<String>,
+    // The slug of the list to subscribe to, the default list when missing.
+    list: Option<String>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -24,7 +37,21 @@ impl TryFrom<FormData> for NewSubscriber {
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(form.name)?;
         let email = SubscriberEmail::parse(form.email)?;
-        Ok(Self { name, email })
+        let locale = match form.locale {
+            Some(locale) => SubscriberLocale::parse(locale)?,
+            None => SubscriberLocale::default(),
+        };
+        let list = form
+            .list
+            .filter(|list| !list.trim().is_empty())
+            .map(ListSlug::parse)
+            .transpose()?;
+        Ok(Self {
+            name,
+            email,
+            locale,
+            list,
+        })
     }
 }
 
@@ -45,6 +72,8 @@ pub enum SubscribeError {
     //  trait
     #[error("{0}")]
     ValidationError(String),
+    #[error("The idempotency key was already used for a different request")]
+    IdempotencyKeyReused,
     // `error(transparent)` delegates `Display` and `source` impl to the type wrapped by
     // `UnexpectedError`
     #[error(transparent)]
@@ -64,14 +93,42 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
+// Subscribing is anonymous, so idempotency keys are scoped to the nil user id. Two visitors
+// may well pick the same key: the saved response is only replayed to the same request, see
+// `request_hash`.
+const ANONYMOUS_USER_ID: Uuid = Uuid::nil();
+
+// Identifies the subscription request, whatever the order of the fields of the form.
+fn request_hash(new_subscriber: &NewSubscriber) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for field in [
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+        new_subscriber.locale.as_ref(),
+        new_subscriber
+            .list
+            .as_ref()
+            .map_or("", |list| list.as_ref()),
+    ] {
+        // Length-prefixed, to keep `ab` + `c` apart from `a` + `bc`.
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field.as_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
+// The confirmation email is queued with the subscriber and sent by
+// `transactional_email_worker` once the transaction is committed: every request gets the same
+// response, in about the same time, whatever happens to the email.
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, request),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -80,58 +137,191 @@ impl ResponseError for SubscribeError {
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    base_url: web::Data<AppBaseUrl>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
+    let mut form = form.0;
+    // An explicit choice wins over the preferences of the browser, an empty one is no choice.
+    form.locale = form.locale.filter(|locale| !locale.trim().is_empty());
+    if form.locale.is_none() {
+        form.locale = request
+            .headers()
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(SubscriberLocale::from_accept_language)
+            .map(|locale| locale.as_ref().to_owned());
+    }
     // manually map the error
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
-    let mut transaction = pool
-        .begin()
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
+    let idempotency_key =
+        IdempotencyKey::from_headers(request.headers()).map_err(SubscribeError::ValidationError)?;
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            let request_hash = request_hash(&new_subscriber);
+            match try_processing(
+                &pool,
+                idempotency_key,
+                ANONYMOUS_USER_ID,
+                Some(&request_hash),
+            )
+            .await?
+            {
+                NextAction::StartProcessing(t) => t,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+                NextAction::RejectReusedKey => return Err(SubscribeError::IdempotencyKeyReused),
+            }
+        }
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire Postgres connection from the pool")?,
+    };
+    let list_id = get_list_id(&mut transaction, new_subscriber.list.as_ref())
         .await
-        .context("Failed to acquire Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction)
+        .context("Failed to look up the list to subscribe to")?
+        .ok_or_else(|| SubscribeError::ValidationError("There is no such list".into()))?;
+    let subscriber_id = match insert_subscriber(&new_subscriber, &mut transaction)
         .await
-        .context("Failed to insert new subscriber in the database")?;
-    let subscription_token = gen_subscription_token();
-    store_token(subscriber_id, &subscription_token, &mut transaction)
+        .context("Failed to insert new subscriber in the database")?
+    {
+        Some(subscriber_id) => Some(subscriber_id),
+        None => prepare_resubscription(&new_subscriber, list_id, &mut transaction)
+            .await
+            .context("Failed to handle a subscription for an already registered email")?,
+    };
+    if let Some(subscriber_id) = subscriber_id {
+        upsert_list_membership(
+            &mut transaction,
+            subscriber_id,
+            list_id,
+            SubscriptionStatus::PendingConfirmation,
+        )
+        .await
+        .context("Failed to add the subscriber to the list")?;
+        let subscription_token = gen_subscription_token();
+        store_token(
+            subscriber_id,
+            list_id,
+            &subscription_token,
+            &mut transaction,
+        )
         .await
         .context(
             "Failed to store the confirmation token for a new \
-        subscriber",
+            subscriber",
         )?;
-    transaction
-        .commit()
+        enqueue_email(
+            &mut transaction,
+            TransactionalEmail::Confirmation,
+            subscriber_id,
+            list_id,
+        )
         .await
-        .context("Failed to commit SQL transaction to store a new subscriber")?;
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send confirmation email")?;
-    Ok(HttpResponse::Ok().finish())
+        .context("Failed to enqueue the confirmation email")?;
+    }
+    // Same response whether the email was already registered or not, we don't want to reveal
+    // who is subscribed.
+    let response = HttpResponse::Ok().finish();
+    match idempotency_key {
+        Some(idempotency_key) => {
+            Ok(
+                save_response(transaction, &idempotency_key, ANONYMOUS_USER_ID, response)
+                    .await
+                    .context("Failed to save the response to an idempotent request")?,
+            )
+        }
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a new subscriber")?;
+            Ok(response)
+        }
+    }
 }
 
 // attach instrumentation
+// Returns `None` if the email is already registered. A concurrent request for the same email
+// waits on the unique index until ours is done, and then gets `None` as well.
 #[tracing::instrument(name = "Saving new subscriber details to the database", skip_all)]
-async fn insert_subscriber<'a>(
-    new_subscriber: &'a NewSubscriber,
+async fn insert_subscriber(
+    new_subscriber: &NewSubscriber,
     transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     let query = sqlx::query!(
         //  TODO: Raw string literals ignore special characters and escapes. r#""# (raw string literal) documented on: https://doc.rust-lang.org/reference/tokens.html#raw-string-literals.
-        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')",
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status, locale)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (email) DO NOTHING",
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        SubscriptionStatus::PendingConfirmation.as_str(),
+        new_subscriber.locale.as_ref()
     );
-    transaction.execute(query).await?;
-    Ok(subscriber_id)
+    let n_inserted_rows = transaction.execute(query).await?.rows_affected();
+    Ok((n_inserted_rows > 0).then_some(subscriber_id))
+}
+
+// Somebody subscribes to a list with an email we already know. Returns the id of the
+// subscriber that needs a (new) confirmation email for that list, if any:
+// - pending subscribers get a fresh token, the links sent earlier for the list stop working;
+// - unsubscribed ones go back to pending, they have to confirm again;
+// - confirmed subscribers joining another list stay confirmed, and confirm the new list;
+// - subscribers confirmed on the list already, bounced and complained ones are left untouched.
+// The ones getting an email switch to the locale they asked for this time.
+#[tracing::instrument(name = "Handle the re-subscription of a known email", skip_all)]
+async fn prepare_resubscription(
+    new_subscriber: &NewSubscriber,
+    list_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let existing = sqlx::query!(
+        r#"
+        SELECT s.id, s.status, m.status AS "list_status?"
+        FROM subscriptions s
+        LEFT JOIN list_memberships m ON m.subscriber_id = s.id AND m.list_id = $2
+        WHERE s.email = $1
+        FOR UPDATE OF s
+        "#,
+        new_subscriber.email.as_ref(),
+        list_id
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    let status = SubscriptionStatus::try_from(existing.status).map_err(anyhow::Error::msg)?;
+    let on_the_list =
+        existing.list_status.as_deref() == Some(SubscriptionStatus::Confirmed.as_str());
+    match status {
+        SubscriptionStatus::PendingConfirmation => {}
+        SubscriptionStatus::Confirmed if !on_the_list => {}
+        _ if status.can_transition_to(SubscriptionStatus::PendingConfirmation) => {
+            sqlx::query!(
+                "UPDATE subscriptions SET status = $1 WHERE id = $2",
+                SubscriptionStatus::PendingConfirmation.as_str(),
+                existing.id
+            )
+            .execute(&mut **transaction)
+            .await?;
+        }
+        _ => return Ok(None),
+    }
+    sqlx::query!(
+        "UPDATE subscriptions SET locale = $1 WHERE id = $2",
+        new_subscriber.locale.as_ref(),
+        existing.id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND list_id = $2",
+        existing.id,
+        list_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(Some(existing.id))
 }
 
 // New error type for `store_token`
@@ -164,41 +354,17 @@ impl std::error::Error for StoreTokenError {
 #[tracing::instrument(name = "Saving new subscription_token to the database", skip_all)]
 async fn store_token(
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
-        "INSERT INTO subscription_tokens (subscriber_id, subscription_token)
-        VALUES ($1, $2)",
+        "INSERT INTO subscription_tokens (subscriber_id, list_id, subscription_token)
+        VALUES ($1, $2, $3)",
         subscriber_id,
+        list_id,
         subscription_token
     );
     transaction.execute(query).await.map_err(StoreTokenError)?;
     Ok(())
 }
-
-#[tracing::instrument(name = "Sending confirmation email to subscriber", skip_all)]
-async fn send_confirmation_email<'a>(
-    email_client: &'a EmailClient,
-    new_subscriber: NewSubscriber,
-    base_url: &'a str,
-    subscription_token: &'a str,
-) -> Result<(), reqwest::Error> {
-    let confirmation_link = format!(
-        "{}/subscriptions/confirm?subscription_token={}",
-        base_url, subscription_token
-    );
-    let subject = "subject";
-    let html_body = format!(
-        "Welcome to our newsletter!<br>\
-            Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    email_client
-        .send_email(new_subscriber.email, subject, &html_body, &plain_body)
-        .await
-}
@@ -1,9 +1,14 @@
 use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::util::error_chain_fmt;
+use crate::{
+    configuration::ExpirySettings,
+    subscriber_status::{confirm_list_membership, StatusUpdateError},
+    util::error_chain_fmt,
+};
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
@@ -16,6 +21,13 @@ pub enum ConfirmationError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("There is no subscriber associated with the provided token")]
     UnknownToken,
+    // The token is older than its TTL, but still within the grace period before the cleanup task
+    // deletes it.
+    #[error("The confirmation link has expired, please subscribe again")]
+    ExpiredToken,
+    // e.g. the subscriber unsubscribed before clicking the confirmation link.
+    #[error("The subscription can no longer be confirmed")]
+    IllegalTransition(#[source] StatusUpdateError),
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -29,6 +41,8 @@ impl ResponseError for ConfirmationError {
         match self {
             ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ConfirmationError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmationError::ExpiredToken => StatusCode::GONE,
+            ConfirmationError::IllegalTransition(_) => StatusCode::CONFLICT,
         }
     }
 }
@@ -37,39 +51,41 @@ impl ResponseError for ConfirmationError {
 pub async fn confirm(
     params: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    expiry: web::Data<ExpirySettings>,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let subscriber_id = get_subcriber_id_from_token(&pool, &params.subscription_token)
+    let token = get_token(&pool, &params.subscription_token)
         .await
         .context("Failed to retrieve the subscriber id from the token provided")?
         .ok_or(ConfirmationError::UnknownToken)?;
-    confirm_susbcriber(&pool, subscriber_id)
+    if token.created_at < Utc::now() - expiry.subscription_token_ttl() {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    // The token was issued for one list, the other lists of the subscriber are left as they are.
+    confirm_list_membership(&pool, token.subscriber_id, token.list_id)
         .await
-        .context("Failed to confirm subscriber")?;
+        .map_err(|e| match e {
+            StatusUpdateError::IllegalTransition(_) => ConfirmationError::IllegalTransition(e),
+            _ => ConfirmationError::UnexpectedError(
+                anyhow::Error::new(e).context("Failed to confirm subscriber"),
+            ),
+        })?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
-async fn get_subcriber_id_from_token(
-    pool: &PgPool,
-    token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id AS id FROM subscription_tokens \
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+async fn get_token(pool: &PgPool, token: &str) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriptionToken,
+        "SELECT subscriber_id, list_id, created_at FROM subscription_tokens \
         WHERE subscription_token = $1",
         token
     )
     .fetch_optional(pool)
-    .await?;
-    Ok(result.map(|r| r.id))
-}
-
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip_all)]
-async fn confirm_susbcriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id
-    )
-    .execute(pool)
-    .await?;
-    Ok(())
+    .await
 }
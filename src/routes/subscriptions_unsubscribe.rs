@@ -0,0 +1,115 @@
+use actix_web::{http::header::ContentType, http::StatusCode, web, HttpResponse, ResponseError};
+use sqlx::PgPool;
+
+use crate::{
+    domain::{UnsubscribeLinks, UnsubscribeTarget},
+    subscriber_status::{leave_list, StatusUpdateError},
+    util::error_chain_fmt,
+};
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+    #[error("The unsubscribe link is invalid")]
+    InvalidToken(#[source] anyhow::Error),
+    // Bounced and complained addresses are already excluded for good.
+    #[error("The subscription can no longer be changed")]
+    IllegalTransition(#[source] StatusUpdateError),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            UnsubscribeError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::IllegalTransition(_) => StatusCode::CONFLICT,
+        }
+    }
+}
+
+// The link a subscriber clicks in the email body.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip_all)]
+pub async fn unsubscribe(
+    params: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let target = unsubscribe_with_token(&pool, &unsubscribe_links, &params.token).await?;
+    if target.preview {
+        return Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>This is the unsubscribe link of a preview, nobody has been unsubscribed.</p>
+</body>
+</html>"#,
+        ));
+    }
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribed</title>
+</head>
+<body>
+    <p>You have been unsubscribed, you will not receive any more emails from this list.</p>
+</body>
+</html>"#,
+    ))
+}
+
+// RFC 8058 one-click unsubscription, sent by mail clients to the URL found in the
+// `List-Unsubscribe` header with a `List-Unsubscribe=One-Click` form body.
+// The body carries no information, the token in the URL is all we need.
+#[tracing::instrument(name = "Unsubscribe a subscriber (one-click)", skip_all)]
+pub async fn unsubscribe_one_click(
+    params: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    unsubscribe_links: web::Data<UnsubscribeLinks>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    unsubscribe_with_token(&pool, &unsubscribe_links, &params.token).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// Links of previews and test copies are checked like any other, but unsubscribe nobody: they
+// are in the hands of editors and seed addresses, not of the subscriber.
+async fn unsubscribe_with_token(
+    pool: &PgPool,
+    unsubscribe_links: &UnsubscribeLinks,
+    token: &str,
+) -> Result<UnsubscribeTarget, UnsubscribeError> {
+    let target = unsubscribe_links
+        .verify(token)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    if target.preview {
+        return Ok(target);
+    }
+    // Unsubscribing twice, or after the subscriber or the list has been removed, is not an
+    // error: the outcome the caller asked for holds either way.
+    match leave_list(pool, target.subscriber_id, target.list_id).await {
+        Ok(()) | Err(StatusUpdateError::UnknownSubscriber(_)) => Ok(target),
+        Err(e @ StatusUpdateError::IllegalTransition(_)) => {
+            Err(UnsubscribeError::IllegalTransition(e))
+        }
+        Err(e) => Err(UnsubscribeError::UnexpectedError(
+            anyhow::Error::new(e).context("Failed to unsubscribe subscriber"),
+        )),
+    }
+}
@@ -0,0 +1,130 @@
+use actix_web::{
+    http::{
+        header::{self, CacheControl, CacheDirective},
+        StatusCode,
+    },
+    web, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::{TrackedDelivery, TrackingLinks},
+    util::error_chain_fmt,
+};
+
+// A transparent 1x1 GIF.
+const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("Invalid tracking token")]
+    InvalidToken(#[source] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for TrackingError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TrackingError::InvalidToken(_) => StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+// The link of an issue, redirecting to where the issue pointed to. The target is part of the
+// signed token, anything else is a 404: this is not an open redirect. Clicks in previews and
+// test copies are not recorded.
+#[tracing::instrument(name = "Track a click", skip_all)]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking_links: web::Data<TrackingLinks>,
+) -> Result<HttpResponse, TrackingError> {
+    let (delivery, url) = tracking_links
+        .verify_click(&token)
+        .map_err(TrackingError::InvalidToken)?;
+    if tracking_links.is_enabled() && !delivery.preview {
+        record_tracking_event(&pool, delivery, "click", Some(&url)).await;
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish())
+}
+
+// The pixel at the bottom of an issue, fetched when the email is displayed with its images.
+// Like clicks, opens of previews and test copies are not recorded.
+#[tracing::instrument(name = "Track an open", skip_all)]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking_links: web::Data<TrackingLinks>,
+) -> Result<HttpResponse, TrackingError> {
+    let delivery = tracking_links
+        .verify_open(&token)
+        .map_err(TrackingError::InvalidToken)?;
+    if tracking_links.is_enabled() && !delivery.preview {
+        record_tracking_event(&pool, delivery, "open", None).await;
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(TRACKING_PIXEL))
+}
+
+// The reader gets their redirect or pixel whatever happens here, a failure only costs us
+// the event.
+async fn record_tracking_event(
+    pool: &PgPool,
+    delivery: TrackedDelivery,
+    kind: &str,
+    url: Option<&str>,
+) {
+    if let Err(e) = insert_tracking_event(pool, delivery, kind, url).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record a tracking event",
+        );
+    }
+}
+
+// Nothing is recorded for issues that opted out of tracking, nor for subscribers or issues
+// that are gone.
+#[tracing::instrument(skip(pool))]
+async fn insert_tracking_event(
+    pool: &PgPool,
+    delivery: TrackedDelivery,
+    kind: &str,
+    url: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_tracking_events (
+            id, newsletter_issue_id, subscriber_id, kind, url, occurred_at
+        )
+        SELECT $1, i.newsletter_issue_id, s.id, $4, $5, now()
+        FROM newsletter_issues i, subscriptions s
+        WHERE i.newsletter_issue_id = $2 AND i.tracking_enabled AND s.id = $3
+        "#,
+        Uuid::new_v4(),
+        delivery.newsletter_issue_id,
+        delivery.subscriber_id,
+        kind,
+        url,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a tracking event")?;
+    Ok(())
+}
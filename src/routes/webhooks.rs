@@ -0,0 +1,94 @@
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::basic_authentication,
+    configuration::WebhookSettings,
+    email_events::{parse_webhook, record_email_event},
+    util::error_chain_fmt,
+};
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+    #[error("Invalid webhook payload")]
+    InvalidPayload(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::InvalidPayload(_) => HttpResponse::new(StatusCode::BAD_REQUEST),
+            // The provider retries, the event is not lost.
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="webhooks""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+        }
+    }
+}
+
+// Bounces, spam complaints and deliveries reported by the email provider, see `parse_webhook`
+// for the providers we understand. Every event is stored in `email_events`; hard bounces and
+// complaints also end the subscription, the subscriber gets no more emails.
+#[tracing::instrument(name = "Receive email events", skip_all)]
+pub async fn email_webhook(
+    body: web::Bytes,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate(&request, &settings).map_err(WebhookError::AuthError)?;
+    let events = parse_webhook(&body).map_err(|e| WebhookError::InvalidPayload(e.into()))?;
+    for event in &events {
+        record_email_event(&pool, event).await?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn authenticate(request: &HttpRequest, settings: &WebhookSettings) -> Result<(), anyhow::Error> {
+    let secret = match request.headers().get("X-Webhook-Secret") {
+        Some(secret) => secret
+            .to_str()
+            .context("The 'X-Webhook-Secret' header was not a valid UTF8 string.")?
+            .to_owned(),
+        None => {
+            let credentials = basic_authentication(request.headers())?;
+            if credentials.username != settings.username {
+                anyhow::bail!("Unknown username.");
+            }
+            credentials.password.expose_secret().to_owned()
+        }
+    };
+    // Comparing digests rather than the secrets themselves, the time it takes tells nothing
+    // about the secret.
+    if Sha256::digest(secret) != Sha256::digest(settings.secret.expose_secret()) {
+        anyhow::bail!("Invalid secret.");
+    }
+    Ok(())
+}
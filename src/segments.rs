@@ -0,0 +1,153 @@
+use sqlx::{PgConnection, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::domain::{IssueStatus, Segment, SubscriptionStatus};
+
+// Appends `FROM subscriptions s WHERE ...`, selecting the subscribers an issue sent to
+// `list_ids` reaches: confirmed, on at least one of the lists, and in `segment` if there is
+// one. Each subscriber comes up once, however many of the lists they are on.
+pub fn push_audience(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    list_ids: &[Uuid],
+    segment: Option<&Segment>,
+) {
+    builder
+        .push(" FROM subscriptions s WHERE s.status = ")
+        .push_bind(SubscriptionStatus::Confirmed.as_str())
+        .push(
+            " AND EXISTS (SELECT 1 FROM list_memberships m \
+            WHERE m.subscriber_id = s.id AND m.status = ",
+        )
+        .push_bind(SubscriptionStatus::Confirmed.as_str())
+        .push(" AND m.list_id = ANY(")
+        .push_bind(list_ids.to_vec())
+        .push("))");
+    if let Some(segment) = segment {
+        builder.push(" AND ");
+        push_segment_filter(builder, segment);
+    }
+}
+
+// Appends the condition `segment` puts on `s`, a row of `subscriptions`. Every value coming
+// from the filter is bound as a parameter.
+pub fn push_segment_filter(builder: &mut QueryBuilder<'_, Postgres>, segment: &Segment) {
+    match segment {
+        Segment::All(conditions) | Segment::Any(conditions) => {
+            let operator = match segment {
+                Segment::All(_) => " AND ",
+                _ => " OR ",
+            };
+            builder.push("(");
+            for (i, condition) in conditions.iter().enumerate() {
+                if i > 0 {
+                    builder.push(operator);
+                }
+                push_segment_filter(builder, condition);
+            }
+            builder.push(")");
+        }
+        Segment::Not(condition) => {
+            builder.push("NOT (");
+            push_segment_filter(builder, condition);
+            builder.push(")");
+        }
+        Segment::Tag(tag) => {
+            builder
+                .push(
+                    "EXISTS (SELECT 1 FROM subscriber_tags t \
+                    WHERE t.subscriber_id = s.id AND t.tag = ",
+                )
+                .push_bind(tag.as_ref().to_owned())
+                .push(")");
+        }
+        Segment::SubscribedAfter(date) => {
+            builder.push("s.subscribed_at > ").push_bind(*date);
+        }
+        Segment::Locale(locale) => {
+            let locale = locale.as_ref().to_owned();
+            builder.push("(s.locale = ").push_bind(locale.clone());
+            // `fr` is for French speakers, wherever they are.
+            if !locale.contains('-') {
+                builder
+                    .push(" OR s.locale LIKE ")
+                    .push_bind(format!("{}-%", locale));
+            }
+            builder.push(")");
+        }
+        Segment::OpenedLastIssue(opened) => {
+            // The issue published last, not counting the one being sent right now (published
+            // in the current transaction). Nobody opened it if it was not tracked.
+            if !opened {
+                builder.push("NOT ");
+            }
+            builder
+                .push(
+                    "EXISTS (SELECT 1 FROM newsletter_tracking_events e \
+                    WHERE e.subscriber_id = s.id AND e.kind = 'open' AND \
+                    e.newsletter_issue_id = (SELECT i.newsletter_issue_id \
+                    FROM newsletter_issues i WHERE i.status IN (",
+                )
+                .push_bind(IssueStatus::Sending.as_str())
+                .push(", ")
+                .push_bind(IssueStatus::Sent.as_str())
+                .push(
+                    ") AND i.published_at < now() \
+                    ORDER BY i.published_at DESC LIMIT 1))",
+                );
+        }
+    }
+}
+
+// How many subscribers an issue sent to `list_ids` and restricted to `segment` would reach.
+#[tracing::instrument(skip(connection))]
+pub async fn count_audience(
+    connection: &mut PgConnection,
+    list_ids: &[Uuid],
+    segment: Option<&Segment>,
+) -> Result<i64, sqlx::Error> {
+    let mut builder = QueryBuilder::new("SELECT COUNT(*)");
+    push_audience(&mut builder, list_ids, segment);
+    builder
+        .build_query_scalar::<i64>()
+        .fetch_one(connection)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_segment_filter;
+    use crate::domain::Segment;
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn compile(filter: serde_json::Value) -> String {
+        let segment = Segment::parse(&filter).unwrap();
+        let mut builder = QueryBuilder::<Postgres>::new("");
+        push_segment_filter(&mut builder, &segment);
+        builder.into_sql()
+    }
+
+    #[test]
+    fn values_are_bound_as_parameters() {
+        let sql = compile(serde_json::json!({
+            "all": [
+                { "tag": "beta" },
+                { "not": { "locale": "fr" } },
+                { "subscribed_after": "2024-01-01T00:00:00Z" },
+            ]
+        }));
+        assert_eq!(
+            sql,
+            "(EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND \
+            t.tag = $1) AND NOT ((s.locale = $2 OR s.locale LIKE $3)) AND s.subscribed_at > $4)"
+        );
+        assert!(!sql.contains("beta"));
+    }
+
+    #[test]
+    fn any_groups_are_joined_with_or() {
+        let sql = compile(serde_json::json!({
+            "any": [{ "locale": "pt-BR" }, { "tag": "beta" }]
+        }));
+        assert!(sql.starts_with("((s.locale = $1) OR EXISTS"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use actix_session::storage::{
+    generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError,
+};
+use actix_web::cookie::time::Duration;
+
+type SessionState = HashMap<String, String>;
+
+// Cloning the store shares the underlying map, every actix worker sees the same sessions.
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<Mutex<HashMap<String, (SessionState, Instant)>>>,
+}
+
+fn expires_at(ttl: &Duration) -> Instant {
+    Instant::now() + std::time::Duration::from_secs(ttl.whole_seconds().max(0) as u64)
+}
+
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions
+            .get(session_key.as_ref())
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(state, _)| state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        self.sessions.lock().unwrap().insert(
+            session_key.as_ref().to_owned(),
+            (session_state, expires_at(ttl)),
+        );
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(session_key.as_ref()) {
+            Some(entry) if entry.1 > Instant::now() => {
+                *entry = (session_state, expires_at(ttl));
+                Ok(session_key)
+            }
+            // The session expired in the meantime, store the state under a brand new key.
+            _ => {
+                let session_key = generate_session_key();
+                sessions.insert(
+                    session_key.as_ref().to_owned(),
+                    (session_state, expires_at(ttl)),
+                );
+                Ok(session_key)
+            }
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(entry) = self.sessions.lock().unwrap().get_mut(session_key.as_ref()) {
+            entry.1 = expires_at(ttl);
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.sessions.lock().unwrap().remove(session_key.as_ref());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_session::storage::SessionStore;
+    use actix_web::cookie::time::Duration;
+    use claims::{assert_none, assert_ok, assert_some_eq};
+
+    use super::InMemorySessionStore;
+
+    fn state() -> HashMap<String, String> {
+        HashMap::from([("user_id".to_string(), "\"42\"".to_string())])
+    }
+
+    #[tokio::test]
+    async fn a_saved_session_can_be_loaded() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::hours(1)).await.unwrap();
+
+        assert_some_eq!(store.load(&key).await.unwrap(), state());
+    }
+
+    #[tokio::test]
+    async fn an_expired_session_is_not_loaded() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::ZERO).await.unwrap();
+
+        assert_none!(store.load(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_deleted_session_is_not_loaded() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::hours(1)).await.unwrap();
+
+        assert_ok!(store.delete(&key).await);
+        assert_none!(store.load(&key).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_same_sessions() {
+        let store = InMemorySessionStore::default();
+        let key = store.save(state(), &Duration::hours(1)).await.unwrap();
+
+        assert_some_eq!(store.clone().load(&key).await.unwrap(), state());
+    }
+}
@@ -0,0 +1,10 @@
+// Server-side storage for `actix-session`.
+//
+// The cookie only carries an opaque session key, the state itself lives behind the
+// `actix_session::storage::SessionStore` trait, so the backend can be swapped without touching
+// the routes.
+mod memory;
+mod postgres;
+
+pub use memory::InMemorySessionStore;
+pub use postgres::PostgresSessionStore;
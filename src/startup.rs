@@ -1,11 +1,15 @@
-use actix_web::{dev::Server, web, App, HttpServer};
+use actix_session::{storage::SessionStore, SessionMiddleware};
+use actix_web::{cookie::Key, dev::Server, web, App, HttpServer};
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    configuration::{AppSettings, DatabaseSettings, ExpirySettings, SessionStoreKind, Settings},
     email_client::EmailClient,
+    email_templates::EmailTemplates,
     routes,
+    session_store::{InMemorySessionStore, PostgresSessionStore},
 };
 
 // new type to hold the application and its port
@@ -16,20 +20,18 @@ pub struct Application {
 
 impl Application {
     // convert `build` into an `App` struct constructor
-    pub async fn build(config: Settings) -> Result<Self, std::io::Error> {
+    // The email client is handed over, rather than built here, so that the background worker
+    // can share it, and its rate limits, with the API.
+    pub async fn build(
+        config: Settings,
+        email_client: EmailClient,
+    ) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&config.database);
-
-        let sender_email = config
-            .email_client
-            .sender()
-            .expect("Failed to parse sender email");
-        let timeout = config.email_client.timeout();
-        let email_client = EmailClient::new(
-            config.email_client.base_url,
-            sender_email,
-            config.email_client.auth_token,
-            timeout,
-        );
+        // Refuse to start without all of the email templates.
+        let email_templates = config
+            .email_templates
+            .load()
+            .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
 
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = std::net::TcpListener::bind(address)?;
@@ -37,12 +39,26 @@ impl Application {
 
         // Bubble up the io::Error  if we failed to bind the address
         // Otherwise call .await on the Server
-        let server = run(
-            listener,
-            connection_pool,
-            email_client,
-            config.application.base_url,
-        )?;
+        let server = match config.application.session_store {
+            SessionStoreKind::Postgres => run(
+                listener,
+                connection_pool.clone(),
+                email_client,
+                email_templates,
+                config.application,
+                config.expiry,
+                PostgresSessionStore::new(connection_pool),
+            )?,
+            SessionStoreKind::InMemory => run(
+                listener,
+                connection_pool,
+                email_client,
+                email_templates,
+                config.application,
+                config.expiry,
+                InMemorySessionStore::default(),
+            )?,
+        };
         Ok(Self { port, server })
     }
 
@@ -69,25 +85,116 @@ pub fn get_connection_pool(config: &DatabaseSettings) -> PgPool {
 
 pub struct AppBaseUrl(pub String);
 
-fn run(
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+fn run<S>(
     listener: std::net::TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-) -> Result<Server, std::io::Error> {
-    let base_url = web::Data::new(AppBaseUrl(base_url));
+    email_templates: EmailTemplates,
+    application: AppSettings,
+    expiry: ExpirySettings,
+    session_store: S,
+) -> Result<Server, std::io::Error>
+where
+    // Each actix worker gets its own copy of the store.
+    S: SessionStore + Clone + Send + 'static,
+{
+    let unsubscribe_links = web::Data::new(application.unsubscribe_links());
+    let tracking_links = web::Data::new(application.tracking_links());
+    let base_url = web::Data::new(AppBaseUrl(application.base_url));
+    let hmac_secret = application.hmac_secret;
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
+    let email_templates = web::Data::new(email_templates);
+    let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let expiry = web::Data::new(expiry);
+    let webhooks = web::Data::new(application.webhooks);
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                secret_key.clone(),
+            ))
             // instead of Logger:default()
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(routes::health_check))
+            .route("/login", web::get().to(routes::login_form))
+            .route("/login", web::post().to(routes::login))
+            .service(
+                web::scope("/admin")
+                    .route("/dashboard", web::get().to(routes::admin_dashboard))
+                    .route(
+                        "/issues/{issue_id}/preview",
+                        web::get().to(routes::preview_issue),
+                    )
+                    .route(
+                        "/issues/{issue_id}/test-send",
+                        web::post().to(routes::test_send_issue),
+                    )
+                    .route("/password", web::get().to(routes::change_password_form))
+                    .route("/password", web::post().to(routes::change_password))
+                    .route("/logout", web::post().to(routes::log_out)),
+            )
             .route("/subscriptions", web::post().to(routes::subscribe))
             .route("/subscriptions/confirm", web::get().to(routes::confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(routes::unsubscribe),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::post().to(routes::unsubscribe_one_click),
+            )
+            .route("/lists", web::post().to(routes::create_list))
+            .route(
+                "/subscribers/{subscriber_id}/tags",
+                web::get().to(routes::get_subscriber_tags),
+            )
+            .route(
+                "/subscribers/{subscriber_id}/tags",
+                web::post().to(routes::add_subscriber_tags),
+            )
+            .route(
+                "/subscribers/{subscriber_id}/tags/{tag}",
+                web::delete().to(routes::remove_subscriber_tag),
+            )
+            .route("/segments/preview", web::post().to(routes::preview_segment))
+            .route("/newsletters", web::post().to(routes::publish_newsletter))
+            .route("/newsletters/drafts", web::post().to(routes::create_draft))
+            .route("/newsletters/{issue_id}", web::put().to(routes::edit_draft))
+            .route(
+                "/newsletters/{issue_id}/schedule",
+                web::post().to(routes::schedule_issue),
+            )
+            .route(
+                "/newsletters/{issue_id}/unschedule",
+                web::post().to(routes::unschedule_issue),
+            )
+            .route(
+                "/newsletters/{issue_id}/send",
+                web::post().to(routes::send_issue),
+            )
+            .route(
+                "/newsletters/{issue_id}/cancel",
+                web::post().to(routes::cancel_issue),
+            )
+            .route("/archive", web::get().to(routes::archive))
+            .route("/archive/{slug}", web::get().to(routes::archived_issue))
+            .route("/webhooks/email", web::post().to(routes::email_webhook))
+            .route("/t/c/{token}", web::get().to(routes::track_click))
+            .route("/t/o/{token}", web::get().to(routes::track_open))
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_templates.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(expiry.clone())
+            .app_data(unsubscribe_links.clone())
+            .app_data(webhooks.clone())
+            .app_data(tracking_links.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,251 @@
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::{IllegalStatusTransition, SubscriptionStatus},
+    transactional_email_worker::{enqueue_email, TransactionalEmail},
+    util::error_chain_fmt,
+};
+
+#[derive(thiserror::Error)]
+pub enum StatusUpdateError {
+    #[error("There is no subscriber with id {0}")]
+    UnknownSubscriber(Uuid),
+    #[error(transparent)]
+    IllegalTransition(#[from] IllegalStatusTransition),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for StatusUpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// Move a subscriber to `next`, as long as `SubscriptionStatus` allows it.
+// The row is locked while we check the transition, concurrent updates of the same subscriber
+// (e.g. a confirmation racing an unsubscription) are applied one after the other.
+#[tracing::instrument(name = "Update subscriber status", skip(pool))]
+pub async fn update_subscriber_status(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    next: SubscriptionStatus,
+) -> Result<(), StatusUpdateError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    set_subscriber_status(&mut transaction, subscriber_id, next).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update the subscriber status")?;
+    Ok(())
+}
+
+// Confirming a subscription confirms the address, and the subscription to that list only.
+// The subscriber is welcomed to the list the first time around, not on every click.
+#[tracing::instrument(name = "Confirm a list membership", skip(pool))]
+pub async fn confirm_list_membership(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), StatusUpdateError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    set_subscriber_status(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionStatus::Confirmed,
+    )
+    .await?;
+    let current = sqlx::query!(
+        r#"
+        SELECT status FROM list_memberships
+        WHERE subscriber_id = $1 AND list_id = $2
+        FOR UPDATE
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to read the list membership status")?
+    .ok_or(StatusUpdateError::UnknownSubscriber(subscriber_id))?;
+    let current = SubscriptionStatus::try_from(current.status).map_err(anyhow::Error::msg)?;
+    current.transition_to(SubscriptionStatus::Confirmed)?;
+    sqlx::query!(
+        "UPDATE list_memberships SET status = $1 WHERE subscriber_id = $2 AND list_id = $3",
+        SubscriptionStatus::Confirmed.as_str(),
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the list membership status")?;
+    if current != SubscriptionStatus::Confirmed {
+        enqueue_email(
+            &mut transaction,
+            TransactionalEmail::Welcome,
+            subscriber_id,
+            list_id,
+        )
+        .await
+        .context("Failed to enqueue the welcome email")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a list membership")?;
+    Ok(())
+}
+
+// Unsubscribing leaves one list. The address is unsubscribed too once it is on no list
+// anymore, pending or confirmed. Leaving a confirmed subscription is acknowledged by email.
+#[tracing::instrument(name = "Leave a list", skip(pool))]
+pub async fn leave_list(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), StatusUpdateError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire Postgres connection from the pool")?;
+    let current = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to read the subscriber status")?
+    .ok_or(StatusUpdateError::UnknownSubscriber(subscriber_id))?;
+    let current = SubscriptionStatus::try_from(current.status).map_err(anyhow::Error::msg)?;
+    // Bounced and complained addresses have left every list already, for good.
+    current.transition_to(SubscriptionStatus::Unsubscribed)?;
+    let membership = sqlx::query!(
+        r#"
+        SELECT status FROM list_memberships
+        WHERE subscriber_id = $1 AND list_id = $2
+        FOR UPDATE
+        "#,
+        subscriber_id,
+        list_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to read the list membership status")?;
+    if let Some(membership) = membership {
+        let membership =
+            SubscriptionStatus::try_from(membership.status).map_err(anyhow::Error::msg)?;
+        membership.transition_to(SubscriptionStatus::Unsubscribed)?;
+        sqlx::query!(
+            "UPDATE list_memberships SET status = $1 WHERE subscriber_id = $2 AND list_id = $3",
+            SubscriptionStatus::Unsubscribed.as_str(),
+            subscriber_id,
+            list_id
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to update the list membership status")?;
+        if membership == SubscriptionStatus::Confirmed {
+            enqueue_email(
+                &mut transaction,
+                TransactionalEmail::UnsubscribeConfirmation,
+                subscriber_id,
+                list_id,
+            )
+            .await
+            .context("Failed to enqueue the unsubscribe confirmation email")?;
+        }
+    }
+    let remaining = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM list_memberships
+        WHERE subscriber_id = $1 AND status = ANY($2)
+        "#,
+        subscriber_id,
+        &[
+            SubscriptionStatus::PendingConfirmation.as_str().to_owned(),
+            SubscriptionStatus::Confirmed.as_str().to_owned(),
+        ]
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to count the remaining list memberships")?;
+    if remaining.count == 0 {
+        set_subscriber_status(
+            &mut transaction,
+            subscriber_id,
+            SubscriptionStatus::Unsubscribed,
+        )
+        .await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to leave a list")?;
+    Ok(())
+}
+
+// `update_subscriber_status`, as part of the caller's transaction.
+pub async fn set_subscriber_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    next: SubscriptionStatus,
+) -> Result<(), StatusUpdateError> {
+    let current = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to read the subscriber status")?
+    .ok_or(StatusUpdateError::UnknownSubscriber(subscriber_id))?;
+    let current = SubscriptionStatus::try_from(current.status).map_err(anyhow::Error::msg)?;
+    current.transition_to(next)?;
+    if current != next {
+        sqlx::query!(
+            "UPDATE subscriptions SET status = $1 WHERE id = $2",
+            next.as_str(),
+            subscriber_id
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to update the subscriber status")?;
+    }
+    // Bouncing and complaining are about the address: every list is left. Unsubscribing is
+    // about one list, see `leave_list`.
+    if matches!(
+        next,
+        SubscriptionStatus::Bounced | SubscriptionStatus::Complained
+    ) {
+        let leaving: Vec<String> = [
+            SubscriptionStatus::PendingConfirmation,
+            SubscriptionStatus::Confirmed,
+            SubscriptionStatus::Unsubscribed,
+        ]
+        .into_iter()
+        .filter(|status| *status != next && status.can_transition_to(next))
+        .map(|status| status.as_str().to_owned())
+        .collect();
+        sqlx::query!(
+            r#"
+            UPDATE list_memberships
+            SET status = $2
+            WHERE subscriber_id = $1 AND status = ANY($3)
+            "#,
+            subscriber_id,
+            next.as_str(),
+            &leaving
+        )
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to update the list membership statuses")?;
+    }
+    Ok(())
+}
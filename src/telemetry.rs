@@ -1,3 +1,4 @@
+use tokio::task::JoinHandle;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
@@ -45,3 +46,14 @@ pub fn init_subscriber(subscriber: impl Subscriber + Sync + Send) {
     // `set_global_default` is used to specify which subscriber should be used to process spans.
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+// `spawn_blocking` runs the closure on a different thread, which does not inherit the current
+// span. Attach it explicitly so the work shows up under the caller's span.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
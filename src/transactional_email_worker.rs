@@ -0,0 +1,406 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::{
+    configuration::Settings,
+    domain::{SubscriberEmail, SubscriberLocale, SubscriptionStatus, UnsubscribeLinks},
+    email_client::{EmailClient, EmailMessage, SendEmailError},
+    email_templates::{EmailTemplate, EmailTemplates},
+    issue_delivery_worker::ExecutionOutcome,
+    startup::get_connection_pool,
+    subscriber_status::update_subscriber_status,
+};
+
+// Same policy as issue deliveries: up to this many attempts, the n-th one `2^n` minutes later.
+const MAX_DELIVERY_RETRIES: i16 = 5;
+
+// The emails a subscriber gets because of something they did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionalEmail {
+    // The link to confirm a subscription to a list.
+    Confirmation,
+    // The subscription to a list is confirmed.
+    Welcome,
+    // A list was left.
+    UnsubscribeConfirmation,
+}
+
+impl TransactionalEmail {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionalEmail::Confirmation => "confirmation",
+            TransactionalEmail::Welcome => "welcome",
+            TransactionalEmail::UnsubscribeConfirmation => "unsubscribe_confirmation",
+        }
+    }
+}
+
+impl TryFrom<String> for TransactionalEmail {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "confirmation" => Ok(Self::Confirmation),
+            "welcome" => Ok(Self::Welcome),
+            "unsubscribe_confirmation" => Ok(Self::UnsubscribeConfirmation),
+            other => Err(format!("{} is not a transactional email", other)),
+        }
+    }
+}
+
+// Queued in the caller's transaction: the email goes out if, and only if, what it is about
+// is committed.
+#[tracing::instrument(skip(transaction))]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    kind: TransactionalEmail,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO transactional_email_queue (id, kind, subscriber_id, list_id)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        Uuid::new_v4(),
+        kind.as_str(),
+        subscriber_id,
+        list_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: EmailClient,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    let email_templates = configuration.email_templates.load()?;
+    let unsubscribe_links = configuration.application.unsubscribe_links();
+    loop {
+        match try_execute_task(
+            &connection_pool,
+            &email_client,
+            &email_templates,
+            &unsubscribe_links,
+            &configuration.application.base_url,
+        )
+        .await
+        {
+            // Somebody is waiting for these, poll more often than for issue deliveries.
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        email_kind = tracing::field::Empty,
+        subscriber_id = tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    unsubscribe_links: &UnsubscribeLinks,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let Some((transaction, task)) = dequeue_task(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    Span::current()
+        .record("email_kind", display(task.kind.as_str()))
+        .record("subscriber_id", display(task.subscriber_id));
+    let Some(data) = get_email_data(pool, &task).await? else {
+        tracing::info!("Skipping an email whose subscriber is gone");
+        delete_task(transaction, task.id).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+    let message = match render(email_templates, unsubscribe_links, base_url, &task, data) {
+        Ok(Some(message)) => message,
+        Ok(None) => {
+            tracing::info!("Skipping an email that is no longer relevant");
+            delete_task(transaction, task.id).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        // What is stored, or the templates, will not be any better on the next attempt.
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to render a transactional email. Skipping.",
+            );
+            delete_task(transaction, task.id).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    if let Err(e) = email_client.send(message).await {
+        return handle_delivery_failure(pool, transaction, &task, e).await;
+    }
+    delete_task(transaction, task.id).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// What an email is made of, as stored.
+struct EmailData {
+    email: String,
+    name: String,
+    locale: String,
+    status: String,
+    membership_status: Option<String>,
+    // Confirmations only.
+    subscription_token: Option<String>,
+}
+
+// `None` if the subscriber is gone.
+async fn get_email_data(
+    pool: &PgPool,
+    task: &EmailTask,
+) -> Result<Option<EmailData>, anyhow::Error> {
+    let Some(subscriber) = sqlx::query!(
+        "SELECT email, name, locale, status FROM subscriptions WHERE id = $1",
+        task.subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to read the recipient of a transactional email")?
+    else {
+        return Ok(None);
+    };
+    let membership_status = sqlx::query!(
+        "SELECT status FROM list_memberships WHERE subscriber_id = $1 AND list_id = $2",
+        task.subscriber_id,
+        task.list_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to read the list membership status")?
+    .map(|m| m.status);
+    let subscription_token = match task.kind {
+        // The latest token: subscribing again while pending rotates it.
+        TransactionalEmail::Confirmation => sqlx::query!(
+            r#"
+            SELECT subscription_token FROM subscription_tokens
+            WHERE subscriber_id = $1 AND list_id = $2
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+            task.subscriber_id,
+            task.list_id
+        )
+        .fetch_optional(pool)
+        .await
+        .context("Failed to read the subscription token")?
+        .map(|t| t.subscription_token),
+        TransactionalEmail::Welcome | TransactionalEmail::UnsubscribeConfirmation => None,
+    };
+    Ok(Some(EmailData {
+        email: subscriber.email,
+        name: subscriber.name,
+        locale: subscriber.locale,
+        status: subscriber.status,
+        membership_status,
+        subscription_token,
+    }))
+}
+
+// `None` if the email should not be sent anymore, e.g. the subscriber bounced in the meantime.
+fn render(
+    email_templates: &EmailTemplates,
+    unsubscribe_links: &UnsubscribeLinks,
+    base_url: &str,
+    task: &EmailTask,
+    data: EmailData,
+) -> Result<Option<EmailMessage>, anyhow::Error> {
+    let status = SubscriptionStatus::try_from(data.status).map_err(anyhow::Error::msg)?;
+    // We must not email those addresses again.
+    if matches!(
+        status,
+        SubscriptionStatus::Bounced | SubscriptionStatus::Complained
+    ) {
+        return Ok(None);
+    }
+    let recipient = SubscriberEmail::parse(data.email).map_err(anyhow::Error::msg)?;
+    // Stored locales were validated on the way in, this is only a safety net.
+    let locale = SubscriberLocale::parse(data.locale).unwrap_or_default();
+    let membership = data
+        .membership_status
+        .map(SubscriptionStatus::try_from)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    let name = data.name;
+    let message = match task.kind {
+        TransactionalEmail::Confirmation => {
+            let Some(token) = data.subscription_token else {
+                return Ok(None);
+            };
+            let confirmation_link = format!(
+                "{}/subscriptions/confirm?subscription_token={}",
+                base_url, token
+            );
+            let email = email_templates.render(
+                EmailTemplate::Confirmation,
+                &locale,
+                minijinja::context! { name, confirmation_link },
+            )?;
+            EmailMessage::new(&recipient, email.subject, email.html_body, email.text_body)
+                .unsubscribe_link(unsubscribe_links.link_for(task.subscriber_id, task.list_id))
+                .tag("confirmation")
+        }
+        TransactionalEmail::Welcome => {
+            // Left the list, or was removed from it, before we got to it.
+            if membership != Some(SubscriptionStatus::Confirmed) {
+                return Ok(None);
+            }
+            let email = email_templates.render(
+                EmailTemplate::Welcome,
+                &locale,
+                minijinja::context! { name },
+            )?;
+            EmailMessage::new(&recipient, email.subject, email.html_body, email.text_body)
+                .unsubscribe_link(unsubscribe_links.link_for(task.subscriber_id, task.list_id))
+                .tag("welcome")
+        }
+        TransactionalEmail::UnsubscribeConfirmation => {
+            // Joined the list again before we got to it.
+            if membership != Some(SubscriptionStatus::Unsubscribed) {
+                return Ok(None);
+            }
+            let email = email_templates.render(
+                EmailTemplate::UnsubscribeConfirmation,
+                &locale,
+                minijinja::context! { name },
+            )?;
+            // Nothing left to unsubscribe from.
+            EmailMessage::new(&recipient, email.subject, email.html_body, email.text_body)
+                .tag("unsubscribe-confirmation")
+        }
+    };
+    Ok(Some(message))
+}
+
+// Like issue deliveries: rescheduled for later, recipient marked as bounced, or dropped.
+async fn handle_delivery_failure(
+    pool: &PgPool,
+    transaction: PgTransaction,
+    task: &EmailTask,
+    e: SendEmailError,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    if e.is_undeliverable_recipient() {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "The recipient is undeliverable, marking the subscriber as bounced",
+        );
+        // e.g. an unsubscribed address cannot bounce. The task goes either way, or it would be
+        // sent again, and again.
+        if let Err(e) =
+            update_subscriber_status(pool, task.subscriber_id, SubscriptionStatus::Bounced).await
+        {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to mark the subscriber as bounced",
+            );
+        }
+    } else if e.is_retryable() && task.n_retries < MAX_DELIVERY_RETRIES {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            n_retries = task.n_retries,
+            "Failed to send a transactional email. Retrying later.",
+        );
+        reschedule_task(transaction, task.id, task.n_retries + 1).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    } else {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a transactional email. Skipping.",
+        );
+    }
+    delete_task(transaction, task.id).await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct EmailTask {
+    id: Uuid,
+    kind: TransactionalEmail,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    n_retries: i16,
+}
+
+// See `issue_delivery_worker::dequeue_task`, the lock is held until the task is deleted.
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(pool: &PgPool) -> Result<Option<(PgTransaction, EmailTask)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query!(
+        r#"
+        SELECT id, kind, subscriber_id, list_id, n_retries
+        FROM transactional_email_queue
+        WHERE execute_after <= now()
+        ORDER BY execute_after
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(task) = task else {
+        return Ok(None);
+    };
+    let task = EmailTask {
+        id: task.id,
+        kind: TransactionalEmail::try_from(task.kind).map_err(anyhow::Error::msg)?,
+        subscriber_id: task.subscriber_id,
+        list_id: task.list_id,
+        n_retries: task.n_retries,
+    };
+    Ok(Some((transaction, task)))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn delete_task(mut transaction: PgTransaction, id: Uuid) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!("DELETE FROM transactional_email_queue WHERE id = $1", id);
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn reschedule_task(
+    mut transaction: PgTransaction,
+    id: Uuid,
+    n_retries: i16,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE transactional_email_queue
+        SET
+            n_retries = $2,
+            execute_after = now() + make_interval(mins => $3)
+        WHERE id = $1
+        "#,
+        id,
+        n_retries,
+        2i32.pow(n_retries as u32)
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use actix_web::{http::header::LOCATION, HttpResponse};
+
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
@@ -10,3 +12,18 @@ pub fn error_chain_fmt(
     }
     Ok(())
 }
+
+// Return a 303 See Other redirect, used after a form submission.
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
+
+// Return an opaque 500 while preserving the error's root cause for logging.
+pub fn e500<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorInternalServerError(e)
+}
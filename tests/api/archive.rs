@@ -0,0 +1,114 @@
+use crate::helpers::{spawn_app, TestApp};
+
+fn issue_body(title: &str, html: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": html,
+        }
+    })
+}
+
+// Returns the id and slug of the new draft.
+async fn create_draft(app: &TestApp, title: &str, html: &str) -> (String, String) {
+    let summary: serde_json::Value = app
+        .post_newsletter_draft(issue_body(title, html))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    (
+        summary["newsletter_issue_id"].as_str().unwrap().to_owned(),
+        summary["slug"].as_str().unwrap().to_owned(),
+    )
+}
+
+#[tokio::test]
+async fn the_archive_lists_sent_issues_only() {
+    // Arrange
+    let app = spawn_app().await;
+    let (issue_id, sent_slug) = create_draft(&app, "Fish & chips", "<p>Sent</p>").await;
+    app.post_newsletter_action(&issue_id, "send", serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+    let (_, draft_slug) = create_draft(&app, "Still a draft", "<p>Draft</p>").await;
+
+    // Act
+    let response = reqwest::get(format!("{}/archive", &app.addr))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(&format!(r#"href="/archive/{}""#, sent_slug)));
+    assert!(html.contains("Fish &amp; chips"));
+    assert!(!html.contains(&draft_slug));
+}
+
+#[tokio::test]
+async fn sent_issues_are_served_from_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    let (issue_id, slug) = create_draft(&app, "March news", "<p>Sent to everyone</p>").await;
+    app.post_newsletter_action(&issue_id, "send", serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(format!("{}/archive/{}", &app.addr, slug))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<h1>March news</h1>"));
+    assert!(html.contains("<p>Sent to everyone</p>"));
+}
+
+#[tokio::test]
+async fn name_placeholders_are_filled_in_in_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    let (issue_id, slug) = create_draft(&app, "March news", "<p>Hi {{ name }}!</p>").await;
+    app.post_newsletter_action(&issue_id, "send", serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(format!("{}/archive/{}", &app.addr, slug))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<p>Hi reader!</p>"));
+    assert!(!html.contains("{{ name }}"));
+}
+
+#[tokio::test]
+async fn issues_that_were_not_sent_are_not_in_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, slug) = create_draft(&app, "March news", "<p>Draft</p>").await;
+
+    // Act
+    let draft = reqwest::get(format!("{}/archive/{}", &app.addr, slug))
+        .await
+        .unwrap();
+    let unknown = reqwest::get(format!("{}/archive/unknown", &app.addr))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(draft.status().as_u16(), 404);
+    assert_eq!(unknown.status().as_u16(), 404);
+}
@@ -0,0 +1,160 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn expired_subscription_tokens_are_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_subscriptions("name=carlos%20jose&email=carlos.cruz%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - make_interval(secs => $1)
+        WHERE subscriber_id = (SELECT id FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com')",
+        app.expiry.subscription_token_retention().as_secs_f64() + 60.
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = app.delete_expired_records().await;
+
+    // Assert
+    assert_eq!(outcome.deleted_subscription_tokens, 1);
+    let remaining = sqlx::query!(
+        "SELECT s.email FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].email, "carlos.cruz@gmail.com");
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_still_say_so_after_a_cleanup() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - make_interval(secs => $1)",
+        (app.expiry.subscription_token_ttl_seconds + 60) as f64
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = app.delete_expired_records().await;
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(outcome.deleted_subscription_tokens, 0);
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn expired_idempotency_records_are_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    let expired_key = Uuid::new_v4().to_string();
+    let fresh_key = Uuid::new_v4().to_string();
+    app.post_newsletters_with_idempotency_key(newsletter_request_body(), &expired_key)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_newsletters_with_idempotency_key(newsletter_request_body(), &fresh_key)
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        "UPDATE idempotency SET created_at = now() - make_interval(secs => $1)
+        WHERE idempotency_key = $2",
+        (app.expiry.idempotency_ttl_seconds + 60) as f64,
+        expired_key
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let outcome = app.delete_expired_records().await;
+
+    // Assert
+    assert_eq!(outcome.deleted_idempotency_records, 1);
+    let remaining = sqlx::query!("SELECT idempotency_key FROM idempotency")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].idempotency_key, fresh_key);
+}
+
+#[tokio::test]
+async fn an_expired_idempotency_key_can_be_reused() {
+    // Arrange
+    let app = spawn_app().await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!(
+        "UPDATE idempotency SET created_at = now() - make_interval(secs => $1)",
+        (app.expiry.idempotency_ttl_seconds + 60) as f64
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.delete_expired_records().await;
+
+    // Act
+    app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let issues = sqlx::query!("SELECT COUNT(*) AS count FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, Some(2));
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
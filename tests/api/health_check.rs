@@ -1,7 +1,7 @@
 use crate::helpers::spawn_app;
 
 #[tokio::test]
-async fn health_check_works() -> () {
+async fn health_check_works() {
     // Arrange
     let app = spawn_app().await;
     let client = reqwest::Client::new();
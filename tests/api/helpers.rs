@@ -1,11 +1,23 @@
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{MockServer, Respond, ResponseTemplate};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    cleanup_worker::{delete_expired_records, CleanupOutcome},
+    configuration::{
+        get_configuration, DatabaseSettings, EmailTransportSettings, ExpirySettings,
+        SessionStoreKind, WebhookSettings,
+    },
+    domain::{TrackingLinks, UnsubscribeLinks},
+    email_client::EmailClient,
+    email_templates::EmailTemplates,
+    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    issue_scheduler::publish_due_issues,
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
+    transactional_email_worker,
 };
 
 // Create a static item `TRACING` which is available for the entire duration of the program
@@ -28,47 +40,473 @@ static TRACING: once_cell::sync::Lazy<()> = Lazy::new(|| {
 
 pub struct TestApp {
     pub addr: String,
+    pub base_url: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub port: u16,
+    pub email_client: EmailClient,
+    pub email_templates: EmailTemplates,
+    pub test_user: TestUser,
+    pub expiry: ExpirySettings,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub tracking_links: TrackingLinks,
+    pub webhooks: WebhookSettings,
+    // Keeps cookies between requests and does not follow redirects, like a browser session
+    // where we want to assert on each redirect.
+    pub api_client: reqwest::Client,
 }
 
-pub struct ConfirmationLinks {
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        // Match the parameters used in production, we are not testing Argon2's defaults.
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash)
+            VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
+pub struct EmailLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
 }
 
 impl TestApp {
+    // The background worker is not running in tests, drain the delivery queue on demand.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.unsubscribe_links,
+                &self.tracking_links,
+                &self.email_templates,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    // Nor is the one sending transactional emails (confirmations, ...).
+    pub async fn dispatch_transactional_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = transactional_email_worker::try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.email_templates,
+                &self.unsubscribe_links,
+                &self.base_url,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    // Nor is the scheduler, publish the issues due on demand. Returns how many started sending.
+    pub async fn publish_due_issues(&self) -> u64 {
+        publish_due_issues(&self.db_pool).await.unwrap()
+    }
+
+    // The cleanup task is not running in tests either, trigger a pass on demand.
+    pub async fn delete_expired_records(&self) -> CleanupOutcome {
+        delete_expired_records(&self.db_pool, &self.expiry)
+            .await
+            .unwrap()
+    }
+
+    // The confirmation email goes out in the background, these wait for it to be sent (or not).
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
-        reqwest::Client::new()
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.addr))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request to /subscriptions.");
+        self.dispatch_transactional_emails().await;
+        response
+    }
+
+    pub async fn post_subscriptions_with_idempotency_key(
+        &self,
+        body: String,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        let response = reqwest::Client::new()
             .post(format!("{}/subscriptions", &self.addr))
             .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", idempotency_key)
             .body(body)
             .send()
             .await
-            .expect("Failed to execute request to /subscriptions.")
+            .expect("Failed to execute request to /subscriptions.");
+        self.dispatch_transactional_emails().await;
+        response
+    }
+
+    pub async fn post_subscriptions_with_accept_language(
+        &self,
+        body: String,
+        accept_language: &str,
+    ) -> reqwest::Response {
+        let response = reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.addr))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept-Language", accept_language)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request to /subscriptions.");
+        self.dispatch_transactional_emails().await;
+        response
+    }
+
+    // Confirming a subscription queues a welcome email, this sends it as well.
+    pub async fn confirm_subscription(&self, confirmation_link: reqwest::Url) -> reqwest::Response {
+        let response = reqwest::get(confirmation_link)
+            .await
+            .expect("Failed to execute request to /subscriptions/confirm.");
+        self.dispatch_transactional_emails().await;
+        response
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.addr))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request to /login.")
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.addr))
+            .send()
+            .await
+            .expect("Failed to execute request to /login.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.addr))
+            .send()
+            .await
+            .expect("Failed to execute request to /admin/dashboard.")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.addr))
+            .send()
+            .await
+            .expect("Failed to execute request to /admin/password.")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.addr))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request to /admin/password.")
+    }
+
+    pub async fn get_issue_preview(&self, issue_id: &str, subscriber: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/issues/{}/preview", &self.addr, issue_id))
+            .query(&[("subscriber", subscriber)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_issue_test_send(
+        &self,
+        issue_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/issues/{}/test-send",
+                &self.addr, issue_id
+            ))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.addr))
+            .send()
+            .await
+            .expect("Failed to execute request to /admin/logout.")
+    }
+
+    pub async fn login_test_user(&self) {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password,
+        }))
+        .await;
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.addr))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /newsletters.")
+    }
+
+    pub async fn post_lists(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/lists", &self.addr))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /lists.")
+    }
+
+    pub async fn post_subscriber_tags(
+        &self,
+        subscriber_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscribers/{}/tags", &self.addr, subscriber_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /subscribers/{subscriber_id}/tags.")
+    }
+
+    pub async fn delete_subscriber_tag(&self, subscriber_id: &str, tag: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/subscribers/{}/tags/{}",
+                &self.addr, subscriber_id, tag
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request to /subscribers/{subscriber_id}/tags/{tag}.")
+    }
+
+    pub async fn post_segment_preview(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/segments/preview", &self.addr))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /segments/preview.")
+    }
+
+    pub async fn post_newsletter_draft(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters/drafts", &self.addr))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /newsletters/drafts.")
+    }
+
+    pub async fn put_newsletter_draft(
+        &self,
+        issue_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/newsletters/{}", &self.addr, issue_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /newsletters/{issue_id}.")
+    }
+
+    // `action` is one of `schedule`, `unschedule`, `send` and `cancel`.
+    pub async fn post_newsletter_action(
+        &self,
+        issue_id: &str,
+        action: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/newsletters/{}/{}",
+                &self.addr, issue_id, action
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /newsletters/{issue_id}/{action}.")
+    }
+
+    // Posted by the email provider, authenticated with `Basic` auth.
+    pub async fn post_email_webhook(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/email", &self.addr))
+            .basic_auth(
+                &self.webhooks.username,
+                Some(self.webhooks.secret.expose_secret()),
+            )
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /webhooks/email.")
     }
 
-    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
-        let body: serde_json::Value =
-            serde_json::from_slice(&email_request.body).expect("Failed to parse body");
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.addr))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request to /newsletters.")
+    }
+
+    // Issues and their test copies go out through Postmark's batch endpoint, these are the
+    // emails of every batch sent so far, in order.
+    pub async fn issue_emails(&self) -> Vec<serde_json::Value> {
+        self.email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.url.path() == "/email/batch")
+            .flat_map(|r| {
+                serde_json::from_slice::<Vec<serde_json::Value>>(&r.body)
+                    .expect("Failed to parse batch body")
+            })
+            .collect()
+    }
+
+    // Every email also carries an unsubscribe link, only pick the links to `path`.
+    fn get_links_to(&self, email: &serde_json::Value, path: &str) -> EmailLinks {
         let get_link = |s: &str| {
             let links: Vec<_> = linkify::LinkFinder::new()
                 .links(s)
                 .filter(|link| *link.kind() == linkify::LinkKind::Url)
+                .filter(|link| link.as_str().contains(path))
                 .collect();
             assert_eq!(links.len(), 1);
 
             let raw_link = links[0].as_str();
-            let mut confirmation_link = reqwest::Url::parse(raw_link).unwrap();
-            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            let mut link = reqwest::Url::parse(raw_link).unwrap();
+            assert_eq!(link.host_str().unwrap(), "127.0.0.1");
             // include the port
-            confirmation_link.set_port(Some(self.port)).unwrap();
-            confirmation_link
+            link.set_port(Some(self.port)).unwrap();
+            link
         };
-        let html = get_link(body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
-        ConfirmationLinks { html, plain_text }
+        let html = get_link(email["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(email["TextBody"].as_str().unwrap());
+        EmailLinks { html, plain_text }
+    }
+
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> EmailLinks {
+        self.get_links_to(&request_body(email_request), "/subscriptions/confirm")
+    }
+
+    pub fn get_unsubscribe_links(&self, email_request: &wiremock::Request) -> EmailLinks {
+        self.get_links_to(&request_body(email_request), "/subscriptions/unsubscribe")
+    }
+
+    // Same, for one of the `issue_emails`.
+    pub fn get_issue_unsubscribe_links(&self, email: &serde_json::Value) -> EmailLinks {
+        self.get_links_to(email, "/subscriptions/unsubscribe")
+    }
+}
+
+fn request_body(email_request: &wiremock::Request) -> serde_json::Value {
+    serde_json::from_slice(&email_request.body).expect("Failed to parse body")
+}
+
+// Answers Postmark's batch endpoint with the same result for every email of the batch.
+pub struct PostmarkBatchResponse(serde_json::Value);
+
+impl PostmarkBatchResponse {
+    pub fn accepted() -> Self {
+        Self(serde_json::json!({"ErrorCode": 0, "Message": "OK"}))
+    }
+
+    pub fn rejected(result: serde_json::Value) -> Self {
+        Self(result)
+    }
+}
+
+impl Respond for PostmarkBatchResponse {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let emails: Vec<serde_json::Value> =
+            serde_json::from_slice(&request.body).expect("Failed to parse batch body");
+        ResponseTemplate::new(200).set_body_json(vec![self.0.clone(); emails.len()])
     }
 }
 
@@ -87,29 +525,61 @@ pub async fn spawn_app() -> TestApp {
         // a scan will be triggered to find an available port, and bind to it.
         c.application.port = 0;
         // use the mock server uri
-        c.email_client.base_url = email_server.uri();
+        c.email_client.transport = EmailTransportSettings::Postmark {
+            base_url: email_server.uri(),
+            auth_token: Secret::new("my-secret-token".into()),
+        };
+        // Still retry, but without slowing the test suite down.
+        c.email_client.retry.base_delay_milliseconds = 1;
+        c.email_client.retry.max_delay_milliseconds = 10;
+        c.email_client.retry.jitter_milliseconds = 0;
+        // Keep sessions in memory, `PostgresSessionStore` is exercised on its own.
+        c.application.session_store = SessionStoreKind::InMemory;
         c
     };
 
     // create and migrate db
     create_database(&config.database).await;
 
-    let app = Application::build(config.clone())
+    let email_client = config.email_client.clone().client();
+    let app = Application::build(config.clone(), email_client.clone())
         .await
         .expect("Failed to build app");
 
     let app_port = app.port();
-    let _ = tokio::spawn(app.run_until_stopped());
+    tokio::spawn(app.run_until_stopped());
+
+    let api_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
 
-    TestApp {
+    let test_app = TestApp {
         db_pool: get_connection_pool(&config.database),
         addr: format!("http://localhost:{}", app_port),
+        base_url: config.application.base_url.clone(),
         email_server,
         port: app_port,
-    }
+        email_client,
+        email_templates: config.email_templates.load().unwrap(),
+        test_user: TestUser::generate(),
+        expiry: config.expiry.clone(),
+        unsubscribe_links: config.application.unsubscribe_links(),
+        tracking_links: config.application.tracking_links(),
+        webhooks: config.application.webhooks.clone(),
+        api_client,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
 }
 
-async fn create_database<'a>(config: &'a DatabaseSettings) -> PgPool {
+async fn create_database(config: &DatabaseSettings) -> PgPool {
     // Connect to db server.
     let mut conn = PgConnection::connect_with(&config.without_db())
         .await
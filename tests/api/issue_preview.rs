@@ -0,0 +1,304 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, PostmarkBatchResponse, TestApp};
+
+// The links of `content` under `path`, pointed at the test server.
+fn links_to(app: &TestApp, content: &str, path: &str) -> Vec<reqwest::Url> {
+    linkify::LinkFinder::new()
+        .links(content)
+        .filter(|link| link.as_str().contains(path))
+        .map(|link| {
+            let mut link = reqwest::Url::parse(link.as_str()).unwrap();
+            link.set_port(Some(app.port)).unwrap();
+            link
+        })
+        .collect()
+}
+
+// Following the links of a preview or test copy records nothing and unsubscribes nobody.
+async fn assert_preview_links_are_inert(app: &TestApp, html: &str) {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let click = &links_to(app, html, "/t/c/")[0];
+    let response = client.get(click.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/article"
+    );
+    let pixel = &links_to(app, html, "/t/o/")[0];
+    let response = client.get(pixel.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let unsubscribe = &links_to(app, html, "/subscriptions/unsubscribe")[0];
+    let response = client.get(unsubscribe.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let response = client
+        .post(unsubscribe.clone())
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let events = sqlx::query!("SELECT kind FROM newsletter_tracking_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(events.is_empty());
+    let membership = sqlx::query!("SELECT status FROM list_memberships")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(membership.status, "pending_confirmation");
+}
+
+async fn create_subscriber(app: &TestApp) {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+// Returns the id of the new draft.
+async fn create_draft(app: &TestApp) -> String {
+    let summary: serde_json::Value = app
+        .post_newsletter_draft(serde_json::json!({
+            "title": "March news",
+            "content": {
+                "text": "Hi {{ name }}, read https://example.com/article",
+                "html": r#"<p>Hi {{ name }}, read <a href="https://example.com/article">this</a></p>"#,
+            }
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    summary["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_preview_an_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+
+    // Act
+    let response = app
+        .get_issue_preview(&issue_id, "ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_preview_is_the_issue_as_the_subscriber_gets_it_with_preview_links() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app
+        .get_issue_preview(&issue_id, "ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("<p>Hi le guin, read"));
+    // Tracked links, pixel and unsubscribe link are all there, as preview links.
+    assert!(!html.contains(r#"href="https://example.com/article""#));
+    assert_eq!(links_to(&app, &html, "/t/c/preview.").len(), 1);
+    assert_eq!(links_to(&app, &html, "/t/o/preview.").len(), 1);
+    assert_eq!(
+        links_to(&app, &html, "/subscriptions/unsubscribe?token=preview.").len(),
+        1
+    );
+    assert_preview_links_are_inert(&app, &html).await;
+}
+
+#[tokio::test]
+async fn previewing_as_an_unknown_subscriber_is_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    app.login_test_user().await;
+
+    // Act
+    let response = app.get_issue_preview(&issue_id, "nobody@example.com").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn test_copies_are_sent_to_each_seed_address_only() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.login_test_user().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_issue_test_send(
+            &issue_id,
+            serde_json::json!({
+                "addresses": ["editor@example.com", "proofreader@example.com"],
+                "subscriber": "ursula_le_guin@gmail.com",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let outcomes: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        outcomes,
+        serde_json::json!([
+            {"address": "editor@example.com", "sent": true},
+            {"address": "proofreader@example.com", "sent": true},
+        ])
+    );
+    let bodies = app.issue_emails().await;
+    let recipients: Vec<_> = bodies.iter().map(|b| b["To"].as_str().unwrap()).collect();
+    assert_eq!(
+        recipients,
+        ["editor@example.com", "proofreader@example.com"]
+    );
+    for body in &bodies {
+        assert_eq!(body["Tag"], "newsletter-test");
+        let html = body["HtmlBody"].as_str().unwrap();
+        assert!(html.contains("<p>Hi le guin, read"));
+        // The links are those the subscriber gets, as preview links.
+        assert_eq!(links_to(&app, html, "/t/c/preview.").len(), 1);
+        assert_eq!(links_to(&app, html, "/t/o/preview.").len(), 1);
+        let text = body["TextBody"].as_str().unwrap();
+        let unsubscribe = links_to(&app, text, "/subscriptions/unsubscribe?token=preview.");
+        assert_eq!(unsubscribe.len(), 1);
+        assert_eq!(body["Headers"][0]["Name"], "List-Unsubscribe");
+        assert!(body["Headers"][0]["Value"]
+            .as_str()
+            .unwrap()
+            .contains("/subscriptions/unsubscribe?token=preview."));
+        // Seed addresses must not be able to unsubscribe the subscriber.
+        assert_preview_links_are_inert(&app, html).await;
+    }
+    // Nothing is queued for the subscribers.
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_test_copy_that_fails_does_not_keep_the_others_from_being_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.login_test_user().await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &wiremock::Request| {
+            let emails: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = emails
+                .iter()
+                .map(|email| match email["To"].as_str().unwrap() {
+                    "inactive@example.com" => serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to recipient(s) that have been marked as inactive."
+                    }),
+                    _ => serde_json::json!({"ErrorCode": 0, "Message": "OK"}),
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_issue_test_send(
+            &issue_id,
+            serde_json::json!({
+                "addresses": ["inactive@example.com", "proofreader@example.com"],
+                "subscriber": "ursula_le_guin@gmail.com",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 502);
+    let outcomes: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(outcomes[0]["address"], "inactive@example.com");
+    assert_eq!(outcomes[0]["sent"], false);
+    assert!(outcomes[0]["error"].as_str().unwrap().contains("inactive"));
+    assert_eq!(
+        outcomes[1],
+        serde_json::json!({"address": "proofreader@example.com", "sent": true})
+    );
+}
+
+#[tokio::test]
+async fn test_copies_require_valid_seed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+    app.login_test_user().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = vec![
+        (serde_json::json!([]), "no address"),
+        (
+            serde_json::json!(["editor@example.com", "not-an-email"]),
+            "an invalid address",
+        ),
+    ];
+
+    for (addresses, description) in test_cases {
+        // Act
+        let response = app
+            .post_issue_test_send(
+                &issue_id,
+                serde_json::json!({
+                    "addresses": addresses,
+                    "subscriber": "ursula_le_guin@gmail.com",
+                }),
+            )
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, PostmarkBatchResponse, TestApp};
+
+const SUBSCRIBER: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn create_list(app: &TestApp, slug: &str) {
+    let response = app
+        .post_lists(serde_json::json!({ "slug": slug, "name": "Weekly digest" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+// Subscribe through the public API and return the confirmation link that was emailed.
+async fn subscribe(app: &TestApp, body: String) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Subscribe")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+async fn confirm(app: &TestApp, link: reqwest::Url) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Welcome")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.confirm_subscription(link)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+// Publish an issue to `lists` and return how many emails went out.
+async fn deliver_issue(app: &TestApp, lists: serde_json::Value) -> usize {
+    let n_received = app.issue_emails().await.len();
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .named("Deliver issue")
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "lists": lists,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    app.issue_emails().await.len() - n_received
+}
+
+#[tokio::test]
+async fn list_slugs_are_unique_and_well_formed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+
+    // Act
+    let taken = app
+        .post_lists(serde_json::json!({ "slug": "weekly", "name": "Another one" }))
+        .await;
+    let malformed = app
+        .post_lists(serde_json::json!({ "slug": "Weekly digest", "name": "Weekly" }))
+        .await;
+
+    // Assert
+    assert_eq!(taken.status().as_u16(), 409);
+    assert_eq!(malformed.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!("{}&list=weekly", SUBSCRIBER))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscriptions_are_confirmed_list_by_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    confirm(&app, subscribe(&app, SUBSCRIBER.into()).await).await;
+
+    // Act - Part 1 - Subscribe to another list with the same address
+    let weekly_link = subscribe(&app, format!("{}&list=weekly", SUBSCRIBER)).await;
+
+    // Assert - Part 1 - Still confirmed on the default list only
+    assert_eq!(deliver_issue(&app, serde_json::json!(["weekly"])).await, 0);
+    assert_eq!(
+        deliver_issue(&app, serde_json::json!(["newsletter"])).await,
+        1
+    );
+
+    // Act - Part 2 - Confirm the other list
+    confirm(&app, weekly_link).await;
+
+    // Assert - Part 2
+    assert_eq!(deliver_issue(&app, serde_json::json!(["weekly"])).await, 1);
+}
+
+#[tokio::test]
+async fn subscribers_on_several_lists_of_an_issue_get_it_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    confirm(&app, subscribe(&app, SUBSCRIBER.into()).await).await;
+    confirm(
+        &app,
+        subscribe(&app, format!("{}&list=weekly", SUBSCRIBER)).await,
+    )
+    .await;
+
+    // Act
+    let n_delivered = deliver_issue(&app, serde_json::json!(["newsletter", "weekly"])).await;
+
+    // Assert
+    assert_eq!(n_delivered, 1);
+}
+
+async fn memberships(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!(
+        r#"
+        SELECT l.slug, m.status
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        ORDER BY l.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|m| (m.slug, m.status))
+    .collect()
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn unsubscribing_only_leaves_the_list_the_email_was_sent_for() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    confirm(&app, subscribe(&app, SUBSCRIBER.into()).await).await;
+    confirm(
+        &app,
+        subscribe(&app, format!("{}&list=weekly", SUBSCRIBER)).await,
+    )
+    .await;
+    // A confirmation and a welcome email for each list, in turn.
+    let emails = app.email_server.received_requests().await.unwrap();
+    let (newsletter_email, weekly_email) = (&emails[1], &emails[3]);
+
+    // Act - Part 1 - Leave the weekly list
+    reqwest::get(app.get_unsubscribe_links(weekly_email).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert - Part 1
+    assert_eq!(
+        memberships(&app).await,
+        [
+            ("newsletter".to_owned(), "confirmed".to_owned()),
+            ("weekly".to_owned(), "unsubscribed".to_owned())
+        ]
+    );
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    assert_eq!(deliver_issue(&app, serde_json::json!(["weekly"])).await, 0);
+    assert_eq!(
+        deliver_issue(&app, serde_json::json!(["newsletter", "weekly"])).await,
+        1
+    );
+
+    // Act - Part 2 - Leave the last list
+    reqwest::get(app.get_unsubscribe_links(newsletter_email).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert - Part 2 - Nothing left, the address is unsubscribed
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+    assert_eq!(
+        deliver_issue(&app, serde_json::json!(["newsletter", "weekly"])).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn a_hard_bounce_leaves_every_list() {
+    // Arrange
+    let app = spawn_app().await;
+    create_list(&app, "weekly").await;
+    confirm(&app, subscribe(&app, SUBSCRIBER.into()).await).await;
+    confirm(
+        &app,
+        subscribe(&app, format!("{}&list=weekly", SUBSCRIBER)).await,
+    )
+    .await;
+
+    // Act
+    app.post_email_webhook(serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": "HardBounce",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Email": "ursula_le_guin@gmail.com",
+        "BouncedAt": "2024-07-30T16:33:54Z",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    assert_eq!(
+        memberships(&app).await,
+        [
+            ("newsletter".to_owned(), "bounced".to_owned()),
+            ("weekly".to_owned(), "bounced".to_owned())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn issues_cannot_be_sent_to_unknown_lists() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "lists": ["weekly"],
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
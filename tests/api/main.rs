@@ -1,4 +1,18 @@
+mod admin_dashboard;
+mod archive;
+mod change_password;
+mod cleanup_worker;
 mod health_check;
 mod helpers;
+mod issue_preview;
+mod lists;
+mod login;
+mod newsletter_issues;
+mod newsletters;
+mod segments;
+mod session_store;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
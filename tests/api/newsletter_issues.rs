@@ -0,0 +1,357 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, PostmarkBatchResponse, TestApp};
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create confirmed subscriber")
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.confirm_subscription(app.get_confirmation_links(&email_request).html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+fn issue_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+// Returns the id of the new draft.
+async fn create_draft(app: &TestApp, title: &str) -> String {
+    let response = app.post_newsletter_draft(issue_body(title)).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    summary["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn issue_status(app: &TestApp, issue_id: &str) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        uuid::Uuid::parse_str(issue_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletter_draft(issue_body("March news")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["status"], "draft");
+    assert!(summary["slug"].as_str().unwrap().starts_with("march-news-"));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "March news").await;
+
+    // Act
+    let response = app
+        .put_newsletter_draft(&issue_id, issue_body("April news"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert!(summary["slug"].as_str().unwrap().starts_with("april-news-"));
+    let saved = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.title, "April news");
+}
+
+#[tokio::test]
+async fn issues_that_are_not_drafts_cannot_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "March news").await;
+    app.post_newsletter_action(&issue_id, "send", serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app
+        .put_newsletter_draft(&issue_id, issue_body("April news"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_their_time_has_come() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let past = create_draft(&app, "Past").await;
+    let future = create_draft(&app, "Future").await;
+    for (issue_id, scheduled_for) in [
+        (&past, "2024-01-01T09:00:00Z"),
+        (&future, "2999-01-01T09:00:00+02:00"),
+    ] {
+        let response = app
+            .post_newsletter_action(
+                issue_id,
+                "schedule",
+                serde_json::json!({ "scheduled_for": scheduled_for }),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let n_published = app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_published, 1);
+    assert_eq!(issue_status(&app, &past).await, "sent");
+    assert_eq!(issue_status(&app, &future).await, "scheduled");
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_that_cannot_be_sent_does_not_block_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let broken = create_draft(&app, "Broken").await;
+    let fine = create_draft(&app, "Fine").await;
+    for (issue_id, scheduled_for) in [
+        (&broken, "2024-01-01T09:00:00Z"),
+        (&fine, "2024-01-01T10:00:00Z"),
+    ] {
+        app.post_newsletter_action(
+            issue_id,
+            "schedule",
+            serde_json::json!({ "scheduled_for": scheduled_for }),
+        )
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+    // A segment the API would have refused, e.g. stored by an older version.
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET segment = '{"unknown": 1}' WHERE newsletter_issue_id = $1"#,
+        uuid::Uuid::parse_str(&broken).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let n_published = app.publish_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_published, 1);
+    assert_eq!(issue_status(&app, &broken).await, "draft");
+    assert_eq!(issue_status(&app, &fine).await, "sent");
+    // It is out of the way for good, the next runs do not pick it again.
+    assert_eq!(app.publish_due_issues().await, 0);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "March news").await;
+    app.post_newsletter_action(
+        &issue_id,
+        "schedule",
+        serde_json::json!({ "scheduled_for": "2024-01-01T09:00:00Z" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter_action(&issue_id, "cancel", serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(app.publish_due_issues().await, 0);
+    app.dispatch_all_pending_emails().await;
+    let response = app
+        .post_newsletter_action(&issue_id, "send", serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn unscheduled_issues_are_drafts_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "March news").await;
+    app.post_newsletter_action(
+        &issue_id,
+        "schedule",
+        serde_json::json!({ "scheduled_for": "2999-01-01T09:00:00Z" }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_newsletter_action(&issue_id, "unschedule", serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["status"], "draft");
+    assert!(summary["scheduled_for"].is_null());
+    let response = app
+        .put_newsletter_draft(&issue_id, issue_body("April news"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn sent_issues_are_sent_once_every_delivery_is_done() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app, "March news").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletter_action(&issue_id, "send", serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(issue_status(&app, &issue_id).await, "sending");
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, &issue_id).await, "sent");
+    let response = app
+        .post_newsletter_action(&issue_id, "send", serde_json::json!({}))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn scheduling_requires_a_valid_date() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "March news").await;
+
+    // Act
+    let response = app
+        .post_newsletter_action(
+            &issue_id,
+            "schedule",
+            serde_json::json!({ "scheduled_for": "next tuesday" }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(issue_status(&app, &issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn unknown_issues_are_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletter_action(
+            &uuid::Uuid::new_v4().to_string(),
+            "send",
+            serde_json::json!({}),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn managing_issues_requires_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters/drafts", &app.addr))
+        .json(&issue_body("March news"))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,620 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, EmailLinks, PostmarkBatchResponse, TestApp, TestUser};
+
+// Use the public API of the application under test to create an unconfirmed subscriber.
+async fn create_unconfirmed_subscriber(app: &TestApp) -> EmailLinks {
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    // `mount_as_scoped` returns a guard, the mock is only active while the guard is alive.
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Welcome confirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.confirm_subscription(confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        // We assert that no request is fired at Postmark
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_in_the_locale_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET locale = 'fr'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let body = app.issue_emails().await.pop().unwrap();
+    assert_eq!(body["Subject"], "Newsletter title");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Vous recevez cet email car vous êtes inscrit à notre newsletter."));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_with_an_invalid_stored_email_are_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // Bypass the API validation to simulate a row stored under older validation rules.
+    sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'not-an-email', 'legacy', now(), 'confirmed')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn subscribers_whose_address_is_inactive_are_marked_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::rejected(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to recipient(s) that have been marked as inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "bounced");
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(0));
+}
+
+#[tokio::test]
+async fn deliveries_failing_for_a_transient_reason_are_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let queued = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"postponed!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.postponed);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn concurrent_workers_deliver_each_issue_exactly_once() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 0..10 {
+        let subscriber_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'reader', now(), 'confirmed')",
+            subscriber_id,
+            format!("reader-{}@example.com", i)
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO list_memberships (list_id, subscriber_id, status)
+            SELECT list_id, $1, 'confirmed' FROM lists WHERE is_default",
+            subscriber_id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    tokio::join!(
+        app.dispatch_all_pending_emails(),
+        app.dispatch_all_pending_emails(),
+        app.dispatch_all_pending_emails(),
+    );
+
+    // Assert
+    let remaining = sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, Some(0));
+    // Every subscriber got exactly one email.
+    let emails = app.issue_emails().await;
+    let recipients: HashSet<_> = emails.iter().map(|email| email["To"].as_str()).collect();
+    assert_eq!(emails.len(), 10);
+    assert_eq!(recipients.len(), 10);
+}
+
+#[tokio::test]
+async fn one_request_to_the_email_provider_delivers_an_issue_to_several_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 0..3 {
+        let subscriber_id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, 'reader', now(), 'confirmed')",
+            subscriber_id,
+            format!("reader-{}@example.com", i)
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "INSERT INTO list_memberships (list_id, subscriber_id, status)
+            SELECT list_id, $1, 'confirmed' FROM lists WHERE is_default",
+            subscriber_id
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let mut recipients: Vec<_> = app
+        .issue_emails()
+        .await
+        .into_iter()
+        .map(|email| email["To"].as_str().unwrap().to_owned())
+        .collect();
+    recipients.sort();
+    assert_eq!(
+        recipients,
+        [
+            "reader-0@example.com",
+            "reader-1@example.com",
+            "reader-2@example.com"
+        ]
+    );
+    // Mock verifies on Drop that a single batch request was sent
+}
+
+#[tokio::test]
+async fn logged_in_users_can_publish_without_basic_auth() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.login_test_user().await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.addr))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.addr))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    // Random credentials
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.addr))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    // Random password
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.addr))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        // Act
+        let response = app.post_newsletters(invalid_body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish the newsletter
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // Act - Part 2 - Publish it again with the same key
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn an_idempotency_key_cannot_be_reused_for_another_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // Act
+    let mut other_issue = newsletter_request_body();
+    other_issue["title"] = "Another newsletter title".into();
+    let response = app
+        .post_newsletters_with_idempotency_key(other_issue, &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 422);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that only the first issue was sent
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_submissions_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Submit the same request twice at the same time
+    let (response1, response2) = tokio::join!(
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key),
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key),
+    );
+
+    // Assert
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_scoped_per_user() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let idempotency_key = Uuid::new_v4().to_string();
+    let other_user = TestUser::generate();
+    other_user.store(&app.db_pool).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.addr))
+        .basic_auth(&other_user.username, Some(&other_user.password))
+        .header("Idempotency-Key", &idempotency_key)
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 202);
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    // Each user's issue went out.
+    assert_eq!(app.issue_emails().await.len(), 2);
+}
+
+#[tokio::test]
+async fn an_invalid_idempotency_key_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_newsletters_with_idempotency_key(newsletter_request_body(), &"a".repeat(100))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
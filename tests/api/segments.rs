@@ -0,0 +1,314 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, PostmarkBatchResponse, TestApp};
+
+// Subscribe and confirm through the public API, returns the id of the subscriber.
+async fn create_confirmed_subscriber(app: &TestApp, name: &str, locale: &str) -> Uuid {
+    let email = format!("{}@example.com", name);
+    let body = format!("name={}&email={}&locale={}", name, email, locale);
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create confirmed subscriber")
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.confirm_subscription(app.get_confirmation_links(&email_request).html)
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn tag(app: &TestApp, subscriber_id: Uuid, tags: &[&str]) {
+    app.post_subscriber_tags(
+        &subscriber_id.to_string(),
+        serde_json::json!({ "tags": tags }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+async fn count_matching(app: &TestApp, segment: serde_json::Value) -> i64 {
+    let response = app
+        .post_segment_preview(serde_json::json!({ "segment": segment }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let preview: serde_json::Value = response.json().await.unwrap();
+    preview["matching_subscribers"].as_i64().unwrap()
+}
+
+// Publish an issue to `segment` and return the addresses it went to, sorted.
+async fn deliver_issue(app: &TestApp, segment: serde_json::Value) -> Vec<String> {
+    let n_received = app.issue_emails().await.len();
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .named("Deliver issue")
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "segment": segment,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let mut recipients: Vec<String> = app.issue_emails().await[n_received..]
+        .iter()
+        .map(|email| email["To"].as_str().unwrap().to_owned())
+        .collect();
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn subscribers_can_be_tagged_and_untagged() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = create_confirmed_subscriber(&app, "ursula", "en").await;
+    let subscriber_id = subscriber_id.to_string();
+
+    // Act - Part 1 - Tag
+    let response = app
+        .post_subscriber_tags(
+            &subscriber_id,
+            serde_json::json!({ "tags": ["Beta", "early-adopter", "beta"] }),
+        )
+        .await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["tags"], serde_json::json!(["beta", "early-adopter"]));
+
+    // Act - Part 2 - Untag
+    let response = app.delete_subscriber_tag(&subscriber_id, "beta").await;
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["tags"], serde_json::json!(["early-adopter"]));
+}
+
+#[tokio::test]
+async fn tagging_rejects_unknown_subscribers_and_malformed_tags() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = create_confirmed_subscriber(&app, "ursula", "en").await;
+
+    // Act
+    let unknown = app
+        .post_subscriber_tags(
+            &Uuid::new_v4().to_string(),
+            serde_json::json!({ "tags": ["beta"] }),
+        )
+        .await;
+    let malformed = app
+        .post_subscriber_tags(
+            &subscriber_id.to_string(),
+            serde_json::json!({ "tags": ["beta tester"] }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(unknown.status().as_u16(), 404);
+    assert_eq!(malformed.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_preview_counts_the_subscribers_matching_a_segment() {
+    // Arrange
+    let app = spawn_app().await;
+    let ursula = create_confirmed_subscriber(&app, "ursula", "en").await;
+    let marguerite = create_confirmed_subscriber(&app, "marguerite", "fr-CA").await;
+    create_confirmed_subscriber(&app, "albert", "fr").await;
+    tag(&app, ursula, &["beta"]).await;
+    tag(&app, marguerite, &["beta"]).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2020-01-01T00:00:00Z' WHERE id = $1",
+        ursula
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act & Assert
+    assert_eq!(
+        count_matching(&app, serde_json::json!({ "tag": "beta" })).await,
+        2
+    );
+    assert_eq!(
+        count_matching(&app, serde_json::json!({ "locale": "fr" })).await,
+        2
+    );
+    assert_eq!(
+        count_matching(
+            &app,
+            serde_json::json!({ "all": [{ "tag": "beta" }, { "locale": "fr" }] })
+        )
+        .await,
+        1
+    );
+    assert_eq!(
+        count_matching(&app, serde_json::json!({ "not": { "tag": "beta" } })).await,
+        1
+    );
+    assert_eq!(
+        count_matching(
+            &app,
+            serde_json::json!({ "subscribed_after": "2024-01-01T00:00:00Z" })
+        )
+        .await,
+        2
+    );
+}
+
+#[tokio::test]
+async fn malformed_segments_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let segment = serde_json::json!({ "tag": "beta", "locale": "fr" });
+
+    // Act
+    let preview = app
+        .post_segment_preview(serde_json::json!({ "segment": segment }))
+        .await;
+    let issue = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "segment": segment,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(preview.status().as_u16(), 400);
+    assert_eq!(issue.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_only_reach_its_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let ursula = create_confirmed_subscriber(&app, "ursula", "en").await;
+    create_confirmed_subscriber(&app, "albert", "fr").await;
+    tag(&app, ursula, &["beta"]).await;
+
+    // Act
+    let recipients = deliver_issue(&app, serde_json::json!({ "tag": "beta" })).await;
+
+    // Assert
+    assert_eq!(recipients, vec!["ursula@example.com"]);
+}
+
+#[tokio::test]
+async fn drafts_keep_their_segment_until_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app, "ursula", "en").await;
+    create_confirmed_subscriber(&app, "albert", "fr").await;
+    let response = app
+        .post_newsletter_draft(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "segment": { "locale": "FR" },
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let draft: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(draft["segment"], serde_json::json!({ "locale": "fr" }));
+    let issue_id = draft["newsletter_issue_id"].as_str().unwrap();
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletter_action(issue_id, "send", serde_json::json!({}))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let emails = app.issue_emails().await;
+    assert_eq!(emails.len(), 1);
+    assert_eq!(emails[0]["To"], "albert@example.com");
+}
+
+#[tokio::test]
+async fn the_last_issue_can_be_followed_up_with_those_who_opened_it() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app, "ursula", "en").await;
+    create_confirmed_subscriber(&app, "albert", "fr").await;
+    let recipients = deliver_issue(&app, serde_json::json!({ "not": { "tag": "nobody" } })).await;
+    assert_eq!(recipients.len(), 2);
+    // Ursula opens the last issue.
+    let html = app
+        .issue_emails()
+        .await
+        .into_iter()
+        .rev()
+        .find(|email| email["To"] == "ursula@example.com")
+        .unwrap()["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let pixel = linkify::LinkFinder::new()
+        .links(&html)
+        .find(|link| link.as_str().contains("/t/o/"))
+        .unwrap();
+    let mut pixel = reqwest::Url::parse(pixel.as_str()).unwrap();
+    pixel.set_port(Some(app.port)).unwrap();
+    reqwest::get(pixel)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let opened = deliver_issue(&app, serde_json::json!({ "opened_last_issue": true })).await;
+
+    // Assert
+    assert_eq!(opened, vec!["ursula@example.com"]);
+    // The issue just sent is the last one now, nobody opened it yet.
+    assert_eq!(
+        count_matching(&app, serde_json::json!({ "opened_last_issue": false })).await,
+        2
+    );
+}
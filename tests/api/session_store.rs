@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use actix_session::storage::SessionStore;
+use actix_web::cookie::time::Duration;
+use claims::{assert_none, assert_ok, assert_some_eq};
+use zero2prod::session_store::PostgresSessionStore;
+
+use crate::helpers::spawn_app;
+
+fn state(user_id: &str) -> HashMap<String, String> {
+    HashMap::from([("user_id".to_string(), format!("\"{}\"", user_id))])
+}
+
+#[tokio::test]
+async fn postgres_store_round_trips_session_state() {
+    // Arrange
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+
+    // Act
+    let key = store.save(state("a"), &Duration::hours(1)).await.unwrap();
+
+    // Assert
+    assert_some_eq!(store.load(&key).await.unwrap(), state("a"));
+}
+
+#[tokio::test]
+async fn postgres_store_updates_existing_sessions_in_place() {
+    // Arrange
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+    let key = store.save(state("a"), &Duration::hours(1)).await.unwrap();
+    let original_key = key.as_ref().to_owned();
+
+    // Act
+    let key = store
+        .update(key, state("b"), &Duration::hours(1))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(key.as_ref(), original_key);
+    assert_some_eq!(store.load(&key).await.unwrap(), state("b"));
+}
+
+#[tokio::test]
+async fn postgres_store_does_not_load_expired_or_deleted_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    let store = PostgresSessionStore::new(app.db_pool.clone());
+    let expired = store.save(state("a"), &Duration::ZERO).await.unwrap();
+    let deleted = store.save(state("b"), &Duration::hours(1)).await.unwrap();
+
+    // Act
+    assert_ok!(store.delete(&deleted).await);
+
+    // Assert
+    assert_none!(store.load(&expired).await.unwrap());
+    assert_none!(store.load(&deleted).await.unwrap());
+}
@@ -1,12 +1,12 @@
 use wiremock::{
-    matchers::{method, path},
+    matchers::{body_partial_json, method, path},
     Mock, ResponseTemplate,
 };
 
 use crate::helpers::spawn_app;
 
 #[tokio::test]
-async fn subscribe_returns_a_200_for_valid_form_data() -> () {
+async fn subscribe_returns_a_200_for_valid_form_data() {
     // Arrange
     let app = spawn_app().await;
     let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
@@ -26,7 +26,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() -> () {
 }
 
 #[tokio::test]
-async fn subscribe_persists_a_new_subscriber() -> () {
+async fn subscribe_persists_a_new_subscriber() {
     // Arrange
     let app = spawn_app().await;
     let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
@@ -53,7 +53,7 @@ async fn subscribe_persists_a_new_subscriber() -> () {
 }
 
 #[tokio::test]
-async fn subscribe_returns_a_400_when_data_is_missing() -> () {
+async fn subscribe_returns_a_400_when_data_is_missing() {
     // Arrange
     let app = spawn_app().await;
     let test_cases = vec![
@@ -76,13 +76,17 @@ async fn subscribe_returns_a_400_when_data_is_missing() -> () {
 }
 
 #[tokio::test]
-async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() -> () {
+async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     // Arrange
     let app = spawn_app().await;
     let test_cases = vec![
         ("name=&email=le%20guin%40gmail.com", "empty name"),
         ("name=carlos%20cruz&email=", "empty email"),
         ("name=Carlos&email=not-an-email", "invalid email"),
+        (
+            "name=Carlos&email=carlos.cruz%40gmail.com&locale=english",
+            "invalid locale",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -118,6 +122,29 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     // Mock assert on drop
 }
 
+#[tokio::test]
+async fn subscribe_tags_the_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({"Tag": "confirmation"}),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    // Mock assert on drop
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_with_a_link() {
     // Arrange
@@ -135,7 +162,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     // Assert
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_link = app.get_confirmation_links(&email_request);
+    let confirmation_link = app.get_confirmation_links(email_request);
     assert_eq!(confirmation_link.html, confirmation_link.plain_text);
 }
 
@@ -156,3 +183,488 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response1 = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+    let response2 = app
+        .post_subscriptions_with_idempotency_key(body.into(), &idempotency_key)
+        .await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(1));
+    // Mock asserts on drop that a single confirmation email went out
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_subscription_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let idempotency_key = uuid::Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Two visitors happen to pick the same key
+    let response1 = app
+        .post_subscriptions_with_idempotency_key(
+            "name=carlos%20jose&email=carlos.cruz%40gmail.com".into(),
+            &idempotency_key,
+        )
+        .await;
+    let response2 = app
+        .post_subscriptions_with_idempotency_key(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+            &idempotency_key,
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 422);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "carlos.cruz@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_responds_before_the_confirmation_email_is_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Without draining the queue, unlike `post_subscriptions`
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.addr))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let queued = sqlx::query!("SELECT kind FROM transactional_email_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].kind, "confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_keeps_the_subscriber_and_retries_later_if_the_confirmation_email_fails() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(1));
+    let queued = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"later!\" FROM transactional_email_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.later);
+}
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_resends_the_confirmation_email_with_a_new_token() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response1 = app.post_subscriptions(body.into()).await;
+    let response2 = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]).html;
+    let second_link = app.get_confirmation_links(&email_requests[1]).html;
+    assert_ne!(first_link, second_link);
+    // The token has been rotated, only the latest link works.
+    assert_eq!(
+        reqwest::get(first_link).await.unwrap().status().as_u16(),
+        401
+    );
+    assert_eq!(
+        reqwest::get(second_link).await.unwrap().status().as_u16(),
+        200
+    );
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_succeeds_without_changing_anything() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.post_subscriptions(body.into()).await;
+        let email_request = &app.email_server.received_requests().await.unwrap()[0];
+        app.confirm_subscription(app.get_confirmation_links(email_request).html)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=somebody%20else&email=carlos.cruz%40gmail.com".into())
+        .await;
+
+    // Assert
+    // Indistinguishable from a first time subscription.
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.text().await.unwrap(), "");
+    let saved = sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].name, "carlos jose");
+    assert_eq!(saved[0].status, "confirmed");
+    // Mock asserts on drop that no confirmation email went out
+}
+
+#[tokio::test]
+async fn an_unsubscribed_subscriber_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_unsubscribe_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_subscriptions_for_the_same_email_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (response1, response2) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into()),
+    );
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(1));
+}
+
+#[tokio::test]
+async fn subscribe_retries_the_confirmation_email_on_transient_failures() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_does_not_retry_the_confirmation_email_if_it_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM transactional_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(0));
+}
+
+#[tokio::test]
+async fn subscribers_the_email_provider_cannot_deliver_to_are_marked_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to recipient(s) that have been marked as inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert - Same response as for any other address, we don't reveal anything about it
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "bounced");
+}
+
+#[tokio::test]
+async fn subscribe_sends_the_confirmation_email_in_the_requested_locale() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com&locale=fr";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Confirmez votre inscription");
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.locale, "fr");
+    // The confirmation link is still there.
+    app.get_confirmation_links(email_request);
+}
+
+#[tokio::test]
+async fn subscribe_falls_back_to_the_accept_language_header_for_the_locale() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions_with_accept_language(body.into(), "es-MX,es;q=0.9,en;q=0.8")
+        .await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    // There is no catalog for `es-MX`, the Spanish one is used.
+    assert_eq!(body["Subject"], "Confirma tu suscripción");
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.locale, "es-MX");
+}
+
+#[tokio::test]
+async fn subscribe_defaults_to_english_without_a_usable_locale() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com&locale=";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions_with_accept_language(body.into(), "*")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Confirm your subscription");
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.locale, "en");
+}
+
+#[tokio::test]
+async fn subscribe_accepts_names_in_non_latin_scripts() {
+    // Arrange
+    let app = spawn_app().await;
+    // "李小龍", percent-encoded.
+    let body = "name=%E6%9D%8E%E5%B0%8F%E9%BE%8D&email=bruce.lee%40gmail.com&locale=zh-TW";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "李小龍");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["TextBody"].as_str().unwrap().contains("Hi 李小龍,"));
+}
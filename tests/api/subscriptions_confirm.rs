@@ -1,5 +1,5 @@
 use wiremock::{
-    matchers::{method, path},
+    matchers::{body_partial_json, method, path},
     Mock, ResponseTemplate,
 };
 
@@ -38,7 +38,6 @@ async fn confirmations_with_unknown_token_are_unauthorized() {
         .await
         .expect("Failed to execute request to `/subscriptions/confirm`");
 
-    println!("");
     // Assert
     assert_eq!(response.status().as_u16(), 401)
 }
@@ -59,7 +58,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     app.post_subscriptions(body.to_owned()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     let response = reqwest::get(confirmation_links.html)
         .await
@@ -87,7 +86,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     app.post_subscriptions(body.to_owned()).await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Click the link
     reqwest::get(confirmation_links.html)
@@ -106,3 +105,138 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.email, "carlos.cruz@gmail.com");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirmations_with_an_expired_token_are_rejected_with_a_410() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.to_owned()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    // Age the token past its TTL, without giving the cleanup task a chance to delete it.
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - make_interval(secs => $1)",
+        (app.expiry.subscription_token_ttl_seconds + 60) as f64
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html)
+        .await
+        .expect("Failed to execute request to `/subscriptions/confirm`");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to read the subscriptions table");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn an_unsubscribed_subscriber_cannot_be_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.to_owned()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    // Leave before confirming, from the link in the confirmation email itself.
+    reqwest::get(app.get_unsubscribe_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .expect("Failed to execute request to `/subscriptions/confirm`");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to read the subscriptions table");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn the_database_rejects_unknown_statuses() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let result = sqlx::query!(
+        "INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'carlos.cruz@gmail.com', 'carlos', now(), 'active')",
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await;
+
+    // Assert
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn confirming_a_subscription_sends_a_welcome_email_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com&locale=fr";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({"Tag": "confirmation"}),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_partial_json(serde_json::json!({"Tag": "welcome"})))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.to_owned()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+
+    // Act - Clicking twice
+    for _ in 0..2 {
+        app.confirm_subscription(confirmation_link.clone())
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let welcome_request = &app.email_server.received_requests().await.unwrap()[1];
+    let welcome: serde_json::Value = serde_json::from_slice(&welcome_request.body).unwrap();
+    assert_eq!(welcome["To"], "carlos.cruz@gmail.com");
+    assert!(welcome["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Bonjour carlos jose"));
+    // Panics unless there is exactly one unsubscribe link.
+    app.get_unsubscribe_links(welcome_request);
+    // Mocks verify on Drop that a single welcome email went out
+}
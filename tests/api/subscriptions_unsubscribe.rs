@@ -0,0 +1,376 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{body_partial_json, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, PostmarkBatchResponse, TestApp};
+
+// Subscribe and confirm through the public API, returns the confirmation email.
+async fn create_confirmed_subscriber(app: &TestApp) -> wiremock::Request {
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create confirmed subscriber")
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.confirm_subscription(app.get_confirmation_links(&email_request).html)
+        .await
+        .error_for_status()
+        .unwrap();
+    email_request
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to read the subscriptions table")
+        .status
+}
+
+async fn default_list_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT list_id FROM lists WHERE is_default")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to read the lists table")
+        .list_id
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_in_the_body_unsubscribes_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    let unsubscribe_links = app.get_unsubscribe_links(&email_request);
+    assert_eq!(unsubscribe_links.html, unsubscribe_links.plain_text);
+
+    // Act
+    let response = reqwest::get(unsubscribe_links.html)
+        .await
+        .expect("Failed to execute request to `/subscriptions/unsubscribe`");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You have been unsubscribed"));
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn the_list_unsubscribe_headers_support_one_click_unsubscription() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|h| h["Name"] == name)
+            .unwrap_or_else(|| panic!("Missing {} header", name))["Value"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    };
+    assert_eq!(
+        header("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+    let list_unsubscribe = header("List-Unsubscribe");
+    let mut one_click_url = reqwest::Url::parse(
+        list_unsubscribe
+            .strip_prefix('<')
+            .and_then(|s| s.strip_suffix('>'))
+            .unwrap(),
+    )
+    .unwrap();
+    one_click_url.set_port(Some(app.port)).unwrap();
+
+    // Act - What a mail client does, as per RFC 8058
+    let response = reqwest::Client::new()
+        .post(one_click_url)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .expect("Failed to execute request to `/subscriptions/unsubscribe`");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_with_a_tampered_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    // A token issued for somebody else, with their id swapped for the subscriber's.
+    let list_id = default_list_id(&app).await;
+    let token = app.unsubscribe_links.token_for(Uuid::new_v4(), list_id);
+    let signature = token.rsplit('.').next().unwrap();
+
+    for token in [
+        format!("{}.{}.{}", subscriber_id, list_id, signature),
+        "".into(),
+    ] {
+        // Act
+        let response = reqwest::get(format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            &app.addr, token
+        ))
+        .await
+        .expect("Failed to execute request to `/subscriptions/unsubscribe`");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    reqwest::get(app.get_unsubscribe_links(&email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that no email went out
+}
+
+#[tokio::test]
+async fn unsubscribing_after_an_issue_was_published_skips_its_delivery() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Unsubscribe while the delivery task is still queued
+    reqwest::get(app.get_unsubscribe_links(&email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    app.dispatch_all_pending_emails().await;
+    let remaining = sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, Some(0));
+    // Mock verifies on Drop that no email went out
+}
+
+#[tokio::test]
+async fn newsletter_emails_carry_the_recipients_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email = app.issue_emails().await.pop().unwrap();
+    let unsubscribe_link = app.get_issue_unsubscribe_links(&email).html;
+    assert_eq!(
+        unsubscribe_link.query(),
+        Some(
+            format!(
+                "token={}",
+                app.unsubscribe_links
+                    .token_for(subscriber_id, default_list_id(&app).await)
+            )
+            .as_str()
+        )
+    );
+}
+
+#[tokio::test]
+async fn a_bounced_subscriber_cannot_unsubscribe() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'bounced'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(app.get_unsubscribe_links(&email_request).html)
+        .await
+        .expect("Failed to execute request to `/subscriptions/unsubscribe`");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_is_fine() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_links(&email_request).html;
+
+    for _ in 0..2 {
+        // Act
+        let response = reqwest::get(unsubscribe_link.clone())
+            .await
+            .expect("Failed to execute request to `/subscriptions/unsubscribe`");
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_sends_a_confirmation_email_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    let unsubscribe_link = app.get_unsubscribe_links(&email_request).html;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({"Tag": "unsubscribe-confirmation"}),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Unsubscribing twice
+    for _ in 0..2 {
+        reqwest::get(unsubscribe_link.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        app.dispatch_transactional_emails().await;
+    }
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["To"], "carlos.cruz@gmail.com");
+    assert_eq!(email["Subject"], "You have been unsubscribed");
+    // Nothing left to unsubscribe from.
+    assert!(!email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/unsubscribe"));
+    assert!(!email["Headers"].to_string().contains("List-Unsubscribe"));
+    // Mock verifies on Drop that a single confirmation went out
+}
+
+#[tokio::test]
+async fn an_undeliverable_unsubscribe_confirmation_is_not_sent_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let email_request = create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to recipient(s) that have been marked as inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    reqwest::get(app.get_unsubscribe_links(&email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_transactional_emails().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM transactional_email_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(0));
+    // An unsubscribed address cannot bounce.
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+    // Mock verifies on Drop that the email was attempted once
+}
@@ -0,0 +1,200 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::configuration::get_configuration;
+
+use crate::helpers::{spawn_app, PostmarkBatchResponse, TestApp};
+
+// Subscribe and confirm through the public API.
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create confirmed subscriber")
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.confirm_subscription(app.get_confirmation_links(&email_request).html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+// Publish an issue linking to an article and return the HTML body delivered to the subscriber.
+async fn deliver_issue(app: &TestApp, tracking: Option<bool>) -> String {
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(PostmarkBatchResponse::accepted())
+        .named("Deliver issue")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let mut body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Read it at https://example.com/article",
+            "html": r#"<p><a href="https://example.com/article?a=1&amp;b=2">Read it</a></p>"#,
+        }
+    });
+    if let Some(tracking) = tracking {
+        body["tracking"] = tracking.into();
+    }
+    app.post_newsletters(body).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email = app.issue_emails().await.pop().unwrap();
+    email["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+// The single link of `html` under `path`, pointed at the test server.
+fn tracking_link(app: &TestApp, html: &str, path: &str) -> reqwest::Url {
+    let links: Vec<_> = linkify::LinkFinder::new()
+        .links(html)
+        .filter(|link| link.as_str().contains(path))
+        .collect();
+    assert_eq!(links.len(), 1);
+    let mut link = reqwest::Url::parse(links[0].as_str()).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+struct TrackingEvent {
+    kind: String,
+    url: Option<String>,
+}
+
+async fn tracking_events(app: &TestApp) -> Vec<TrackingEvent> {
+    sqlx::query_as!(
+        TrackingEvent,
+        "SELECT kind, url FROM newsletter_tracking_events ORDER BY occurred_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+fn without_redirects() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn links_of_an_issue_redirect_to_their_target_and_record_a_click() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue(&app, None).await;
+    assert!(!html.contains("https://example.com/article"));
+    let link = tracking_link(&app, &html, "/t/c/");
+
+    // Act
+    let response = without_redirects().get(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/article?a=1&b=2"
+    );
+    let events = tracking_events(&app).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, "click");
+    assert_eq!(
+        events[0].url.as_deref(),
+        Some("https://example.com/article?a=1&b=2")
+    );
+}
+
+#[tokio::test]
+async fn the_tracking_pixel_records_an_open() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue(&app, None).await;
+    let pixel = tracking_link(&app, &html, "/t/o/");
+
+    // Act
+    let response = reqwest::get(pixel).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let events = tracking_events(&app).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, "open");
+    assert_eq!(events[0].url, None);
+}
+
+#[tokio::test]
+async fn tampered_click_links_are_not_followed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let html = deliver_issue(&app, None).await;
+    let mut link = tracking_link(&app, &html, "/t/c/");
+    let token = link
+        .path_segments()
+        .unwrap()
+        .next_back()
+        .unwrap()
+        .to_owned();
+    let mut parts: Vec<&str> = token.split('.').collect();
+    // base64url of `https://evil.example.com`
+    parts[2] = "aHR0cHM6Ly9ldmlsLmV4YW1wbGUuY29t";
+    link.set_path(&format!("/t/c/{}", parts.join(".")));
+
+    // Act
+    let response = without_redirects().get(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(response.headers().get("Location").is_none());
+    assert!(tracking_events(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn issues_can_opt_out_of_tracking() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let html = deliver_issue(&app, Some(false)).await;
+
+    // Assert
+    assert!(html.contains(r#"href="https://example.com/article?a=1&amp;b=2""#));
+    assert!(!html.contains("/t/c/"));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn tracking_can_be_turned_off_for_every_issue() {
+    // Arrange
+    let mut app = spawn_app().await;
+    let mut config = get_configuration().unwrap();
+    config.application.email_tracking = false;
+    app.tracking_links = config.application.tracking_links();
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let html = deliver_issue(&app, None).await;
+
+    // Assert
+    assert!(html.contains(r#"href="https://example.com/article?a=1&amp;b=2""#));
+    assert!(!html.contains("/t/c/"));
+    assert!(!html.contains("/t/o/"));
+}
@@ -0,0 +1,276 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+const SUBSCRIBER_EMAIL: &str = "carlos.cruz@gmail.com";
+
+// Subscribe and confirm through the public API.
+async fn create_confirmed_subscriber(app: &TestApp) {
+    let body = "name=carlos%20jose&email=carlos.cruz%40gmail.com";
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create confirmed subscriber")
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.confirm_subscription(app.get_confirmation_links(&email_request).html)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to read the subscriptions table")
+        .status
+}
+
+fn postmark_bounce(bounce_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "Type": bounce_type,
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Email": SUBSCRIBER_EMAIL,
+        "BouncedAt": "2024-07-30T16:33:54Z",
+    })
+}
+
+#[tokio::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!("{}/webhooks/email", &app.addr);
+    let client = reqwest::Client::new();
+
+    for request in [
+        client.post(&url),
+        client
+            .post(&url)
+            .basic_auth(&app.webhooks.username, Some("wrong-secret")),
+        client
+            .post(&url)
+            .basic_auth("someone-else", Some("webhook-secret")),
+        client.post(&url).header("X-Webhook-Secret", "wrong-secret"),
+    ] {
+        // Act
+        let response = request
+            .json(&postmark_bounce("HardBounce"))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            response.headers()["WWW-Authenticate"],
+            r#"Basic realm="webhooks""#
+        );
+    }
+    let n_events = sqlx::query!("SELECT count(*) AS n FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_events, Some(0));
+}
+
+#[tokio::test]
+async fn unknown_payloads_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_email_webhook(serde_json::json!({"event": "bounce"}))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_is_stored_and_marks_the_subscriber_as_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let response = app.post_email_webhook(postmark_bounce("HardBounce")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+    let event = sqlx::query!(
+        "SELECT e.provider, e.kind, e.recipient, e.provider_message_id
+        FROM email_events e JOIN subscriptions s ON s.id = e.subscriber_id"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the stored event");
+    assert_eq!(event.provider, "postmark");
+    assert_eq!(event.kind, "hard_bounce");
+    assert_eq!(event.recipient, SUBSCRIBER_EMAIL);
+    assert_eq!(
+        event.provider_message_id.as_deref(),
+        Some("883953f4-6105-42a2-a16a-77a8eac79483")
+    );
+}
+
+#[tokio::test]
+async fn a_spam_complaint_authenticated_with_the_shared_secret_marks_the_subscriber_as_complained()
+{
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // As posted by SendGrid.
+    let body = serde_json::json!([
+        {"email": SUBSCRIBER_EMAIL, "event": "spamreport", "timestamp": 1722357234},
+    ]);
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/email", &app.addr))
+        .header("X-Webhook-Secret", "webhook-secret")
+        .json(&body)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "complained");
+}
+
+#[tokio::test]
+async fn soft_bounces_and_deliveries_leave_the_subscriber_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let delivery = serde_json::json!({
+        "RecordType": "Delivery",
+        "Recipient": SUBSCRIBER_EMAIL,
+        "DeliveredAt": "2024-07-30T16:33:54Z",
+    });
+
+    // Act
+    for body in [postmark_bounce("Transient"), delivery] {
+        let response = app.post_email_webhook(body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    let kinds: Vec<_> = sqlx::query!("SELECT kind FROM email_events ORDER BY received_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.kind)
+        .collect();
+    assert_eq!(kinds, ["soft_bounce", "delivery"]);
+}
+
+#[tokio::test]
+async fn events_about_unknown_recipients_are_stored() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_email_webhook(postmark_bounce("HardBounce")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let event = sqlx::query!("SELECT recipient, subscriber_id FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.recipient, SUBSCRIBER_EMAIL);
+    assert_eq!(event.subscriber_id, None);
+}
+
+#[tokio::test]
+async fn an_event_sent_again_is_stored_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mut bounce = postmark_bounce("HardBounce");
+    bounce["ID"] = 4323372036854775807_u64.into();
+
+    // Act
+    for _ in 0..2 {
+        let response = app.post_email_webhook(bounce.clone()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let events = sqlx::query!("SELECT provider_event_id FROM email_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].provider_event_id, "4323372036854775807");
+    assert_eq!(subscriber_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn a_complaint_after_a_bounce_leaves_the_subscriber_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_email_webhook(postmark_bounce("HardBounce")).await;
+
+    // Act
+    let response = app
+        .post_email_webhook(postmark_bounce("SpamComplaint"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn bounced_subscribers_get_no_more_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_email_webhook(postmark_bounce("HardBounce")).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that no newsletter went out
+}